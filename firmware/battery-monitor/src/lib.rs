@@ -0,0 +1,9 @@
+//! Battery bookkeeping of the Makita power supply.
+//!
+//! Pure state machines fed with measurements and timestamps by the firmware
+//! tasks, so they can be tested on the host.
+#![deny(unsafe_code)]
+#![no_std]
+
+pub mod soc;
+pub mod uvlo;
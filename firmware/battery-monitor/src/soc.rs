@@ -0,0 +1,225 @@
+//! State of charge from open-circuit voltage and coulomb counting.
+//!
+//! The integral of the battery current is the charge taken out. Once the
+//! pack has rested long enough for its voltage to settle, the open-circuit
+//! voltage curve re-anchors the charge. Before the first rest the voltage
+//! at startup serves as anchor.
+
+use can_messages::BATTERY_CELLS;
+use embassy_time::{Duration, Instant};
+
+/// Open-circuit voltage of a Li-ion cell in mV and the state of charge in 0.1 % at it.
+const OCV_CURVE: [(u16, u16); 12] = [
+    (3000, 0),
+    (3450, 50),
+    (3550, 100),
+    (3620, 200),
+    (3670, 300),
+    (3710, 400),
+    (3760, 500),
+    (3820, 600),
+    (3890, 700),
+    (3970, 800),
+    (4060, 900),
+    (4180, 1000),
+];
+/// Battery current below which the pack counts as resting.
+pub const REST_CURRENT_MA: u32 = 50;
+/// Time the pack voltage needs to settle once the load is gone.
+pub const REST_TIME: Duration = Duration::from_secs(300);
+/// Weight of a new current sample in the load average for the runtime, 1/n.
+const LOAD_AVERAGE: u32 = 16;
+
+const MS_PER_HOUR: u64 = 3_600_000;
+
+/// What the estimator reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Estimate {
+    pub soc_pct10: u16,
+    pub remaining_mah: u16,
+    /// At the current load, `None` without load.
+    pub runtime_min: Option<u16>,
+}
+
+/// State of charge of a 5S pack from its open-circuit voltage in mV.
+pub fn ocv_soc_pct10(battery_mv: u16) -> u16 {
+    let cell_mv = battery_mv / BATTERY_CELLS as u16;
+    let Some(above) = OCV_CURVE.iter().position(|&(mv, _)| mv > cell_mv) else {
+        return 1000;
+    };
+    let Some(below) = above.checked_sub(1) else {
+        return 0;
+    };
+    let ((mv0, soc0), (mv1, soc1)) = (OCV_CURVE[below], OCV_CURVE[above]);
+    soc0 + ((cell_mv - mv0) as u32 * (soc1 - soc0) as u32 / (mv1 - mv0) as u32) as u16
+}
+
+/// Charge left in a pack, fed with the battery voltage and current.
+pub struct Estimator {
+    capacity_mah: u32,
+    /// Charge left in mA·ms.
+    charge: u64,
+    /// Battery current, averaged.
+    load_ma: u32,
+    resting_since: Option<Instant>,
+}
+
+impl Estimator {
+    pub fn new(battery_mv: u16, capacity_mah: u32) -> Self {
+        let mut estimator = Self { capacity_mah, charge: 0, load_ma: 0, resting_since: None };
+        estimator.anchor(battery_mv);
+        estimator
+    }
+
+    pub fn capacity_mah(&self) -> u32 {
+        self.capacity_mah
+    }
+
+    fn full(&self) -> u64 {
+        self.capacity_mah as u64 * MS_PER_HOUR
+    }
+
+    fn anchor(&mut self, battery_mv: u16) {
+        self.charge = self.full() * ocv_soc_pct10(battery_mv) as u64 / 1000;
+    }
+
+    /// Keep the state of charge, but for a pack of another capacity.
+    pub fn set_capacity(&mut self, capacity_mah: u32) {
+        self.charge = self.charge * capacity_mah as u64 / self.capacity_mah.max(1) as u64;
+        self.capacity_mah = capacity_mah;
+    }
+
+    /// Account for `current_ma` drawn over `elapsed`.
+    pub fn update(&mut self, battery_mv: u16, current_ma: u32, elapsed: Duration, now: Instant) {
+        self.charge = self.charge.saturating_sub(current_ma as u64 * elapsed.as_millis());
+        self.load_ma = (self.load_ma * (LOAD_AVERAGE - 1) + current_ma) / LOAD_AVERAGE;
+        if current_ma >= REST_CURRENT_MA {
+            self.resting_since = None;
+            return;
+        }
+        let since = *self.resting_since.get_or_insert(now);
+        if now - since >= REST_TIME {
+            self.anchor(battery_mv);
+        }
+    }
+
+    pub fn estimate(&self) -> Estimate {
+        let remaining_mah = self.charge / MS_PER_HOUR;
+        Estimate {
+            soc_pct10: (self.charge * 1000 / self.full().max(1)) as u16,
+            remaining_mah: remaining_mah.min(u16::MAX as u64) as u16,
+            runtime_min: (self.load_ma >= REST_CURRENT_MA)
+                .then(|| (remaining_mah * 60 / self.load_ma as u64).min(u16::MAX as u64 - 1) as u16),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn soc(estimator: &Estimator) -> (u16, u16) {
+        let estimate = estimator.estimate();
+        (estimate.soc_pct10, estimate.remaining_mah)
+    }
+
+    #[test]
+    fn ocv() {
+        let cases = [
+            (0, 0),
+            (14_000, 0),
+            (15_000, 0),
+            (15_005, 0),
+            (17_250, 50),
+            (17_500, 75),
+            (18_800, 500),
+            (18_950, 550),
+            (20_000, 833),
+            (20_895, 999),
+            (20_900, 1000),
+            (21_500, 1000),
+            (u16::MAX, 1000),
+        ];
+        for (battery_mv, soc_pct10) in cases {
+            assert_eq!(ocv_soc_pct10(battery_mv), soc_pct10, "{battery_mv} mV");
+        }
+    }
+
+    #[test]
+    fn ocv_is_monotonic() {
+        let socs = (14_000..22_000).step_by(5).map(ocv_soc_pct10);
+        assert!(socs.clone().zip(socs.skip(1)).all(|(a, b)| a <= b));
+    }
+
+    #[test]
+    fn coulomb_counting() {
+        let now = Instant::from_secs(10);
+        let mut estimator = Estimator::new(18_800, 5000);
+        assert_eq!(soc(&estimator), (500, 2500));
+        estimator.update(17_000, 1000, HOUR, now);
+        assert_eq!(soc(&estimator), (300, 1500));
+        estimator.update(17_000, 2000, HOUR, now);
+        assert_eq!(soc(&estimator), (0, 0), "clamped at empty");
+    }
+
+    #[test]
+    fn rest() {
+        let start = Instant::from_secs(10);
+        let mut estimator = Estimator::new(18_800, 5000);
+        estimator.update(18_000, 1000, HOUR, start);
+        assert_eq!(soc(&estimator), (300, 1500));
+
+        // The voltage says 50 %, but only after resting for long enough.
+        estimator.update(18_800, 0, Duration::from_secs(1), start);
+        estimator.update(18_800, REST_CURRENT_MA - 1, Duration::from_secs(1), start + REST_TIME / 2);
+        estimator.update(18_800, 0, Duration::from_secs(1), start + REST_TIME - Duration::from_secs(1));
+        assert_eq!(soc(&estimator).0, 299);
+        estimator.update(18_800, 0, Duration::from_secs(1), start + REST_TIME);
+        assert_eq!(soc(&estimator), (500, 2500));
+
+        // Any load starts the rest over.
+        let later = start + REST_TIME * 2;
+        estimator.update(18_000, 1000, Duration::from_secs(1), later);
+        estimator.update(18_000, 0, Duration::from_secs(1), later + REST_TIME / 2);
+        estimator.update(18_000, REST_CURRENT_MA, Duration::from_secs(1), later + REST_TIME);
+        let rested = later + REST_TIME * 2;
+        estimator.update(18_000, 0, Duration::from_secs(1), rested);
+        assert_eq!(soc(&estimator).0, 499);
+        estimator.update(18_000, 0, Duration::from_secs(1), rested + REST_TIME);
+        assert_eq!(soc(&estimator).0, 171);
+    }
+
+    #[test]
+    fn capacity() {
+        let mut estimator = Estimator::new(18_800, 5000);
+        estimator.set_capacity(3000);
+        assert_eq!(estimator.capacity_mah(), 3000);
+        assert_eq!(soc(&estimator), (500, 1500));
+        estimator.set_capacity(6000);
+        assert_eq!(soc(&estimator), (500, 3000));
+
+        let mut empty = Estimator::new(18_800, 0);
+        assert_eq!(soc(&empty), (0, 0));
+        empty.set_capacity(4000);
+        assert_eq!(soc(&empty), (0, 0));
+    }
+
+    #[test]
+    fn runtime() {
+        let now = Instant::from_secs(10);
+        let mut estimator = Estimator::new(20_900, 5000);
+        assert_eq!(estimator.estimate().runtime_min, None);
+        for _ in 0..200 {
+            estimator.update(20_000, 2500, Duration::from_ticks(0), now);
+        }
+        // The average settles just below the load.
+        assert_eq!(estimator.estimate().runtime_min, Some(120));
+        for _ in 0..200 {
+            estimator.update(20_000, 0, Duration::from_ticks(0), now);
+        }
+        assert_eq!(estimator.estimate().runtime_min, None);
+    }
+}
@@ -0,0 +1,140 @@
+//! Undervoltage lockout levels.
+//!
+//! The voltage has to stay beyond a threshold for a while to count, so load
+//! peaks do not trip it, and a warning clears only once the voltage
+//! recovered by a hysteresis.
+
+use can_messages::ProtectionLevel;
+use embassy_time::{Duration, Instant};
+
+/// Below the warning threshold for this long warns.
+pub const WARNING_DELAY: Duration = Duration::from_secs(5);
+/// Below the cutoff for this long, and warned, switches off.
+pub const CUTOFF_DELAY: Duration = Duration::from_secs(10);
+/// Recovered for this long clears the warning.
+pub const CLEAR_DELAY: Duration = Duration::from_secs(5);
+/// Above the warning threshold by this much counts as recovered. Also needed
+/// above the cutoff to stay on after an undervoltage shutdown.
+pub const HYSTERESIS_MV: u16 = 500;
+
+/// Time `condition` has held without a break.
+fn held(since: &mut Option<Instant>, condition: bool, now: Instant) -> Duration {
+    if condition {
+        now - *since.get_or_insert(now)
+    } else {
+        *since = None;
+        Duration::from_ticks(0)
+    }
+}
+
+/// Protection level from the battery voltage over time.
+pub struct Monitor {
+    level: ProtectionLevel,
+    below_warning: Option<Instant>,
+    below_cutoff: Option<Instant>,
+    recovered: Option<Instant>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self { level: ProtectionLevel::Normal, below_warning: None, below_cutoff: None, recovered: None }
+    }
+
+    pub fn level(&self) -> ProtectionLevel {
+        self.level
+    }
+
+    /// A warning threshold below the cutoff counts as the cutoff, there is always a warning first.
+    pub fn update(&mut self, battery_mv: u16, warning_mv: u16, cutoff_mv: u16, now: Instant) -> ProtectionLevel {
+        let below_warning = held(&mut self.below_warning, battery_mv < warning_mv, now);
+        let below_cutoff = held(&mut self.below_cutoff, battery_mv < cutoff_mv, now);
+        let recovered = held(&mut self.recovered, battery_mv >= warning_mv.saturating_add(HYSTERESIS_MV), now);
+        self.level = match self.level {
+            ProtectionLevel::Normal if below_warning >= WARNING_DELAY => ProtectionLevel::Warning,
+            ProtectionLevel::Warning if below_cutoff >= CUTOFF_DELAY => ProtectionLevel::Tripped,
+            ProtectionLevel::Warning if recovered >= CLEAR_DELAY => ProtectionLevel::Normal,
+            level => level,
+        };
+        self.level
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARNING_MV: u16 = 16_000;
+    const CUTOFF_MV: u16 = 15_000;
+    const STEP: Duration = Duration::from_millis(100);
+
+    /// Feed `battery_mv` from `*now` for `duration`, return the level at the end.
+    fn hold(monitor: &mut Monitor, now: &mut Instant, battery_mv: u16, duration: Duration) -> ProtectionLevel {
+        let end = *now + duration;
+        let mut level = monitor.level();
+        while *now <= end {
+            level = monitor.update(battery_mv, WARNING_MV, CUTOFF_MV, *now);
+            *now += STEP;
+        }
+        level
+    }
+
+    #[test]
+    fn warning_then_cutoff() {
+        let (mut monitor, mut now) = (Monitor::new(), Instant::from_secs(10));
+        assert_eq!(hold(&mut monitor, &mut now, 17_000, Duration::from_secs(60)), ProtectionLevel::Normal);
+        assert_eq!(hold(&mut monitor, &mut now, 15_500, WARNING_DELAY - STEP), ProtectionLevel::Normal);
+        assert_eq!(hold(&mut monitor, &mut now, 15_500, STEP), ProtectionLevel::Warning);
+        // Above the cutoff the warning goes on forever.
+        assert_eq!(hold(&mut monitor, &mut now, 15_500, Duration::from_secs(600)), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, 14_900, CUTOFF_DELAY - STEP), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, 14_900, STEP), ProtectionLevel::Tripped);
+        // Only a restart gets out of it.
+        assert_eq!(hold(&mut monitor, &mut now, 20_000, Duration::from_secs(60)), ProtectionLevel::Tripped);
+    }
+
+    #[test]
+    fn straight_to_cutoff() {
+        // Warned after 5 s, tripped 10 s after dropping below the cutoff.
+        let (mut monitor, mut now) = (Monitor::new(), Instant::from_secs(10));
+        assert_eq!(hold(&mut monitor, &mut now, 14_000, WARNING_DELAY), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, 14_000, CUTOFF_DELAY - WARNING_DELAY - STEP * 2), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, 14_000, STEP), ProtectionLevel::Tripped);
+    }
+
+    #[test]
+    fn short_dips() {
+        let (mut monitor, mut now) = (Monitor::new(), Instant::from_secs(10));
+        for _ in 0..10 {
+            assert_eq!(hold(&mut monitor, &mut now, 14_000, WARNING_DELAY - STEP * 2), ProtectionLevel::Normal);
+            assert_eq!(hold(&mut monitor, &mut now, 17_000, STEP), ProtectionLevel::Normal);
+        }
+
+        // Nor do dips below the cutoff add up while warned.
+        assert_eq!(hold(&mut monitor, &mut now, 15_500, WARNING_DELAY), ProtectionLevel::Warning);
+        for _ in 0..10 {
+            assert_eq!(hold(&mut monitor, &mut now, 14_000, CUTOFF_DELAY - STEP * 2), ProtectionLevel::Warning);
+            assert_eq!(hold(&mut monitor, &mut now, 15_500, STEP), ProtectionLevel::Warning);
+        }
+    }
+
+    #[test]
+    fn recovery() {
+        let (mut monitor, mut now) = (Monitor::new(), Instant::from_secs(10));
+        assert_eq!(hold(&mut monitor, &mut now, 15_500, WARNING_DELAY), ProtectionLevel::Warning);
+        // Back above the threshold, but not by the hysteresis.
+        let almost = WARNING_MV + HYSTERESIS_MV - 1;
+        assert_eq!(hold(&mut monitor, &mut now, almost, Duration::from_secs(600)), ProtectionLevel::Warning);
+
+        let recovered = WARNING_MV + HYSTERESIS_MV;
+        assert_eq!(hold(&mut monitor, &mut now, recovered, CLEAR_DELAY - STEP * 2), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, almost, STEP), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, recovered, CLEAR_DELAY - STEP), ProtectionLevel::Warning);
+        assert_eq!(hold(&mut monitor, &mut now, recovered, STEP), ProtectionLevel::Normal);
+    }
+}
@@ -0,0 +1,12 @@
+use can_messages::boot::{memory_x, Image};
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("memory.x"), memory_x(Image::Bootloader)).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
+    println!("cargo:rustc-link-arg-bins=--nmagic");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,166 @@
+//! bxCAN through its registers, see RM0091.
+//!
+//! Only the frames of the update protocol pass: standard data frames in,
+//! through one filter for the request identifier, and out through one
+//! mailbox, which keeps them in order. `block_on` polls the halves over and
+//! over, so they never register a waker.
+
+use can_messages::{
+    isotp::{FrameRx, FrameTx},
+    MessageId, BITRATE,
+};
+use core::{convert::Infallible, future::poll_fn, task::Poll};
+use embedded_can::{Id, StandardId};
+use stm32_metapac::{
+    can::regs::{Fr1, Fr2, Tdhr, Tdlr},
+    gpio::vals::{Moder, Ospeedr},
+    CAN, GPIOA, RCC, SYSCFG,
+};
+
+/// Time quanta per bit: the sync segment, 5 before the sample point and 2 after.
+const QUANTA: u32 = 8;
+const PRESCALER: u32 = crate::SYSCLK_HZ / BITRATE / QUANTA;
+const _: () = assert!(PRESCALER * QUANTA * BITRATE == crate::SYSCLK_HZ, "bit rate not reachable from the HSI");
+
+/// Classic CAN data frame with a standard identifier.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    id: StandardId,
+    len: u8,
+    data: [u8; 8],
+}
+
+impl embedded_can::Frame for Frame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        let Id::Standard(id) = id.into() else {
+            return None;
+        };
+        let mut frame = Self { id, len: data.len() as u8, data: [0; 8] };
+        frame.data.get_mut(..data.len())?.copy_from_slice(data);
+        Some(frame)
+    }
+
+    fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
+        None
+    }
+
+    fn is_extended(&self) -> bool {
+        false
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Id {
+        Id::Standard(self.id)
+    }
+
+    fn dlc(&self) -> usize {
+        self.len.into()
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data[..self.len.into()]
+    }
+}
+
+pub struct Tx(());
+
+pub struct Rx(());
+
+/// Join the bus, receiving the data frames of `id` only.
+pub fn init(id: MessageId) -> (Tx, Rx) {
+    let MessageId::Standard(id) = id else {
+        panic!("update requests use standard identifiers");
+    };
+
+    RCC.ahbenr().modify(|w| w.set_gpioaen(true));
+    RCC.apb1enr().modify(|w| w.set_canen(true));
+    RCC.apb2enr().modify(|w| w.set_syscfgen(true));
+    // CAN_RX on PA11 and CAN_TX on PA12, in place of PA9/PA10.
+    SYSCFG.cfgr1().modify(|w| w.set_pa11_pa12_rmp(true));
+    GPIOA.afr(1).modify(|w| {
+        w.set_afr(3, 4);
+        w.set_afr(4, 4);
+    });
+    GPIOA.ospeedr().modify(|w| w.set_ospeedr(12, Ospeedr::VERY_HIGH_SPEED));
+    GPIOA.moder().modify(|w| {
+        w.set_moder(11, Moder::ALTERNATE);
+        w.set_moder(12, Moder::ALTERNATE);
+    });
+
+    CAN.mcr().write(|w| w.set_inrq(true));
+    while !CAN.msr().read().inak() {}
+    CAN.btr().write(|w| {
+        w.set_brp((PRESCALER - 1) as u16);
+        w.set_ts(0, 5 - 1);
+        w.set_ts(1, 2 - 1);
+        w.set_sjw(2 - 1);
+    });
+
+    // Bank 0 lists the identifier four times in 16-bit scale, for FIFO 0.
+    let entry = (id as u32) << 5;
+    CAN.fmr().modify(|w| w.set_finit(true));
+    CAN.fa1r().write(|_| {});
+    CAN.fs1r().write(|_| {});
+    CAN.fm1r().write(|w| w.set_fbm(0, true));
+    CAN.ffa1r().write(|_| {});
+    CAN.fb(0).fr1().write_value(Fr1(entry << 16 | entry));
+    CAN.fb(0).fr2().write_value(Fr2(entry << 16 | entry));
+    CAN.fa1r().write(|w| w.set_fact(0, true));
+    CAN.fmr().modify(|w| w.set_finit(false));
+
+    // Leaving initialisation waits for the bus to be idle.
+    CAN.mcr().write(|w| w.set_abom(true));
+    while CAN.msr().read().inak() {}
+    (Tx(()), Rx(()))
+}
+
+impl FrameTx for Tx {
+    type Frame = Frame;
+    type Error = Infallible;
+
+    async fn send(&mut self, frame: &Frame) -> Result<(), Infallible> {
+        poll_fn(|_| {
+            crate::pet_watchdog();
+            if CAN.tsr().read().tme(0) { Poll::Ready(()) } else { Poll::Pending }
+        })
+        .await;
+        let mailbox = CAN.tx(0);
+        let [d0, d1, d2, d3, d4, d5, d6, d7] = frame.data;
+        mailbox.tdtr().write(|w| w.set_dlc(frame.len));
+        mailbox.tdlr().write_value(Tdlr(u32::from_le_bytes([d0, d1, d2, d3])));
+        mailbox.tdhr().write_value(Tdhr(u32::from_le_bytes([d4, d5, d6, d7])));
+        mailbox.tir().write(|w| {
+            w.set_stid(frame.id.as_raw());
+            w.set_txrq(true);
+        });
+        Ok(())
+    }
+}
+
+impl FrameRx for Rx {
+    type Frame = Frame;
+    type Error = Infallible;
+
+    async fn recv(&mut self) -> Result<Frame, Infallible> {
+        poll_fn(|_| {
+            crate::pet_watchdog();
+            if CAN.rfr(0).read().fmp() == 0 {
+                return Poll::Pending;
+            }
+            let mailbox = CAN.rx(0);
+            let (low, high) = (mailbox.rdlr().read().0, mailbox.rdhr().read().0);
+            let frame = Frame {
+                // The filter passes only standard identifiers.
+                id: StandardId::new(mailbox.rir().read().stid()).unwrap_or(StandardId::ZERO),
+                len: mailbox.rdtr().read().dlc().min(8),
+                data: ((high as u64) << 32 | low as u64).to_le_bytes(),
+            };
+            CAN.rfr(0).write(|w| w.set_rfom(true));
+            Poll::Ready(Ok(frame))
+        })
+        .await
+    }
+}
@@ -0,0 +1,66 @@
+//! Internal flash through its registers, see RM0091.
+
+use can_messages::boot::{self, FLASH_BASE, PAGE_SIZE};
+use core::{ptr, slice};
+use stm32_metapac::FLASH;
+
+/// Internal flash for the update protocol, unlocked for good.
+pub struct BootFlash(());
+
+/// Programming or erasing failed, or the page is write protected.
+#[derive(Debug)]
+pub struct Error;
+
+impl BootFlash {
+    pub fn unlock() -> Self {
+        FLASH.keyr().write_value(0x4567_0123);
+        FLASH.keyr().write_value(0xCDEF_89AB);
+        Self(())
+    }
+}
+
+/// Wait for the operation started last, then leave programming and erasing.
+fn finish() -> Result<(), Error> {
+    while FLASH.sr().read().bsy() {
+        crate::pet_watchdog();
+    }
+    FLASH.cr().write(|_| {});
+    let status = FLASH.sr().read();
+    // The flags clear by writing them back.
+    FLASH.sr().write_value(status);
+    if status.pgerr() || status.wrprt() { Err(Error) } else { Ok(()) }
+}
+
+impl boot::Flash for BootFlash {
+    type Error = Error;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        for page in (from..to).step_by(PAGE_SIZE as usize) {
+            FLASH.cr().write(|w| w.set_per(true));
+            FLASH.ar().write(|w| w.set_far(FLASH_BASE + page));
+            FLASH.cr().write(|w| {
+                w.set_per(true);
+                w.set_strt(true);
+            });
+            finish()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        let target = (FLASH_BASE + offset) as *mut u16;
+        for (i, half) in data.chunks_exact(2).enumerate() {
+            FLASH.cr().write(|w| w.set_pg(true));
+            unsafe { ptr::write_volatile(target.add(i), u16::from_le_bytes([half[0], half[1]])) };
+            finish()?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        crate::pet_watchdog();
+        // SAFETY: flash is always readable.
+        buf.copy_from_slice(unsafe { slice::from_raw_parts((FLASH_BASE + offset) as *const u8, buf.len()) });
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! CAN bootloader of the trailer bus nodes.
+//!
+//! Starts the application right away if its image is intact and no update
+//! was requested, before touching any peripheral. Otherwise it waits for the
+//! host to send a new image, see `can_messages::boot`. Build it for the node
+//! it goes on with `--features power-supply` or `--features coolbox`.
+//!
+//! Every byte left here is one more for the application, so it drives the
+//! few peripherals it needs through their registers, stays on the 8 MHz HSI
+//! it starts on and runs the ISO-TP transport with a busy `block_on` instead
+//! of the HAL and an executor.
+#![no_std]
+#![no_main]
+
+mod can;
+mod flash;
+mod time;
+
+use crate::flash::BootFlash;
+use can_messages::{
+    boot::{self, BootRecord, Crc32, Updater, MAX_REQUEST, MAX_RESPONSE},
+    isotp::IsoTp,
+    NodeId,
+};
+use core::{panic::PanicInfo, ptr, slice};
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::entry;
+use embassy_futures::block_on;
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+#[cfg(feature = "power-supply")]
+use stm32_metapac::{gpio::vals::Moder, GPIOB};
+use stm32_metapac::{
+    iwdg::vals::{Key, Pr},
+    syscfg::vals::MemMode,
+    IWDG, RCC, SYSCFG,
+};
+
+#[cfg(feature = "power-supply")]
+const NODE_ID: NodeId = NodeId::POWER_SUPPLY;
+#[cfg(feature = "coolbox")]
+const NODE_ID: NodeId = NodeId::COOLBOX;
+#[cfg(not(any(feature = "power-supply", feature = "coolbox")))]
+compile_error!("select the node with `--features power-supply` or `--features coolbox`");
+
+/// HSI, the clock after reset.
+const SYSCLK_HZ: u32 = 8_000_000;
+/// About one second: the 40 kHz LSI divided by 64. Flash operations and
+/// waiting for frames reload it as they go.
+const WATCHDOG_RELOAD: u16 = 625;
+/// Time for the last response to leave before the reset.
+const BOOT_DELAY: Duration = Duration::from_millis(10);
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    SCB::sys_reset()
+}
+
+/// Reload the watchdog, which the application may have left running.
+fn pet_watchdog() {
+    IWDG.kr().write(|w| w.set_key(Key::RESET));
+}
+
+fn start_watchdog() {
+    IWDG.kr().write(|w| w.set_key(Key::ENABLE));
+    IWDG.pr().write(|w| w.set_pr(Pr::DIVIDE_BY64));
+    IWDG.rlr().write(|w| w.set_rl(WATCHDOG_RELOAD));
+    IWDG.kr().write(|w| w.set_key(Key::START));
+}
+
+/// Turn the software power switch on again, the reset released it.
+#[cfg(feature = "power-supply")]
+fn hold_power() {
+    RCC.ahbenr().modify(|w| w.set_gpioben(true));
+    GPIOB.bsrr().write(|w| w.set_bs(8, true));
+    GPIOB.moder().modify(|w| w.set_moder(8, Moder::OUTPUT));
+}
+
+/// Check the application against its boot record, straight from flash.
+fn image_is_valid() -> bool {
+    // SAFETY: both lie in flash, which is always readable.
+    let record = unsafe { &*(boot::RECORD as *const [u8; BootRecord::LEN]) };
+    let Some(record) = BootRecord::from_bytes(record) else {
+        return false;
+    };
+    let image = unsafe { slice::from_raw_parts(boot::APP_START as *const u8, record.len as usize) };
+    let mut crc = Crc32::new();
+    // At 8 MHz this takes longer than the watchdog period of the applications.
+    for chunk in image.chunks(boot::PAGE_SIZE as usize) {
+        pet_watchdog();
+        crc.update(chunk);
+    }
+    crc.finish() == record.crc
+}
+
+/// Start the application as if it came out of reset.
+///
+/// # Safety
+///
+/// The application image has to be valid.
+unsafe fn start_application() -> ! {
+    let vectors = boot::APP_START as *const u32;
+    // Cortex-M0 has no VTOR: serve the vectors of the application from RAM mapped at 0.
+    unsafe { ptr::copy_nonoverlapping(vectors, boot::RAM_BASE as *mut u32, boot::VECTORS_LEN / 4) };
+    RCC.apb2enr().modify(|w| w.set_syscfgen(true));
+    SYSCFG.cfgr1().modify(|w| w.set_mem_mode(MemMode::SRAM));
+    unsafe { cortex_m::asm::bootload(vectors) }
+}
+
+/// Answer update requests until the host boots the new image.
+async fn serve(mut isotp: IsoTp<can::Tx, can::Rx>, mut updater: Updater<BootFlash>) -> ! {
+    let mut request = Vec::<u8, MAX_REQUEST>::new();
+    loop {
+        if isotp.receive(&mut request).await.is_err() {
+            continue;
+        }
+        let response = updater.handle(&request);
+        let mut buf = [0; MAX_RESPONSE];
+        // A lost response times out on the host, which then asks again.
+        let _ = isotp.send(response.encode(&mut buf)).await;
+        if updater.boot_requested() {
+            Timer::after(BOOT_DELAY).await;
+            SCB::sys_reset();
+        }
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    #[cfg(feature = "power-supply")]
+    hold_power();
+    // SAFETY: linked with the reserved RAM area of `boot::memory_x`.
+    let update = unsafe { boot::take_update_request() };
+    if !update && image_is_valid() {
+        unsafe { start_application() }
+    }
+
+    start_watchdog();
+    time::init();
+    let (tx, rx) = can::init(boot::request_id(NODE_ID));
+    let isotp = IsoTp::new(tx, rx, boot::node_config(NODE_ID));
+    block_on(serve(isotp, Updater::new(BootFlash::unlock())))
+}
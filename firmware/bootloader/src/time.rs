@@ -0,0 +1,30 @@
+//! `embassy-time` on the 32-bit counter of TIM2.
+//!
+//! It counts up from reset and wraps after 36 hours, far beyond any update.
+//! Nothing sleeps: `block_on` polls all the time, so timers wake right away.
+
+use core::task::Waker;
+use embassy_time_driver::{time_driver_impl, Driver, TICK_HZ};
+use stm32_metapac::{RCC, TIM2};
+
+struct Tim2;
+
+impl Driver for Tim2 {
+    fn now(&self) -> u64 {
+        TIM2.cnt().read().into()
+    }
+
+    fn schedule_wake(&self, _at: u64, waker: &Waker) {
+        waker.wake_by_ref();
+    }
+}
+
+time_driver_impl!(static DRIVER: Tim2 = Tim2);
+
+pub fn init() {
+    RCC.apb1enr().modify(|w| w.set_tim2en(true));
+    TIM2.psc().write_value((crate::SYSCLK_HZ / TICK_HZ as u32 - 1) as u16);
+    // Load the prescaler now instead of at the first overflow.
+    TIM2.egr().write(|w| w.set_ug(true));
+    TIM2.cr1().write(|w| w.set_cen(true));
+}
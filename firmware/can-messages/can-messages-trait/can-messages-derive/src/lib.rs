@@ -1,39 +1,255 @@
-use darling::FromDeriveInput;
+use darling::{FromDeriveInput, FromField, ast::Data, util::{Flag, Ignored}};
 use proc_macro::{self, TokenStream};
-use quote::quote;
-use syn::{Ident, Item, Expr, parse_macro_input};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{
+    DeriveInput, Expr, ExprLit, ExprPath, Ident, Index, ItemStruct, Lit, Member, Path, Type,
+    Token, TypePath, parse::{Parse, ParseStream}, parse_macro_input, parse_quote, spanned::Spanned,
+    visit_mut::{self, VisitMut},
+};
+
+/// Largest payload of a classic CAN frame.
+const MAX_PAYLOAD: usize = 8;
+
+/// Replaces multi-byte primitives with their little-endian wire types.
+struct WireLayout;
+
+impl VisitMut for WireLayout {
+    fn visit_type_path_mut(&mut self, ty: &mut TypePath) {
+        let wire = match ty.path.get_ident().map(Ident::to_string).as_deref() {
+            Some("u16") => quote!(U16),
+            Some("i16") => quote!(I16),
+            Some("u32") => quote!(U32),
+            Some("i32") => quote!(I32),
+            Some("u64") => quote!(U64),
+            Some("i64") => quote!(I64),
+            Some("f32") => quote!(F32),
+            Some("f64") => quote!(F64),
+            _ => return visit_mut::visit_type_path_mut(self, ty),
+        };
+        *ty = parse_quote!(::can_messages_trait::zerocopy::little_endian::#wire);
+    }
+}
+
+/// Physical unit and factor implied by common field name suffixes.
+const UNIT_SUFFIXES: &[(&str, &str, f32)] = &[
+    ("_mv", "V", 0.001),
+    ("_ma", "A", 0.001),
+    ("_mw", "W", 0.001),
+    ("_mah", "Ah", 0.001),
+    ("_ms", "s", 0.001),
+    ("_deg10", "degC", 0.1),
+    ("_pct10", "%", 0.1),
+];
 
 #[derive(FromDeriveInput)]
-#[darling(attributes(can))]
+#[darling(attributes(can), supports(struct_any))]
 struct Can {
     ident: Ident,
     id: Expr,
+    extended: Flag,
+    data: Data<Ignored, CanField>,
+}
+
+#[derive(FromField)]
+#[darling(attributes(can))]
+struct CanField {
+    ident: Option<Ident>,
+    ty: Type,
+    unit: Option<String>,
+    factor: Option<f32>,
+    offset: Option<f32>,
+    counter: Flag,
+    crc: Flag,
+}
+
+impl CanField {
+    fn name_and_member(&self, index: usize) -> (String, Member) {
+        match &self.ident {
+            Some(ident) => (ident.to_string(), Member::Named(ident.clone())),
+            None => (format!("field{index}"), Member::Unnamed(Index::from(index))),
+        }
+    }
+
+    /// Signal descriptions for this field, one per array element.
+    fn signals(&self, index: usize, msg: &Ident) -> darling::Result<Vec<TokenStream2>> {
+        let (name, member) = self.name_and_member(index);
+        let suffix = UNIT_SUFFIXES.iter().find(|(suffix, _, _)| name.ends_with(suffix));
+
+        let (ty, count) = match &self.ty {
+            Type::Array(array) => match &array.len {
+                Expr::Lit(ExprLit { lit: Lit::Int(len), .. }) => (&*array.elem, Some(len.base10_parse::<usize>()?)),
+                len => return Err(darling::Error::custom("array length must be a literal").with_span(len)),
+            },
+            ty => (ty, None),
+        };
+        let value = quote!(<#ty as ::can_messages_trait::SignalValue>);
+        let unit = match (&self.unit, suffix) {
+            (Some(unit), _) => quote!(#unit),
+            (None, Some((_, unit, _))) => quote!(#unit),
+            (None, None) => quote!(#value::UNIT),
+        };
+        let factor = match (self.factor, suffix) {
+            (Some(factor), _) => quote!(#factor),
+            (None, Some((_, _, factor))) => quote!(#factor),
+            (None, None) => quote!(#value::FACTOR),
+        };
+        let offset = self.offset.unwrap_or(0.0);
+
+        let signal = |name: String, element: usize| quote! {
+            ::can_messages_trait::Signal {
+                name: #name,
+                start_bit: ((::core::mem::offset_of!(#msg, #member) + #element * ::core::mem::size_of::<#ty>()) * 8) as u16,
+                size: (::core::mem::size_of::<#ty>() * 8) as u16,
+                signed: #value::SIGNED,
+                float: #value::FLOAT,
+                factor: #factor,
+                offset: #offset,
+                unit: #unit,
+            }
+        };
+        Ok(match count {
+            Some(count) => (0..count).map(|i| signal(format!("{name}_{i}"), i)).collect(),
+            None => vec![signal(name, 0)],
+        })
+    }
+}
+
+/// Type part of an `Enum::VARIANT` identifier expression, if any.
+fn id_type(id: &Expr) -> Option<Path> {
+    let Expr::Path(ExprPath { path, .. }) = id else {
+        return None;
+    };
+    let mut path = path.clone();
+    path.segments.pop()?;
+    path.segments.pop_punct();
+    (!path.segments.is_empty()).then_some(path)
 }
 
 #[proc_macro_derive(CanMessage, attributes(can))]
 pub fn derive(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input);
+    let input: DeriveInput = parse_macro_input!(input);
 
-    let Can { ident, id } = Can::from_derive_input(&input).expect("Missing or incorrect #[can] attribute");
+    let Can { ident, id, extended, data } = match Can::from_derive_input(&input) {
+        Ok(can) => can,
+        Err(e) => return e.write_errors().into(),
+    };
+    let span = ident.span();
+    let name = ident.to_string();
 
-    let output = quote! {
+    let fields = data.take_struct().map(|fields| fields.fields).unwrap_or_default();
+    let mut errors = darling::Error::accumulator();
+    let signals: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, field)| errors.handle(field.signals(i, &ident)))
+        .flatten()
+        .collect();
+
+    // Byte offset of the single field marked with `flag`, which must be a `u8`.
+    let mut marked = |flag: fn(&CanField) -> &Flag, what: &str| {
+        let mut found = fields.iter().enumerate().filter(|(_, field)| flag(field).is_present());
+        let (index, field) = found.next()?;
+        if let Some((_, extra)) = found.next() {
+            let msg = format!("only one field can be the {what}");
+            errors.push(syn::Error::new(flag(extra).span(), msg).into());
+        }
+        let (_, member) = field.name_and_member(index);
+        Some(quote_spanned! {field.ty.span()=>
+            {
+                let _ = |msg: &#ident| -> u8 { msg.#member };
+                ::core::mem::offset_of!(#ident, #member)
+            }
+        })
+    };
+    let crc = marked(|field| &field.crc, "checksum")
+        .map(|offset| quote!(const CRC: Option<usize> = Some(#offset);));
+    let counter = marked(|field| &field.counter, "counter")
+        .map(|offset| quote!(const COUNTER: Option<usize> = Some(#offset);));
+    if let Err(e) = errors.finish() {
+        return e.write_errors().into();
+    }
+
+    let payload_msg = format!("CAN message `{ident}` is larger than {MAX_PAYLOAD} bytes");
+    let align_msg = format!("CAN message `{ident}` must not require alignment");
+    let extended = extended.is_present();
+    let (message_id, max_id, id_msg) = if extended {
+        (
+            quote!(Extended((#id) as u32)),
+            quote!(::can_messages_trait::MessageId::MAX_EXTENDED),
+            format!("CAN message `{ident}` has an identifier above the 29-bit range"),
+        )
+    } else {
+        (
+            quote!(Standard((#id) as u16)),
+            quote!(::can_messages_trait::MessageId::MAX_STANDARD as u32),
+            format!("CAN message `{ident}` has an identifier above the 11-bit range"),
+        )
+    };
+
+    // Two messages sharing one identifier give conflicting implementations here.
+    let unique = id_type(&id).map(|ty| {
+        quote_spanned! {span=>
+            #[automatically_derived]
+            impl ::can_messages_trait::UniqueId<{ (#id) as u32 }, #extended> for #ty {}
+        }
+    });
+
+    let output = quote_spanned! {span=>
         #[automatically_derived]
         impl ::can_messages_trait::CanMessage for #ident {
-            const ID: u16 = (#id) as u16;
+            const ID: ::can_messages_trait::MessageId = ::can_messages_trait::MessageId::#message_id;
+            const NAME: &'static str = #name;
+            const SIGNALS: &'static [::can_messages_trait::Signal] = &[#(#signals),*];
+            #crc
+            #counter
         }
+
+        const _: () = ::core::assert!(::core::mem::size_of::<#ident>() <= #MAX_PAYLOAD, #payload_msg);
+        const _: () = ::core::assert!(::core::mem::align_of::<#ident>() == 1, #align_msg);
+        const _: () = ::core::assert!(((#id) as u32) <= #max_id, #id_msg);
+
+        #unique
     };
     output.into()
 }
 
+/// Arguments of `#[can_message(ID)]` or `#[can_message(ID, extended)]`.
+struct CanMessageArgs {
+    id: Expr,
+    extended: Option<Ident>,
+}
+
+impl Parse for CanMessageArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let id = input.parse()?;
+        let mut extended = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let flag: Ident = input.parse()?;
+            if flag != "extended" {
+                return Err(syn::Error::new(flag.span(), "expected `extended`"));
+            }
+            extended = Some(flag);
+            input.parse::<Option<Token![,]>>()?;
+        }
+        Ok(Self { id, extended })
+    }
+}
+
 #[proc_macro_attribute]
 pub fn can_message(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let id: Expr = parse_macro_input!(attr);
-    let item: Item = parse_macro_input!(item);
+    let CanMessageArgs { id, extended } = parse_macro_input!(attr);
+    let mut item: ItemStruct = parse_macro_input!(item);
+
+    // Fix the wire format to little-endian regardless of the target byte order.
+    for field in item.fields.iter_mut() {
+        WireLayout.visit_type_mut(&mut field.ty);
+    }
 
     let output = quote! {
         #[repr(C)]
         #[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, CanMessage)]
-        #[can(id = #id)]
+        #[can(id = #id, #extended)]
         #item
     };
     output.into()
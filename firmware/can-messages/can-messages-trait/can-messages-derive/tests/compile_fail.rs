@@ -0,0 +1,6 @@
+//! Messages the attribute has to reject while compiling.
+
+#[test]
+fn compile_fail() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
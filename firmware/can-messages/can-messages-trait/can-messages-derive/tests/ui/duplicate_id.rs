@@ -0,0 +1,18 @@
+use can_messages_trait::prelude::*;
+
+#[repr(u16)]
+pub enum Id {
+    Command = 0x010,
+}
+
+#[can_message(Id::Command)]
+pub struct First {
+    pub value: u8,
+}
+
+#[can_message(Id::Command)]
+pub struct Second {
+    pub value: u8,
+}
+
+fn main() {}
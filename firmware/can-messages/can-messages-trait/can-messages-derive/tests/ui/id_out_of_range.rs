@@ -0,0 +1,8 @@
+use can_messages_trait::prelude::*;
+
+#[can_message(0x800)]
+pub struct OutOfRange {
+    pub value: u8,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use can_messages_trait::prelude::*;
+
+#[can_message(0x100)]
+pub struct Oversized {
+    pub time_ms: u64,
+    pub flags: u8,
+}
+
+fn main() {}
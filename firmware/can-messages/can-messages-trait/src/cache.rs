@@ -0,0 +1,204 @@
+//! Latest received value of each message, with its age.
+//!
+//! The receive loop offers every frame to the [`Latest`] stores, consumers
+//! read or await them and decide themselves how old is too old.
+
+use core::{cell::RefCell, future::poll_fn, task::Poll};
+use embassy_sync::{blocking_mutex::{Mutex, raw::RawMutex}, waitqueue::WakerRegistration};
+use embassy_time::{Duration, Instant};
+use crate::{CanMessage, CanParseable, IncomingCan};
+
+/// One received value.
+#[derive(Debug, Clone)]
+pub struct Sample<T> {
+    pub value: T,
+    pub received: Instant,
+    /// Number of values received so far, wrapping, never 0.
+    pub count: u32,
+}
+
+impl<T> Sample<T> {
+    pub fn age(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.received)
+    }
+}
+
+struct State<T> {
+    sample: Option<Sample<T>>,
+    waker: WakerRegistration,
+}
+
+/// Holds the latest value of one message type.
+///
+/// Only one task should wait for updates at a time; more waiters work,
+/// but keep waking each other up.
+pub struct Latest<M: RawMutex, T> {
+    state: Mutex<M, RefCell<State<T>>>,
+}
+
+impl<M: RawMutex, T: CanMessage + Clone> Latest<M, T> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State { sample: None, waker: WakerRegistration::new() })),
+        }
+    }
+
+    /// Store a new value received now.
+    pub fn update(&self, value: T) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let count = match &state.sample {
+                Some(sample) if sample.count != u32::MAX => sample.count + 1,
+                _ => 1,
+            };
+            state.sample = Some(Sample { value, received: Instant::now(), count });
+            state.waker.wake();
+        });
+    }
+
+    /// Store the frame if it carries this message, return `true` if it did.
+    pub fn offer<C: CanParseable>(&self, frame: &C) -> bool {
+        match frame.try_read::<T>() {
+            Some(value) => {
+                self.update(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Last value, however old.
+    pub fn get(&self) -> Option<Sample<T>> {
+        self.state.lock(|state| state.borrow().sample.clone())
+    }
+
+    /// Last value, if it was received within `max_age`.
+    pub fn get_fresh(&self, max_age: Duration) -> Option<T> {
+        self.get().filter(|s| s.age() <= max_age).map(|s| s.value)
+    }
+
+    /// Last value, if it is not the one with counter `seen`.
+    pub fn get_if_newer(&self, seen: u32) -> Option<Sample<T>> {
+        self.get().filter(|s| s.count != seen)
+    }
+
+    /// Wait for a value other than the one with counter `seen`.
+    ///
+    /// Pass 0 to get the current value, if any, without waiting.
+    pub async fn wait_newer(&self, seen: u32) -> Sample<T> {
+        poll_fn(|cx| {
+            self.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                match &state.sample {
+                    Some(sample) if sample.count != seen => Poll::Ready(sample.clone()),
+                    _ => {
+                        state.waker.register(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    /// Wait for the next value received from now on.
+    pub async fn next(&self) -> Sample<T> {
+        let seen = self.get().map_or(0, |s| s.count);
+        self.wait_newer(seen).await
+    }
+}
+
+impl<M: RawMutex, T: CanMessage + Clone> Default for Latest<M, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use embassy_futures::{block_on, poll_once};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_time::Timer;
+    use crate::{MessageId, Signal, prelude::*};
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, TryFromBytes, IntoBytes, Immutable, KnownLayout)]
+    #[repr(C)]
+    struct Level {
+        value: u8,
+    }
+
+    impl CanMessage for Level {
+        const ID: MessageId = MessageId::Standard(0x10);
+        const NAME: &'static str = "Level";
+        const SIGNALS: &'static [Signal] = &[];
+    }
+
+    struct TestFrame(MessageId, [u8; 1]);
+
+    impl CanParseable for TestFrame {
+        fn id_matches<T: CanMessage>(&self) -> bool {
+            self.0 == T::ID
+        }
+
+        fn as_bytes(&self) -> &[u8] {
+            &self.1
+        }
+    }
+
+    type Store = Latest<NoopRawMutex, Level>;
+
+    #[test]
+    fn offer() {
+        let store = Store::new();
+        assert!(!store.offer(&TestFrame(MessageId::Standard(0x11), [1])));
+        assert!(store.get().is_none());
+        assert!(store.offer(&TestFrame(Level::ID, [7])));
+        assert_eq!(store.get().map(|s| s.value), Some(Level { value: 7 }));
+    }
+
+    #[test]
+    fn fresh() {
+        let store = Store::new();
+        assert_eq!(store.get_fresh(Duration::from_secs(60)), None);
+        store.update(Level { value: 1 });
+        assert_eq!(store.get_fresh(Duration::from_secs(60)), Some(Level { value: 1 }));
+        block_on(Timer::after_millis(20));
+        assert_eq!(store.get_fresh(Duration::from_millis(10)), None);
+        assert!(store.get().is_some(), "stale values are still kept");
+    }
+
+    #[test]
+    fn counter() {
+        let store = Store::new();
+        assert!(store.get_if_newer(0).is_none());
+        store.update(Level { value: 1 });
+        let first = store.get_if_newer(0).unwrap();
+        assert_eq!(first.count, 1);
+        assert!(store.get_if_newer(first.count).is_none());
+
+        // The same value again is still a new sample.
+        store.update(Level { value: 1 });
+        assert_eq!(store.get_if_newer(first.count).map(|s| s.count), Some(2));
+
+        // Wraps around, skipping 0.
+        store.state.lock(|state| state.borrow_mut().sample.as_mut().unwrap().count = u32::MAX);
+        store.update(Level { value: 2 });
+        assert_eq!(store.get().map(|s| s.count), Some(1));
+    }
+
+    #[test]
+    fn wait() {
+        let store = Store::new();
+        assert!(poll_once(store.wait_newer(0)).is_pending());
+        store.update(Level { value: 1 });
+        assert_eq!(block_on(store.wait_newer(0)).count, 1);
+
+        let mut next = pin!(store.next());
+        assert!(poll_once(next.as_mut()).is_pending());
+        store.update(Level { value: 2 });
+        let sample = block_on(next);
+        assert_eq!((sample.value, sample.count), (Level { value: 2 }, 2));
+    }
+}
@@ -0,0 +1,143 @@
+//! Bus-wide time, kept by every node from the time of a master.
+//!
+//! The master sends its own time periodically. A [`SyncedClock`] remembers
+//! the last one together with the local [`Instant`] it arrived at, and from
+//! then on converts local instants to master time. Between two syncs it
+//! drifts with the local oscillator, about 1 % with the HSI of an STM32F0.
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{Mutex, raw::RawMutex};
+use embassy_time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+struct Anchor {
+    master_ms: u64,
+    received: Instant,
+}
+
+/// Master time, shared between the receive loop and everyone timestamping.
+pub struct SyncedClock<M: RawMutex> {
+    anchor: Mutex<M, Cell<Option<Anchor>>>,
+}
+
+impl<M: RawMutex> SyncedClock<M> {
+    pub const fn new() -> Self {
+        Self { anchor: Mutex::new(Cell::new(None)) }
+    }
+
+    /// Take the master time `master_ms`, received at `received`.
+    ///
+    /// Use the receive timestamp of the frame, not the time it got processed.
+    pub fn update(&self, master_ms: u64, received: Instant) {
+        self.anchor.lock(|anchor| anchor.set(Some(Anchor { master_ms, received })));
+    }
+
+    /// Master time at `instant` in milliseconds, `None` until the first sync.
+    pub fn at(&self, instant: Instant) -> Option<u64> {
+        let anchor = self.anchor.lock(Cell::get)?;
+        Some(match instant.checked_duration_since(anchor.received) {
+            Some(since) => anchor.master_ms.saturating_add(since.as_millis()),
+            None => anchor.master_ms.saturating_sub(anchor.received.duration_since(instant).as_millis()),
+        })
+    }
+
+    /// Master time now in milliseconds, `None` until the first sync.
+    pub fn now(&self) -> Option<u64> {
+        self.at(Instant::now())
+    }
+
+    /// Timestamp for log lines, the uptime until the first sync.
+    pub fn log_time(&self) -> LogTime {
+        match self.now() {
+            Some(ms) => LogTime::Synced(ms),
+            None => LogTime::Uptime(Instant::now().as_millis()),
+        }
+    }
+
+    /// Time since the last sync, `None` if there was none.
+    pub fn since_sync(&self) -> Option<Duration> {
+        self.anchor.lock(Cell::get).map(|anchor| anchor.received.elapsed())
+    }
+}
+
+impl<M: RawMutex> Default for SyncedClock<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Log timestamp in milliseconds, the uptime is marked with `~`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTime {
+    Synced(u64),
+    Uptime(u64),
+}
+
+/// Seconds with milliseconds, like the `ms` hint of defmt.
+impl core::fmt::Display for LogTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (mark, ms) = match self {
+            LogTime::Synced(ms) => ("", ms),
+            LogTime::Uptime(ms) => ("~", ms),
+        };
+        write!(f, "{mark}{}.{:03}", ms / 1000, ms % 1000)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for LogTime {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            LogTime::Synced(ms) => defmt::write!(f, "{=u64:ms}", ms),
+            LogTime::Uptime(ms) => defmt::write!(f, "~{=u64:ms}", ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use super::*;
+
+    type Clock = SyncedClock<NoopRawMutex>;
+
+    #[test]
+    fn offset() {
+        let clock = Clock::new();
+        let received = Instant::from_millis(5000);
+        assert_eq!(clock.at(received), None);
+        assert_eq!(clock.since_sync(), None);
+
+        clock.update(1_700_000_000_000, received);
+        assert_eq!(clock.at(received), Some(1_700_000_000_000));
+        assert_eq!(clock.at(received + Duration::from_millis(250)), Some(1_700_000_000_250));
+        assert_eq!(clock.at(Instant::from_millis(4000)), Some(1_699_999_999_000));
+
+        // A new sync replaces the old one instead of averaging.
+        clock.update(2000, Instant::from_millis(6000));
+        assert_eq!(clock.at(Instant::from_millis(6100)), Some(2100));
+        assert_eq!(clock.at(Instant::from_millis(3000)), Some(0), "before the master started");
+    }
+
+    #[test]
+    fn log_time() {
+        let clock = Clock::new();
+        assert!(matches!(clock.log_time(), LogTime::Uptime(_)));
+        clock.update(10_000, Instant::now());
+        let LogTime::Synced(ms) = clock.log_time() else {
+            panic!("not synced");
+        };
+        assert!((10_000..11_000).contains(&ms));
+        assert!(clock.since_sync().is_some());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(LogTime::Synced(1234).to_string(), "1.234");
+        assert_eq!(LogTime::Uptime(1234).to_string(), "~1.234");
+        assert_eq!(LogTime::Uptime(5).to_string(), "~0.005");
+        assert_eq!(LogTime::Synced(60_000).to_string(), "60.000");
+    }
+}
@@ -0,0 +1,115 @@
+//! DBC file export for bus analysis tools like SavvyCAN or cantools.
+
+use core::fmt::{Result, Write};
+use crate::{MessageId, MessageInfo};
+
+/// Node name DBC uses for "no particular sender or receiver".
+const NO_NODE: &str = "Vector__XXX";
+/// DBC marks extended identifiers with the top bit.
+const EXTENDED_FLAG: u32 = 1 << 31;
+
+/// Identifier as written in the DBC file.
+fn dbc_id(msg: &MessageInfo) -> u32 {
+    match msg.id {
+        MessageId::Standard(id) => id as u32,
+        MessageId::Extended(id) => id | EXTENDED_FLAG,
+    }
+}
+
+/// Write a DBC database describing `messages`.
+pub fn write_dbc(w: &mut impl Write, messages: &[MessageInfo]) -> Result {
+    writeln!(w, "VERSION \"\"")?;
+    writeln!(w)?;
+    writeln!(w, "NS_ :")?;
+    writeln!(w)?;
+    writeln!(w, "BS_:")?;
+    writeln!(w)?;
+    writeln!(w, "BU_:")?;
+
+    for msg in messages {
+        writeln!(w)?;
+        writeln!(w, "BO_ {} {}: {} {NO_NODE}", dbc_id(msg), msg.name, msg.size)?;
+        for sig in msg.signals {
+            // Byte order `1` is little-endian (Intel).
+            writeln!(
+                w,
+                " SG_ {} : {}|{}@1{} ({},{}) [0|0] \"{}\" {NO_NODE}",
+                sig.name,
+                sig.start_bit,
+                sig.size,
+                if sig.signed { '-' } else { '+' },
+                sig.factor,
+                sig.offset,
+                sig.unit,
+            )?;
+        }
+    }
+
+    let mut floats = messages
+        .iter()
+        .flat_map(|msg| msg.signals.iter().map(move |sig| (msg, sig)))
+        .filter(|(_, sig)| sig.float)
+        .peekable();
+    if floats.peek().is_some() {
+        writeln!(w)?;
+    }
+    for (msg, sig) in floats {
+        // Value type `1` is IEEE single, `2` is IEEE double.
+        let kind = if sig.size == 64 { 2 } else { 1 };
+        writeln!(w, "SIG_VALTYPE_ {} {} : {kind};", dbc_id(msg), sig.name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::String;
+    use crate::Signal;
+    use super::*;
+
+    const fn signal(name: &'static str, start_bit: u16, size: u16, signed: bool, float: bool, unit: &'static str) -> Signal {
+        Signal { name, start_bit, size, signed, float, factor: 1.0, offset: 0.0, unit }
+    }
+
+    const MESSAGES: &[MessageInfo] = &[
+        MessageInfo {
+            id: MessageId::Standard(0x123),
+            name: "Battery",
+            size: 4,
+            signals: &[signal("voltage", 0, 16, false, false, "mV"), signal("current", 16, 16, true, false, "mA")],
+        },
+        MessageInfo {
+            id: MessageId::Extended(0x18FF_0042),
+            name: "Climate",
+            size: 5,
+            signals: &[signal("temperature", 0, 32, true, true, "degC"), signal("state", 32, 8, false, false, "")],
+        },
+    ];
+
+    #[test]
+    fn golden() {
+        let mut dbc = String::new();
+        write_dbc(&mut dbc, MESSAGES).unwrap();
+        assert_eq!(
+            dbc,
+            "VERSION \"\"\n\
+             \n\
+             NS_ :\n\
+             \n\
+             BS_:\n\
+             \n\
+             BU_:\n\
+             \n\
+             BO_ 291 Battery: 4 Vector__XXX\n \
+             SG_ voltage : 0|16@1+ (1,0) [0|0] \"mV\" Vector__XXX\n \
+             SG_ current : 16|16@1- (1,0) [0|0] \"mA\" Vector__XXX\n\
+             \n\
+             BO_ 2566848578 Climate: 5 Vector__XXX\n \
+             SG_ temperature : 0|32@1- (1,0) [0|0] \"degC\" Vector__XXX\n \
+             SG_ state : 32|8@1+ (1,0) [0|0] \"\" Vector__XXX\n\
+             \n\
+             SIG_VALTYPE_ 2566848578 temperature : 1;\n"
+        );
+    }
+}
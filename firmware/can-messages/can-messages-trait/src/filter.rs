@@ -0,0 +1,401 @@
+//! Acceptance filter banks for a set of received messages.
+//!
+//! Follows the bank layout of the bxCAN controller: a bank holds four
+//! standard or two extended identifiers in list mode, two standard or one
+//! extended identifier with a mask in mask mode. When the identifiers do not
+//! fit into the available banks, the closest ones get merged into masks,
+//! letting a few more frames through than strictly needed.
+
+use crate::MessageId;
+
+/// Filter banks of a bxCAN controller that does not share them.
+pub const BXCAN_BANKS: usize = 14;
+
+/// Distinct identifiers kept apart while planning, more get merged early.
+const MAX_GROUPS: usize = 32;
+
+/// Configuration of one filter bank.
+///
+/// Unused slots repeat a used one. Masks have a bit set for every identifier
+/// bit that has to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bank {
+    StandardList([u16; 4]),
+    StandardMask([(u16, u16); 2]),
+    ExtendedList([u32; 2]),
+    ExtendedMask(u32, u32),
+    /// Every frame, the last resort when nothing else fits.
+    AcceptAll,
+}
+
+impl Bank {
+    /// Bank of `kind` from identifier and mask pairs, as many as it holds.
+    const fn new(kind: usize, [a, b, c, d]: [(u32, u32); 4]) -> Self {
+        match kind {
+            0 => Bank::StandardList([a.0 as u16, b.0 as u16, c.0 as u16, d.0 as u16]),
+            1 => Bank::StandardMask([(a.0 as u16, a.1 as u16), (b.0 as u16, b.1 as u16)]),
+            2 => Bank::ExtendedList([a.0, b.0]),
+            _ => Bank::ExtendedMask(a.0, a.1),
+        }
+    }
+}
+
+/// Identifiers matching `id` in all `mask` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Group {
+    extended: bool,
+    id: u32,
+    mask: u32,
+}
+
+impl Group {
+    const fn exact(id: MessageId) -> Self {
+        Self { extended: id.is_extended(), id: id.raw(), mask: Self::full(id.is_extended()) }
+    }
+
+    const fn full(extended: bool) -> u32 {
+        if extended { MessageId::MAX_EXTENDED } else { MessageId::MAX_STANDARD as u32 }
+    }
+
+    const fn is_exact(&self) -> bool {
+        self.mask == Self::full(self.extended)
+    }
+
+    const fn contains(&self, other: &Group) -> bool {
+        self.extended == other.extended && other.mask & self.mask == self.mask && other.id & self.mask == self.id
+    }
+
+    const fn merge(&self, other: &Group) -> Self {
+        let mask = self.mask & other.mask & !(self.id ^ other.id);
+        Self { extended: self.extended, id: self.id & mask, mask }
+    }
+
+    /// Identifier bits that no longer have to match.
+    const fn wildcards(&self) -> u32 {
+        (Self::full(self.extended) & !self.mask).count_ones()
+    }
+
+    /// Bank kind: standard list, standard mask, extended list or extended mask.
+    const fn kind(&self) -> usize {
+        self.extended as usize * 2 + !self.is_exact() as usize
+    }
+}
+
+/// Groups of each bank kind that fit into one bank.
+const PER_BANK: [usize; 4] = [4, 2, 2, 1];
+
+/// Banks needed for the groups.
+const fn bank_count(groups: &[Group]) -> usize {
+    let mut kinds = [0_usize; 4];
+    let mut i = 0;
+    while i < groups.len() {
+        kinds[groups[i].kind()] += 1;
+        i += 1;
+    }
+    let mut banks = 0;
+    let mut kind = 0;
+    while kind < kinds.len() {
+        banks += kinds[kind].div_ceil(PER_BANK[kind]);
+        kind += 1;
+    }
+    banks
+}
+
+/// Merge the two groups of one format that give the tightest mask.
+///
+/// Returns `false` if every format is down to a single group.
+const fn merge_closest(groups: &mut [Group; MAX_GROUPS], len: &mut usize) -> bool {
+    let mut best: Option<(usize, usize, u32)> = None;
+    let mut a = 0;
+    while a < *len {
+        let mut b = a + 1;
+        while b < *len {
+            if groups[a].extended == groups[b].extended {
+                let cost = groups[a].merge(&groups[b]).wildcards();
+                best = match best {
+                    Some((_, _, best_cost)) if best_cost <= cost => best,
+                    _ => Some((a, b, cost)),
+                };
+            }
+            b += 1;
+        }
+        a += 1;
+    }
+    let Some((a, b, _)) = best else {
+        return false;
+    };
+    // The wider mask may cover other groups as well.
+    let merged = groups[a].merge(&groups[b]);
+    let mut kept = 0;
+    let mut i = 0;
+    while i < *len {
+        if !merged.contains(&groups[i]) {
+            groups[kept] = groups[i];
+            kept += 1;
+        }
+        i += 1;
+    }
+    groups[kept] = merged;
+    *len = kept + 1;
+    true
+}
+
+/// Filter banks accepting the frames of a set of messages, at most `N` banks.
+///
+/// Planning takes more code than the plan, so work it out at compile time:
+///
+/// ```ignore
+/// const FILTERS: FilterPlan<BXCAN_BANKS> = FilterPlan::new(Subscribed::IDS);
+/// FILTERS.apply(&mut rx);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FilterPlan<const N: usize> {
+    banks: [Bank; N],
+    len: usize,
+}
+
+impl<const N: usize> FilterPlan<N> {
+    /// Plan the banks for `ids`, duplicates are fine.
+    pub const fn new(ids: &[MessageId]) -> Self {
+        let mut groups = [Group { extended: false, id: 0, mask: 0 }; MAX_GROUPS];
+        let mut len = 0;
+        let mut i = 0;
+        'ids: while i < ids.len() {
+            let group = Group::exact(ids[i]);
+            i += 1;
+            let mut j = 0;
+            while j < len {
+                if groups[j].contains(&group) {
+                    continue 'ids;
+                }
+                j += 1;
+            }
+            if len == MAX_GROUPS {
+                merge_closest(&mut groups, &mut len);
+            }
+            groups[len] = group;
+            len += 1;
+        }
+        while bank_count(groups.split_at(len).0) > N {
+            if !merge_closest(&mut groups, &mut len) {
+                break;
+            }
+        }
+        Self::pack(groups.split_at(len).0)
+    }
+
+    const fn pack(groups: &[Group]) -> Self {
+        let mut plan = Self { banks: [Bank::AcceptAll; N], len: 0 };
+        if bank_count(groups) > N {
+            // Only with a single bank for both formats.
+            if N > 0 {
+                plan.len = 1;
+            }
+            return plan;
+        }
+
+        // Groups of a kind fill its banks in order, unused slots repeat the first one.
+        let mut kind = 0;
+        while kind < PER_BANK.len() {
+            let mut slots = [(0, 0); 4];
+            let mut used = 0;
+            let mut i = 0;
+            while i < groups.len() {
+                if groups[i].kind() == kind {
+                    slots[used] = (groups[i].id, groups[i].mask);
+                    used += 1;
+                }
+                i += 1;
+                let last = i == groups.len();
+                if used == PER_BANK[kind] || (last && used > 0) {
+                    while used < slots.len() {
+                        slots[used] = slots[0];
+                        used += 1;
+                    }
+                    plan.banks[plan.len] = Bank::new(kind, slots);
+                    plan.len += 1;
+                    used = 0;
+                }
+            }
+            kind += 1;
+        }
+        plan
+    }
+
+    /// Banks in use.
+    pub fn banks(&self) -> &[Bank] {
+        &self.banks[..self.len]
+    }
+
+    /// Check if a frame with `id` gets through, wanted or not.
+    pub fn accepts(&self, id: MessageId) -> bool {
+        let raw = id.raw();
+        self.banks().iter().any(|bank| match (*bank, id) {
+            (Bank::StandardList(ids), MessageId::Standard(id)) => ids.contains(&id),
+            (Bank::StandardMask(masks), MessageId::Standard(id)) => masks.iter().any(|&(m, mask)| id & mask == m),
+            (Bank::ExtendedList(ids), MessageId::Extended(_)) => ids.contains(&raw),
+            (Bank::ExtendedMask(m, mask), MessageId::Extended(_)) => raw & mask == m,
+            (Bank::AcceptAll, _) => true,
+            _ => false,
+        })
+    }
+}
+
+#[cfg(feature = "embassy")]
+mod embassy {
+    use embassy_stm32::{
+        can::CanRx,
+        pac::{
+            can::regs::{Fr1, Fr2},
+            CAN,
+        },
+    };
+    use super::{Bank, FilterPlan};
+
+    /// IDE bit of a 16-bit and of a 32-bit filter, masks require it to match.
+    const F16_IDE: u32 = 0b01000;
+    const F32_IDE: u32 = 0b100;
+
+    const fn std(id: u16) -> u32 {
+        (id as u32) << 5
+    }
+
+    const fn ext(id: u32) -> u32 {
+        id << 3 | F32_IDE
+    }
+
+    impl Bank {
+        /// List mode, 32-bit scale and the two bank registers, matching data
+        /// frames in list mode and data and remote frames with a mask.
+        const fn registers(self) -> (bool, bool, u32, u32) {
+            match self {
+                Bank::StandardList([a, b, c, d]) => (true, false, std(b) << 16 | std(a), std(d) << 16 | std(c)),
+                Bank::StandardMask([(a, a_mask), (b, b_mask)]) => (
+                    false,
+                    false,
+                    (std(a_mask) | F16_IDE) << 16 | std(a),
+                    (std(b_mask) | F16_IDE) << 16 | std(b),
+                ),
+                Bank::ExtendedList([a, b]) => (true, true, ext(a), ext(b)),
+                Bank::ExtendedMask(id, mask) => (false, true, ext(id), ext(mask)),
+                Bank::AcceptAll => (false, true, 0, 0),
+            }
+        }
+    }
+
+    impl<const N: usize> FilterPlan<N> {
+        /// Replace all filter banks, sending accepted frames to FIFO 0.
+        ///
+        /// Writes the registers itself, the filter API of `embassy_stm32`
+        /// takes several times the flash. Taking `rx` keeps anyone else from
+        /// changing the banks meanwhile.
+        pub fn apply(&self, _rx: &mut CanRx<'_>) {
+            CAN.fmr().modify(|w| w.set_finit(true));
+            CAN.fa1r().write(|_| {});
+            for (index, bank) in self.banks().iter().enumerate() {
+                let (list, scale32, fr1, fr2) = bank.registers();
+                CAN.fm1r().modify(|w| w.set_fbm(index, list));
+                CAN.fs1r().modify(|w| w.set_fsc(index, scale32));
+                CAN.ffa1r().modify(|w| w.set_ffa(index, false));
+                CAN.fb(index).fr1().write_value(Fr1(fr1));
+                CAN.fb(index).fr2().write_value(Fr2(fr2));
+                CAN.fa1r().modify(|w| w.set_fact(index, true));
+            }
+            CAN.fmr().modify(|w| w.set_finit(false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn std(id: u16) -> MessageId {
+        MessageId::Standard(id)
+    }
+
+    const fn ext(id: u32) -> MessageId {
+        MessageId::Extended(id)
+    }
+
+    #[test]
+    fn exact_ids_in_list_banks() {
+        let ids = [std(0x100), std(0x101), std(0x102), std(0x200), std(0x300), std(0x100), ext(0x1234_5678)];
+        let plan = FilterPlan::<BXCAN_BANKS>::new(&ids);
+        assert_eq!(
+            plan.banks(),
+            [
+                Bank::StandardList([0x100, 0x101, 0x102, 0x200]),
+                Bank::StandardList([0x300; 4]),
+                Bank::ExtendedList([0x1234_5678; 2]),
+            ]
+        );
+        assert!(!plan.accepts(std(0x103)));
+        assert!(!plan.accepts(ext(0x100)));
+    }
+
+    #[test]
+    fn masks_once_banks_run_out() {
+        let ids = [std(0x100), std(0x101), std(0x102), std(0x103), std(0x200), std(0x201)];
+        let plan = FilterPlan::<1>::new(&ids);
+        assert_eq!(plan.banks(), [Bank::StandardMask([(0x200, 0x7FE), (0x100, 0x7FC)])]);
+        assert!(!plan.accepts(std(0x104)));
+
+        let plan = FilterPlan::<1>::new(&[ext(0x10), ext(0x11), ext(0x13)]);
+        assert_eq!(plan.banks(), [Bank::ExtendedMask(0x10, MessageId::MAX_EXTENDED & !0x3)]);
+    }
+
+    #[test]
+    fn mixed_formats() {
+        // Same raw value, different frames.
+        let plan = FilterPlan::<BXCAN_BANKS>::new(&[std(0x123), ext(0x7FF_0000)]);
+        assert!(plan.accepts(std(0x123)));
+        assert!(!plan.accepts(ext(0x123)));
+        assert!(plan.accepts(ext(0x7FF_0000)));
+
+        // A format is never merged into the other one.
+        let plan = FilterPlan::<2>::new(&[std(0x1), std(0x2), std(0x4), std(0x8), std(0x10), ext(0x1), ext(0x2), ext(0x4)]);
+        assert_eq!(plan.banks().len(), 2);
+        assert!(plan.banks().iter().any(|bank| matches!(bank, Bank::StandardMask(_))));
+        assert!(plan.banks().iter().any(|bank| matches!(bank, Bank::ExtendedMask(..))));
+
+        // Neither fits into a single bank.
+        let plan = FilterPlan::<1>::new(&[std(0x1), ext(0x1)]);
+        assert_eq!(plan.banks(), [Bank::AcceptAll]);
+    }
+
+    #[test]
+    fn planned_at_compile_time() {
+        const PLAN: FilterPlan<1> = FilterPlan::new(&[std(0x100), std(0x101)]);
+        assert_eq!(PLAN.banks(), [Bank::StandardList([0x100, 0x101, 0x100, 0x100])]);
+    }
+
+    fn check<const N: usize>(ids: &[MessageId]) {
+        let plan = FilterPlan::<N>::new(ids);
+        assert!(plan.banks().len() <= N);
+        for &id in ids {
+            assert!(plan.accepts(id), "{id} with {} of {N} banks", ids.len());
+        }
+    }
+
+    #[test]
+    fn subscribed_ids_pass() {
+        let mut ids = [std(0); 48];
+        for (i, id) in ids.iter_mut().enumerate() {
+            let i = i as u32;
+            *id = match i % 3 {
+                0 => ext(i.wrapping_mul(0x9E37_79B9) & MessageId::MAX_EXTENDED),
+                _ => std((i * 0x2B5 % 0x800) as u16),
+            };
+        }
+        for len in [1, 2, 5, 8, 20, 33, ids.len()] {
+            let ids = &ids[..len];
+            check::<1>(ids);
+            check::<2>(ids);
+            check::<3>(ids);
+            check::<5>(ids);
+            check::<8>(ids);
+            check::<BXCAN_BANKS>(ids);
+        }
+    }
+}
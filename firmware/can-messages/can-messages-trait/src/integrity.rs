@@ -0,0 +1,185 @@
+//! Checksum and rolling counter of protected messages.
+//!
+//! A message opts in with `#[can(crc)]` and `#[can(counter)]` on `u8` fields.
+//! The checksum is filled in by `try_encode` and checked by `try_decode`.
+//! The counter is up to the sender; receivers check it with [`CounterCheck`].
+
+use crate::CanMessage;
+
+/// CRC-8 SAE J1850: polynomial 0x1D, initial value and final XOR 0xFF.
+fn crc8(crc: u8, data: &[u8]) -> u8 {
+    data.iter().fold(crc, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { crc << 1 ^ 0x1D } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Checksum of a payload of `T`, covering the identifier and every byte
+/// except the checksum itself.
+pub fn checksum<T: CanMessage>(payload: &[u8]) -> u8 {
+    let crc = crc8(0xFF, &T::ID.raw().to_le_bytes());
+    let crc = match T::CRC {
+        Some(at) if at < payload.len() => crc8(crc8(crc, &payload[..at]), &payload[at + 1..]),
+        _ => crc8(crc, payload),
+    };
+    crc ^ 0xFF
+}
+
+/// Check the checksum of a payload of `T`, if it has one.
+pub fn is_intact<T: CanMessage>(payload: &[u8]) -> bool {
+    match T::CRC {
+        Some(at) => payload.get(at) == Some(&checksum::<T>(payload)),
+        None => true,
+    }
+}
+
+/// Receiver side of a rolling counter.
+///
+/// Accepts a message only if its counter is 1 to [`MAX_STEP`](Self::MAX_STEP)
+/// ahead of the previous one, modulo 256, allowing for a few lost frames.
+/// Repeats from a stuck sender or a duplicated frame are rejected, and so are
+/// older counters and jumps. The rejected counter still becomes the reference,
+/// so a sender that restarts its counter after a reboot gets its first
+/// message dropped, and the ones after it through.
+#[derive(Debug, Clone, Default)]
+pub struct CounterCheck {
+    last: Option<u8>,
+}
+
+impl CounterCheck {
+    /// Largest accepted step of the counter.
+    pub const MAX_STEP: u8 = 16;
+
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Record the counter of `msg`, return `true` if it is fresh.
+    ///
+    /// Messages without a counter are always fresh.
+    pub fn accept<T: CanMessage>(&mut self, msg: &T) -> bool {
+        let Some(at) = T::COUNTER else {
+            return true;
+        };
+        let counter = msg.as_bytes()[at];
+        let fresh = self.last.is_none_or(|last| (1..=Self::MAX_STEP).contains(&counter.wrapping_sub(last)));
+        self.last = Some(counter);
+        fresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MessageId, Signal, prelude::*};
+    use super::*;
+
+    #[derive(TryFromBytes, IntoBytes, Immutable, KnownLayout)]
+    #[repr(C)]
+    struct Command {
+        target: u8,
+        counter: u8,
+        crc: u8,
+    }
+
+    impl CanMessage for Command {
+        const ID: MessageId = MessageId::Standard(0x021);
+        const NAME: &'static str = "Command";
+        const SIGNALS: &'static [Signal] = &[];
+        const CRC: Option<usize> = Some(2);
+        const COUNTER: Option<usize> = Some(1);
+    }
+
+    /// Same layout on another identifier.
+    #[derive(TryFromBytes, IntoBytes, Immutable, KnownLayout)]
+    #[repr(C)]
+    struct OtherCommand {
+        target: u8,
+        counter: u8,
+        crc: u8,
+    }
+
+    impl CanMessage for OtherCommand {
+        const ID: MessageId = MessageId::Standard(0x022);
+        const NAME: &'static str = "OtherCommand";
+        const SIGNALS: &'static [Signal] = &[];
+        const CRC: Option<usize> = Some(2);
+    }
+
+    fn command(counter: u8) -> Command {
+        Command { target: 5, counter, crc: 0 }
+    }
+
+    fn sealed(counter: u8) -> [u8; 3] {
+        let mut payload = [5, counter, 0];
+        payload[2] = checksum::<Command>(&payload);
+        payload
+    }
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc8(0xFF, b"123456789") ^ 0xFF, 0x4B);
+    }
+
+    #[test]
+    fn checksum_skips_itself() {
+        let payload = sealed(1);
+        let mut other = payload;
+        other[2] = !other[2];
+        assert_eq!(checksum::<Command>(&other), payload[2]);
+        assert_eq!(payload[2], crc8(crc8(0xFF, &[0x21, 0, 0, 0]), &[5, 1]) ^ 0xFF);
+    }
+
+    #[test]
+    fn intact() {
+        let payload = sealed(1);
+        assert!(is_intact::<Command>(&payload));
+        for bit in 0..16 {
+            let mut corrupt = payload;
+            corrupt[bit / 8] ^= 1 << (bit % 8);
+            assert!(!is_intact::<Command>(&corrupt), "bit {bit} flipped");
+        }
+        assert!(!is_intact::<OtherCommand>(&payload), "wrong identifier");
+        assert!(!is_intact::<Command>(&payload[..2]), "checksum missing");
+    }
+
+    #[test]
+    fn counter_forward() {
+        let mut check = CounterCheck::new();
+        assert!(check.accept(&command(3)), "first message");
+        assert!(check.accept(&command(4)));
+        // Lost frames in between.
+        assert!(check.accept(&command(4 + CounterCheck::MAX_STEP)));
+        assert!(check.accept(&OtherCommand { target: 0, counter: 2, crc: 0 }), "no counter");
+    }
+
+    #[test]
+    fn counter_rejected() {
+        let mut check = CounterCheck::new();
+        assert!(check.accept(&command(3)));
+        assert!(!check.accept(&command(3)), "repeat");
+        assert!(check.accept(&command(4)));
+        assert!(!check.accept(&command(2)), "older");
+        assert!(check.accept(&command(3)));
+        assert!(!check.accept(&command(4 + CounterCheck::MAX_STEP)), "too far ahead");
+        // The rejected counter is the new reference, so the sender syncs up again.
+        assert!(check.accept(&command(5 + CounterCheck::MAX_STEP)));
+    }
+
+    #[test]
+    fn counter_wrap() {
+        let mut check = CounterCheck::new();
+        assert!(check.accept(&command(254)));
+        assert!(check.accept(&command(255)));
+        assert!(check.accept(&command(0)));
+        assert!(!check.accept(&command(0)));
+        assert!(check.accept(&command(1)));
+
+        let mut check = CounterCheck::new();
+        assert!(check.accept(&command(250)));
+        assert!(check.accept(&command(250u8.wrapping_add(CounterCheck::MAX_STEP))));
+        assert!(!check.accept(&command(255)), "older across the wrap");
+    }
+}
@@ -0,0 +1,611 @@
+//! ISO-TP (ISO 15765-2) transport for payloads larger than one CAN frame.
+//!
+//! Classic CAN with normal addressing: one identifier per direction,
+//! frames are always padded to 8 bytes. Payloads are limited to 4095 bytes.
+
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_can::{Frame, Id};
+use heapless::Vec;
+use crate::MessageId;
+
+pub use loopback::{Loopback, LoopbackFrame};
+
+/// Largest payload a single ISO-TP transfer can carry.
+pub const MAX_PAYLOAD: usize = 4095;
+
+const FRAME_LEN: usize = 8;
+const PADDING: u8 = 0xCC;
+
+const SINGLE: u8 = 0x0;
+const FIRST: u8 = 0x1;
+const CONSECUTIVE: u8 = 0x2;
+const FLOW_CONTROL: u8 = 0x3;
+
+const CONTINUE: u8 = 0x0;
+const WAIT: u8 = 0x1;
+const OVERFLOW: u8 = 0x2;
+
+/// Sending half of a CAN interface.
+#[allow(async_fn_in_trait)]
+pub trait FrameTx {
+    type Frame: Frame;
+    type Error;
+
+    async fn send(&mut self, frame: &Self::Frame) -> Result<(), Self::Error>;
+}
+
+/// Receiving half of a CAN interface.
+#[allow(async_fn_in_trait)]
+pub trait FrameRx {
+    type Frame: Frame;
+    type Error;
+
+    async fn recv(&mut self) -> Result<Self::Frame, Self::Error>;
+}
+
+/// Transfer failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<TE, RE> {
+    /// The CAN driver refused to send a frame.
+    Tx(TE),
+    /// The CAN driver failed to receive a frame.
+    Rx(RE),
+    /// The peer did not answer in time.
+    Timeout,
+    /// The payload does not fit into the receiving buffer.
+    Overflow,
+    /// The payload is empty or longer than [`MAX_PAYLOAD`].
+    InvalidLength,
+    /// The peer sent a frame that does not fit the transfer state.
+    Protocol,
+}
+
+/// Transport parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Identifier of the frames we send.
+    pub tx_id: MessageId,
+    /// Identifier of the frames we accept.
+    pub rx_id: MessageId,
+    /// Consecutive frames the peer may send between flow controls, 0 for no limit.
+    pub block_size: u8,
+    /// Minimum gap between consecutive frames we ask for, in ISO-TP encoding.
+    pub st_min: u8,
+    /// How long to wait for the next frame of a transfer (N_Bs, N_Cr).
+    pub timeout: Duration,
+    /// How many flow control WAIT frames to tolerate before giving up.
+    pub max_wait: u8,
+}
+
+impl Config {
+    pub const fn new(tx_id: MessageId, rx_id: MessageId) -> Self {
+        Self {
+            tx_id,
+            rx_id,
+            block_size: 8,
+            st_min: 0,
+            timeout: Duration::from_millis(1000),
+            max_wait: 10,
+        }
+    }
+}
+
+/// Decode the STmin byte of a flow control frame.
+///
+/// Multiplies whole steps instead of converting, which keeps 64-bit division
+/// out of the bootloader. Every step rounds up to a tick, so the gap only
+/// gets longer.
+fn st_min_duration(st_min: u8) -> Duration {
+    match st_min {
+        0x00..=0x7F => Duration::from_millis(1) * st_min as u32,
+        0xF1..=0xF9 => Duration::from_micros(100) * (st_min - 0xF0) as u32,
+        // Reserved values mean the longest gap.
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+/// ISO-TP endpoint on top of a pair of CAN halves.
+pub struct IsoTp<T, R> {
+    tx: T,
+    rx: R,
+    config: Config,
+}
+
+impl<T, R, F> IsoTp<T, R>
+where
+    T: FrameTx<Frame = F>,
+    R: FrameRx<Frame = F>,
+    F: Frame,
+{
+    pub fn new(tx: T, rx: R, config: Config) -> Self {
+        Self { tx, rx, config }
+    }
+
+    /// Give back the CAN halves.
+    pub fn into_inner(self) -> (T, R) {
+        (self.tx, self.rx)
+    }
+
+    async fn send_frame(&mut self, data: &[u8]) -> Result<(), Error<T::Error, R::Error>> {
+        let mut buf = [PADDING; FRAME_LEN];
+        buf[..data.len()].copy_from_slice(data);
+        let id = Id::try_from(self.config.tx_id).map_err(|_| Error::Protocol)?;
+        let frame = F::new(id, &buf).ok_or(Error::Protocol)?;
+        self.tx.send(&frame).await.map_err(Error::Tx)
+    }
+
+    /// Wait for the next frame addressed to us, ignoring everything else.
+    async fn recv_frame(&mut self) -> Result<Vec<u8, FRAME_LEN>, Error<T::Error, R::Error>> {
+        loop {
+            let frame = self.rx.recv().await.map_err(Error::Rx)?;
+            if !frame.is_remote_frame()
+                && MessageId::from(frame.id()) == self.config.rx_id
+                && let Ok(data) = Vec::from_slice(frame.data())
+                && !data.is_empty()
+            {
+                return Ok(data);
+            }
+        }
+    }
+
+    async fn recv_frame_timeout(&mut self) -> Result<Vec<u8, FRAME_LEN>, Error<T::Error, R::Error>> {
+        with_timeout(self.config.timeout, self.recv_frame())
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Wait for a clear-to-send flow control, return block size and STmin.
+    async fn wait_flow_control(&mut self) -> Result<(u8, Duration), Error<T::Error, R::Error>> {
+        let mut waits = 0;
+        loop {
+            let frame = self.recv_frame_timeout().await?;
+            if frame[0] >> 4 != FLOW_CONTROL || frame.len() < 3 {
+                continue;
+            }
+            match frame[0] & 0x0F {
+                CONTINUE => return Ok((frame[1], st_min_duration(frame[2]))),
+                WAIT if waits < self.config.max_wait => waits += 1,
+                WAIT => return Err(Error::Timeout),
+                OVERFLOW => return Err(Error::Overflow),
+                _ => return Err(Error::Protocol),
+            }
+        }
+    }
+
+    /// Send one payload, segmenting it as needed.
+    pub async fn send(&mut self, data: &[u8]) -> Result<(), Error<T::Error, R::Error>> {
+        if data.is_empty() || data.len() > MAX_PAYLOAD {
+            return Err(Error::InvalidLength);
+        }
+
+        if data.len() < FRAME_LEN {
+            let mut frame = [0; FRAME_LEN];
+            frame[0] = SINGLE << 4 | data.len() as u8;
+            frame[1..=data.len()].copy_from_slice(data);
+            return self.send_frame(&frame[..=data.len()]).await;
+        }
+
+        let len = data.len() as u16;
+        let mut frame = [0; FRAME_LEN];
+        frame[0] = FIRST << 4 | (len >> 8) as u8;
+        frame[1] = len as u8;
+        frame[2..].copy_from_slice(&data[..FRAME_LEN - 2]);
+        self.send_frame(&frame).await?;
+
+        let mut seq = 1_u8;
+        let mut chunks = data[FRAME_LEN - 2..].chunks(FRAME_LEN - 1).peekable();
+        while chunks.peek().is_some() {
+            let (block_size, st_min) = self.wait_flow_control().await?;
+            let mut sent = 0_u8;
+            while let Some(chunk) = chunks.next() {
+                frame[0] = CONSECUTIVE << 4 | (seq & 0x0F);
+                frame[1..=chunk.len()].copy_from_slice(chunk);
+                self.send_frame(&frame[..=chunk.len()]).await?;
+                seq = seq.wrapping_add(1);
+                sent = sent.wrapping_add(1);
+                if block_size != 0 && sent == block_size {
+                    break;
+                }
+                if chunks.peek().is_some() {
+                    Timer::after(st_min).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_flow_control(&mut self, status: u8) -> Result<(), Error<T::Error, R::Error>> {
+        let frame = [FLOW_CONTROL << 4 | status, self.config.block_size, self.config.st_min];
+        self.send_frame(&frame).await
+    }
+
+    /// Wait for one payload and reassemble it into `buf`.
+    pub async fn receive<const N: usize>(
+        &mut self,
+        buf: &mut Vec<u8, N>,
+    ) -> Result<(), Error<T::Error, R::Error>> {
+        buf.clear();
+
+        // A new transfer may start at any time, so only the rest of it has a timeout.
+        let frame = loop {
+            let frame = self.recv_frame().await?;
+            if matches!(frame[0] >> 4, SINGLE | FIRST) {
+                break frame;
+            }
+        };
+
+        if frame[0] >> 4 == SINGLE {
+            let len = (frame[0] & 0x0F) as usize;
+            // An empty single frame is invalid, not an empty message.
+            if len == 0 {
+                return Err(Error::Protocol);
+            }
+            let data = frame.get(1..=len).ok_or(Error::Protocol)?;
+            return buf.extend_from_slice(data).map_err(|_| Error::Overflow);
+        }
+
+        if frame.len() < FRAME_LEN {
+            return Err(Error::Protocol);
+        }
+        let len = ((frame[0] & 0x0F) as usize) << 8 | frame[1] as usize;
+        // Anything shorter fits into a single frame, and the first one already carries 6 bytes.
+        if len < FRAME_LEN {
+            return Err(Error::Protocol);
+        }
+        if len > N {
+            self.send_flow_control(OVERFLOW).await?;
+            return Err(Error::Overflow);
+        }
+        buf.extend_from_slice(&frame[2..]).map_err(|_| Error::Overflow)?;
+
+        let mut seq = 1_u8;
+        let mut received = 0_u8;
+        self.send_flow_control(CONTINUE).await?;
+        while buf.len() < len {
+            let frame = self.recv_frame_timeout().await?;
+            if frame[0] >> 4 != CONSECUTIVE {
+                continue;
+            }
+            if frame[0] & 0x0F != seq & 0x0F {
+                return Err(Error::Protocol);
+            }
+            let rest = (len - buf.len()).min(frame.len() - 1);
+            buf.extend_from_slice(&frame[1..=rest]).map_err(|_| Error::Overflow)?;
+            seq = seq.wrapping_add(1);
+            received = received.wrapping_add(1);
+            if self.config.block_size != 0 && received == self.config.block_size && buf.len() < len {
+                received = 0;
+                self.send_flow_control(CONTINUE).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embassy")]
+mod embassy {
+    use core::convert::Infallible;
+    use embassy_stm32::can::enums::BusError;
+    use embassy_stm32::can::{CanRx, CanTx, Frame};
+    use super::{FrameRx, FrameTx};
+
+    impl FrameTx for CanTx<'_> {
+        type Frame = Frame;
+        type Error = Infallible;
+
+        async fn send(&mut self, frame: &Frame) -> Result<(), Infallible> {
+            self.write(frame).await;
+            Ok(())
+        }
+    }
+
+    impl FrameRx for CanRx<'_> {
+        type Frame = Frame;
+        type Error = BusError;
+
+        async fn recv(&mut self) -> Result<Frame, BusError> {
+            self.read().await.map(|envelope| envelope.frame)
+        }
+    }
+}
+
+#[cfg(feature = "socketcan")]
+mod socket {
+    use std::io;
+    use embassy_time::{Duration, Timer};
+    use socketcan::{CanFrame, CanSocket, Socket};
+    use super::{FrameRx, FrameTx};
+
+    /// Retry interval of a busy or empty socket.
+    const POLL: Duration = Duration::from_millis(1);
+
+    /// Host side, the socket has to be in non-blocking mode.
+    impl FrameTx for &CanSocket {
+        type Frame = CanFrame;
+        type Error = io::Error;
+
+        async fn send(&mut self, frame: &CanFrame) -> io::Result<()> {
+            loop {
+                match self.write_frame(frame) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => Timer::after(POLL).await,
+                    result => return result,
+                }
+            }
+        }
+    }
+
+    impl FrameRx for &CanSocket {
+        type Frame = CanFrame;
+        type Error = io::Error;
+
+        async fn recv(&mut self) -> io::Result<CanFrame> {
+            loop {
+                match self.read_frame() {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => Timer::after(POLL).await,
+                    result => return result,
+                }
+            }
+        }
+    }
+}
+
+/// In-memory link, for running endpoints against each other on the host.
+mod loopback {
+    use core::{
+        cell::{Cell, RefCell},
+        convert::Infallible,
+        future::poll_fn,
+        task::{Poll, Waker},
+    };
+    use embedded_can::{Frame, Id};
+    use heapless::Deque;
+    use super::{FRAME_LEN, FrameRx, FrameTx};
+
+    /// Classic CAN frame carried by a [`Loopback`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct LoopbackFrame {
+        id: Id,
+        remote: bool,
+        dlc: usize,
+        data: [u8; FRAME_LEN],
+    }
+
+    impl Frame for LoopbackFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            let mut frame = Self { id: id.into(), remote: false, dlc: data.len(), data: [0; FRAME_LEN] };
+            frame.data.get_mut(..data.len())?.copy_from_slice(data);
+            Some(frame)
+        }
+
+        fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+            (dlc <= FRAME_LEN).then(|| Self { id: id.into(), remote: true, dlc, data: [0; FRAME_LEN] })
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            self.remote
+        }
+
+        fn id(&self) -> Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            self.dlc
+        }
+
+        fn data(&self) -> &[u8] {
+            if self.remote { &[] } else { &self.data[..self.dlc] }
+        }
+    }
+
+    /// One direction of an in-memory CAN link, holding up to `N` frames.
+    ///
+    /// Two of them connect two endpoints: `IsoTp::new(&a, &b, ..)` talks to
+    /// `IsoTp::new(&b, &a, ..)`. Sending waits while the queue is full.
+    pub struct Loopback<F, const N: usize> {
+        frames: RefCell<Deque<F, N>>,
+        sender: Cell<Option<Waker>>,
+        receiver: Cell<Option<Waker>>,
+    }
+
+    impl<F, const N: usize> Loopback<F, N> {
+        pub const fn new() -> Self {
+            Self { frames: RefCell::new(Deque::new()), sender: Cell::new(None), receiver: Cell::new(None) }
+        }
+    }
+
+    impl<F, const N: usize> Default for Loopback<F, N> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    fn wake(waker: &Cell<Option<Waker>>) {
+        if let Some(waker) = waker.take() {
+            waker.wake();
+        }
+    }
+
+    impl<F: Frame + Clone, const N: usize> FrameTx for &Loopback<F, N> {
+        type Frame = F;
+        type Error = Infallible;
+
+        async fn send(&mut self, frame: &F) -> Result<(), Infallible> {
+            poll_fn(|cx| match self.frames.borrow_mut().push_back(frame.clone()) {
+                Ok(()) => {
+                    wake(&self.receiver);
+                    Poll::Ready(Ok(()))
+                }
+                Err(_) => {
+                    self.sender.set(Some(cx.waker().clone()));
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    impl<F: Frame, const N: usize> FrameRx for &Loopback<F, N> {
+        type Frame = F;
+        type Error = Infallible;
+
+        async fn recv(&mut self) -> Result<F, Infallible> {
+            poll_fn(|cx| match self.frames.borrow_mut().pop_front() {
+                Some(frame) => {
+                    wake(&self.sender);
+                    Poll::Ready(Ok(frame))
+                }
+                None => {
+                    self.receiver.set(Some(cx.waker().clone()));
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{convert::Infallible, task::Poll};
+    use embassy_futures::{block_on, join::join, poll_once};
+    use super::*;
+
+    type Link = Loopback<LoopbackFrame, 4>;
+    type Result<T> = core::result::Result<T, Error<Infallible, Infallible>>;
+
+    const SENDER: MessageId = MessageId::Standard(0x700);
+    const RECEIVER: MessageId = MessageId::Standard(0x708);
+
+    fn config(tx_id: MessageId, rx_id: MessageId, block_size: u8) -> Config {
+        Config { block_size, timeout: Duration::from_millis(20), ..Config::new(tx_id, rx_id) }
+    }
+
+    fn frame(id: MessageId, data: &[u8]) -> LoopbackFrame {
+        let mut buf = [PADDING; FRAME_LEN];
+        buf[..data.len()].copy_from_slice(data);
+        LoopbackFrame::new(Id::try_from(id).unwrap(), &buf).unwrap()
+    }
+
+    fn payload(len: usize) -> Vec<u8, MAX_PAYLOAD> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    /// Send `data` across a link, return the result of both ends.
+    fn transfer<const N: usize>(data: &[u8], block_size: u8) -> (Result<()>, Result<Vec<u8, N>>) {
+        let (there, back) = (Link::new(), Link::new());
+        let mut sender = IsoTp::new(&there, &back, config(SENDER, RECEIVER, 0));
+        let mut receiver = IsoTp::new(&back, &there, config(RECEIVER, SENDER, block_size));
+        block_on(join(sender.send(data), async {
+            let mut buf = Vec::new();
+            receiver.receive(&mut buf).await.map(|()| buf)
+        }))
+    }
+
+    /// Feed `frames` to a receiving endpoint, return its result and its answers.
+    fn receive<const N: usize>(frames: &[&[u8]]) -> (Result<Vec<u8, N>>, Vec<LoopbackFrame, 4>) {
+        let (there, back) = (Link::new(), Link::new());
+        for data in frames {
+            block_on((&there).send(&frame(SENDER, data))).unwrap();
+        }
+        let mut receiver = IsoTp::new(&back, &there, config(RECEIVER, SENDER, 8));
+        let mut buf = Vec::new();
+        let result = block_on(receiver.receive(&mut buf)).map(|()| buf);
+        let mut answers = Vec::new();
+        while let Poll::Ready(Ok(frame)) = poll_once((&back).recv()) {
+            answers.push(frame).unwrap();
+        }
+        (result, answers)
+    }
+
+    #[test]
+    fn single_frame() {
+        let (sent, received) = transfer::<16>(&[1, 2, 3], 0);
+        assert_eq!(sent, Ok(()));
+        assert_eq!(received.unwrap(), [1, 2, 3]);
+
+        let (_, received) = transfer::<16>(&payload(7), 0);
+        assert_eq!(received.unwrap(), payload(7));
+    }
+
+    #[test]
+    fn multi_frame() {
+        for len in [8, 13, 14, 100, MAX_PAYLOAD] {
+            let data = payload(len);
+            let (sent, received) = transfer::<MAX_PAYLOAD>(&data, 0);
+            assert_eq!(sent, Ok(()));
+            assert_eq!(received.unwrap(), data, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn block_size() {
+        for block_size in [1, 2, 8] {
+            let data = payload(300);
+            let (sent, received) = transfer::<512>(&data, block_size);
+            assert_eq!(sent, Ok(()));
+            assert_eq!(received.unwrap(), data, "block size {block_size}");
+        }
+    }
+
+    #[test]
+    fn sequence_error() {
+        let first = [0x10, 20, 0, 1, 2, 3, 4, 5];
+        let (result, _) = receive::<64>(&[&first, &[0x21, 6, 7, 8, 9, 10, 11, 12], &[0x23, 13]]);
+        assert_eq!(result, Err(Error::Protocol));
+    }
+
+    #[test]
+    fn overflow() {
+        let (result, answers) = receive::<16>(&[&[0x10, 100, 0, 1, 2, 3, 4, 5]]);
+        assert_eq!(result, Err(Error::Overflow));
+        assert_eq!(answers, [frame(RECEIVER, &[0x32, 8, 0])]);
+
+        let (sent, received) = transfer::<16>(&payload(100), 0);
+        assert_eq!(sent, Err(Error::Overflow));
+        assert_eq!(received, Err(Error::Overflow));
+    }
+
+    #[test]
+    fn empty_single_frame() {
+        let (result, answers) = receive::<64>(&[&[0x00, 1, 2, 3, 4, 5, 6, 7]]);
+        assert_eq!(result, Err(Error::Protocol));
+        assert!(answers.is_empty());
+    }
+
+    #[test]
+    fn short_first_frame() {
+        let (result, answers) = receive::<64>(&[&[0x10, 7, 0, 1, 2, 3, 4, 5]]);
+        assert_eq!(result, Err(Error::Protocol));
+        assert!(answers.is_empty());
+    }
+
+    #[test]
+    fn timeout() {
+        // The rest of the transfer never comes.
+        let (result, answers) = receive::<64>(&[&[0x10, 20, 0, 1, 2, 3, 4, 5]]);
+        assert_eq!(result, Err(Error::Timeout));
+        assert_eq!(answers, [frame(RECEIVER, &[0x30, 8, 0])]);
+
+        // Nobody answers with a flow control.
+        let (there, back) = (Link::new(), Link::new());
+        let mut sender = IsoTp::new(&there, &back, config(SENDER, RECEIVER, 0));
+        assert_eq!(block_on(sender.send(&payload(20))), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn st_min() {
+        for st_min in 0..=0x7F {
+            assert!(st_min_duration(st_min) >= Duration::from_millis(st_min as u64));
+        }
+        for st_min in 0xF1..=0xF9 {
+            assert!(st_min_duration(st_min) >= Duration::from_micros((st_min - 0xF0) as u64 * 100));
+        }
+        assert_eq!(st_min_duration(0x80), Duration::from_millis(0x7F));
+        assert_eq!(st_min_duration(0xFA), Duration::from_millis(0x7F));
+    }
+}
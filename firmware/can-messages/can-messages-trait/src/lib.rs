@@ -1,5 +1,18 @@
 //! Helpers for CAN messages I/O.
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "clock")]
+pub mod clock;
+pub mod dbc;
+pub mod filter;
+pub mod integrity;
+#[cfg(feature = "isotp")]
+pub mod isotp;
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+pub mod supervisor;
 
 pub mod prelude {
     pub use zerocopy::{TryFromBytes, IntoBytes, Immutable, KnownLayout};
@@ -8,14 +21,129 @@ pub mod prelude {
 }
 
 pub use can_messages_derive::*;
+pub use integrity::CounterCheck;
+pub use zerocopy;
+#[cfg(feature = "embedded-can")]
+pub use embedded_can;
+
+use zerocopy::{TryFromBytes, IntoBytes, Immutable, KnownLayout, little_endian as le};
+
+/// CAN bus identifier, either 11-bit standard or 29-bit extended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageId {
+    Standard(u16),
+    Extended(u32),
+}
+
+impl MessageId {
+    /// Largest 11-bit standard identifier.
+    pub const MAX_STANDARD: u16 = 0x7FF;
+    /// Largest 29-bit extended identifier.
+    pub const MAX_EXTENDED: u32 = 0x1FFF_FFFF;
+
+    /// Identifier value without the format.
+    pub const fn raw(self) -> u32 {
+        match self {
+            MessageId::Standard(id) => id as u32,
+            MessageId::Extended(id) => id,
+        }
+    }
+
+    pub const fn is_extended(self) -> bool {
+        matches!(self, MessageId::Extended(_))
+    }
+
+    /// Check that the identifier fits into its format.
+    pub const fn is_valid(self) -> bool {
+        match self {
+            MessageId::Standard(id) => id <= Self::MAX_STANDARD,
+            MessageId::Extended(id) => id <= Self::MAX_EXTENDED,
+        }
+    }
+}
 
-use zerocopy::{TryFromBytes, IntoBytes, Immutable, KnownLayout};
+impl core::fmt::Display for MessageId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MessageId::Standard(id) => write!(f, "{id:03X}"),
+            MessageId::Extended(id) => write!(f, "{id:08X}"),
+        }
+    }
+}
 
 /// Trait for structs that have a CAN bus identifier.
-pub trait CanMessage: TryFromBytes + IntoBytes + Immutable + KnownLayout {
-    const ID: u16;
+pub trait CanMessage: TryFromBytes + IntoBytes + Immutable + KnownLayout + Sized {
+    const ID: MessageId;
+    /// Message name for bus analysis tools.
+    const NAME: &'static str;
+    /// Layout of the message fields on the wire.
+    const SIGNALS: &'static [Signal];
+    /// Byte offset of the `#[can(crc)]` field.
+    const CRC: Option<usize> = None;
+    /// Byte offset of the `#[can(counter)]` field.
+    const COUNTER: Option<usize> = None;
+    /// Everything known about this message.
+    const INFO: MessageInfo = MessageInfo {
+        id: Self::ID,
+        name: Self::NAME,
+        size: core::mem::size_of::<Self>() as u8,
+        signals: Self::SIGNALS,
+    };
+}
+
+/// Static description of a CAN message.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageInfo {
+    pub id: MessageId,
+    pub name: &'static str,
+    /// Payload size in bytes.
+    pub size: u8,
+    pub signals: &'static [Signal],
+}
+
+/// Static description of a single value inside a CAN message.
+///
+/// Physical value is `raw * factor + offset`, all fields are little-endian.
+#[derive(Debug, Clone, Copy)]
+pub struct Signal {
+    pub name: &'static str,
+    pub start_bit: u16,
+    /// Size in bits.
+    pub size: u16,
+    pub signed: bool,
+    pub float: bool,
+    pub factor: f32,
+    pub offset: f32,
+    pub unit: &'static str,
 }
 
+/// Trait for types that can be a field of a CAN message.
+pub trait SignalValue {
+    const SIGNED: bool;
+    const FLOAT: bool = false;
+    const UNIT: &'static str = "";
+    const FACTOR: f32 = 1.0;
+}
+
+macro_rules! signal_value {
+    ($signed:literal, $float:literal: $($t:ty),*) => {
+        $(
+            impl SignalValue for $t {
+                const SIGNED: bool = $signed;
+                const FLOAT: bool = $float;
+            }
+        )*
+    }
+}
+
+signal_value!(false, false: bool, u8, u16, u32, u64, le::U16, le::U32, le::U64);
+signal_value!(true, false: i8, i16, i32, i64, le::I16, le::I32, le::I64);
+signal_value!(true, true: f32, f64, le::F32, le::F64);
+
+/// Marker used by `#[can_message]` to reject two messages sharing one identifier.
+#[doc(hidden)]
+pub trait UniqueId<const ID: u32, const EXTENDED: bool> {}
+
 /// Extension trait for anything that is CAN-parseable.
 pub trait CanParseable {
     fn id_matches<T: CanMessage>(&self) -> bool;
@@ -25,6 +153,8 @@ pub trait CanParseable {
 /// Extension trait for incoming CAN messages.
 pub trait IncomingCan {
     fn try_decode<T: CanMessage>(&self) -> Option<&T>;
+    /// Like [`IncomingCan::try_decode`], but copies the message out of the frame.
+    fn try_read<T: CanMessage>(&self) -> Option<T>;
 }
 
 impl<C> IncomingCan for C
@@ -32,12 +162,20 @@ where
     C: CanParseable,
 {
     fn try_decode<T: CanMessage>(&self) -> Option<&T> {
-        if self.id_matches::<T>() {
+        if self.id_matches::<T>() && integrity::is_intact::<T>(self.as_bytes()) {
             T::try_ref_from_bytes(self.as_bytes()).ok()
         } else {
             None
         }
     }
+
+    fn try_read<T: CanMessage>(&self) -> Option<T> {
+        if self.id_matches::<T>() && integrity::is_intact::<T>(self.as_bytes()) {
+            T::try_read_from_bytes(self.as_bytes()).ok()
+        } else {
+            None
+        }
+    }
 }
 
 /// Extension trait for outfoing CAN messages.
@@ -45,59 +183,132 @@ pub trait OutgoingCan<T> {
     fn try_encode(&self) -> Option<T>;
 }
 
+/// Build an enum that decodes and encodes any of the listed CAN messages.
 #[macro_export]
 macro_rules! can_variant {
-    ($name:ident { $( $n:ident ( $i:path ) ),* $(,)? } ) => {
-        enum $name {
+    ($(#[$attr:meta])* $vis:vis $name:ident { $( $n:ident ( $i:ty ) ),* $(,)? } ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        $vis enum $name {
             $(
                 $n($i)
             ),*
         }
 
-        impl Default for $name
+        #[allow(dead_code)]
+        impl $name {
+            /// All CAN identifiers accepted by this message set.
+            pub const IDS: &'static [$crate::MessageId] = &[
+                $( <$i as $crate::CanMessage>::ID ),*
+            ];
+
+            /// Descriptions of all messages in this set.
+            pub const MESSAGES: &'static [$crate::MessageInfo] = &[
+                $( <$i as $crate::CanMessage>::INFO ),*
+            ];
+
+            /// Decode any known message from an incoming frame.
+            pub fn decode<C: $crate::CanParseable>(frame: &C) -> Option<Self> {
+                use $crate::IncomingCan as _;
+                $(
+                    if let Some(msg) = frame.try_decode::<$i>() {
+                        return Some(Self::$n(msg.clone()));
+                    }
+                )*
+                None
+            }
+
+            /// CAN identifier of the contained message.
+            pub fn id(&self) -> $crate::MessageId {
+                match self {
+                    $(
+                        Self::$n(_) => <$i as $crate::CanMessage>::ID
+                    ),*
+                }
+            }
+        }
+
+        impl<F> $crate::OutgoingCan<F> for $name
         where
             $(
-                $i: Default
+                $i: $crate::OutgoingCan<F>
             ),*
         {
-            fn default() -> Self {
-                unimplemented!()
+            fn try_encode(&self) -> Option<F> {
+                match self {
+                    $(
+                        Self::$n(msg) => msg.try_encode()
+                    ),*
+                }
             }
         }
+
+        $(
+            impl From<$i> for $name {
+                fn from(msg: $i) -> Self {
+                    Self::$n(msg)
+                }
+            }
+        )*
     }
 }
 
-#[cfg(feature = "embassy")]
-mod embassy {
-    use embassy_stm32::can::{Id, StandardId, frame::{Frame, Envelope}};
-    use crate::prelude::*;
+/// Any `embedded-can` frame, including the embassy-stm32 and SocketCAN ones.
+#[cfg(feature = "embedded-can")]
+mod embedded {
+    use embedded_can::{ExtendedId, Frame, Id, StandardId};
+    use crate::{MessageId, integrity, prelude::*};
 
-    impl CanParseable for Frame {
-        fn id_matches<T: CanMessage>(&self) -> bool {
-            StandardId::new(T::ID)
-                .map(|id| *self.id() == Id::Standard(id))
-                .unwrap_or(false)
+    impl TryFrom<MessageId> for Id {
+        type Error = ();
+
+        fn try_from(id: MessageId) -> Result<Id, ()> {
+            match id {
+                MessageId::Standard(id) => StandardId::new(id).map(Id::Standard),
+                MessageId::Extended(id) => ExtendedId::new(id).map(Id::Extended),
+            }
+            .ok_or(())
         }
-        fn as_bytes(&self) -> &[u8] {
-            self.data()
+    }
+
+    impl From<Id> for MessageId {
+        fn from(id: Id) -> MessageId {
+            match id {
+                Id::Standard(id) => MessageId::Standard(id.as_raw()),
+                Id::Extended(id) => MessageId::Extended(id.as_raw()),
+            }
         }
     }
 
-    impl CanParseable for Envelope {
+    impl<F> CanParseable for F
+    where
+        F: Frame,
+    {
         fn id_matches<T: CanMessage>(&self) -> bool {
-            self.frame.id_matches::<T>()
+            !self.is_remote_frame() && MessageId::from(self.id()) == T::ID
         }
         fn as_bytes(&self) -> &[u8] {
-            self.frame.as_bytes()
+            self.data()
         }
     }
 
-    impl<T> OutgoingCan<Frame> for T
+    impl<T, F> OutgoingCan<F> for T
     where
         T: CanMessage,
+        F: Frame,
     {
-        fn try_encode(&self) -> Option<Frame> {
-            Frame::new_standard(Self::ID.into(), self.as_bytes()).ok()
+        fn try_encode(&self) -> Option<F> {
+            let id = Id::try_from(Self::ID).ok()?;
+            match Self::CRC {
+                Some(at) => {
+                    let mut payload = [0; 8];
+                    let payload = payload.get_mut(..size_of::<Self>())?;
+                    payload.copy_from_slice(IntoBytes::as_bytes(self));
+                    payload[at] = integrity::checksum::<Self>(payload);
+                    F::new(id, payload)
+                }
+                None => F::new(id, IntoBytes::as_bytes(self)),
+            }
         }
     }
 }
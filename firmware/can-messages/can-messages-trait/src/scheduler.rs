@@ -0,0 +1,419 @@
+//! Periodic and on-change transmission of telemetry frames.
+//!
+//! Each entry owns a function that builds its frame. A frame that is still
+//! waiting for the bus when the next one is due gets aborted, so the bus
+//! always carries the freshest value, and counts as dropped.
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_can::Frame;
+use heapless::Vec;
+
+/// How soon to try again when all mailboxes are busy.
+const RETRY: Duration = Duration::from_millis(1);
+
+/// Frame accepted by [`TxMailboxes::try_write`].
+#[derive(Debug, Clone, Copy)]
+pub struct Queued<M> {
+    pub mailbox: M,
+    /// A pending lower priority frame was pushed out to make room.
+    pub displaced: bool,
+}
+
+/// Transmit side of a CAN controller with a few hardware mailboxes.
+#[allow(async_fn_in_trait)]
+pub trait TxMailboxes {
+    type Frame: Frame;
+    type Mailbox: Copy + Eq;
+
+    /// Queue a frame if there is room for it.
+    fn try_write(&mut self, frame: &Self::Frame) -> Option<Queued<Self::Mailbox>>;
+    /// Wait for room and queue a frame.
+    async fn write(&mut self, frame: &Self::Frame) -> Queued<Self::Mailbox>;
+    /// Cancel a queued frame, `true` if it had not been sent yet.
+    fn abort(&mut self, mailbox: Self::Mailbox) -> bool;
+}
+
+/// When an entry produces a frame.
+#[derive(Debug, Clone, Copy)]
+pub enum Trigger {
+    /// Every `period`.
+    Periodic(Duration),
+    /// Every `check` if the frame changed, and at least every `refresh`.
+    OnChange { check: Duration, refresh: Duration },
+}
+
+struct Entry<F, M> {
+    trigger: Trigger,
+    build: fn() -> Option<F>,
+    due: Instant,
+    refresh_due: Instant,
+    last: Option<F>,
+    pending: Option<F>,
+    mailbox: Option<M>,
+    dropped: u32,
+}
+
+fn same_frame(a: &impl Frame, b: &impl Frame) -> bool {
+    a.id() == b.id() && a.is_remote_frame() == b.is_remote_frame() && a.data() == b.data()
+}
+
+/// Sends up to `N` frames on their own schedules over one transmitter.
+pub struct Scheduler<T: TxMailboxes, const N: usize> {
+    tx: T,
+    entries: Vec<Entry<T::Frame, T::Mailbox>, N>,
+}
+
+impl<T: TxMailboxes, const N: usize> Scheduler<T, N> {
+    pub fn new(tx: T) -> Self {
+        Self { tx, entries: Vec::new() }
+    }
+
+    /// Register a frame, return its index for [`Scheduler::dropped_of`].
+    ///
+    /// `build` returns `None` to skip a turn. Panics if all `N` slots are taken.
+    pub fn add(&mut self, trigger: Trigger, build: fn() -> Option<T::Frame>) -> usize {
+        let now = Instant::now();
+        let entry = Entry {
+            trigger,
+            build,
+            due: now,
+            refresh_due: now,
+            last: None,
+            pending: None,
+            mailbox: None,
+            dropped: 0,
+        };
+        if self.entries.push(entry).is_err() {
+            panic!("scheduler is full");
+        }
+        self.entries.len() - 1
+    }
+
+    /// Frames of one entry that never made it to the bus.
+    pub fn dropped_of(&self, index: usize) -> u32 {
+        self.entries.get(index).map_or(0, |e| e.dropped)
+    }
+
+    /// Frames of all entries that never made it to the bus.
+    pub fn dropped(&self) -> u32 {
+        self.entries.iter().map(|e| e.dropped).sum()
+    }
+
+    /// Send a one-off frame, waiting for a free mailbox.
+    pub async fn send(&mut self, frame: &T::Frame) {
+        let queued = self.tx.write(frame).await;
+        self.claim(queued);
+    }
+
+    /// Wait for the next due entry and send whatever is due.
+    ///
+    /// Safe to cancel, e.g. when used in `select`.
+    pub async fn tick(&mut self) {
+        match self.next_deadline() {
+            Some(deadline) => Timer::at(deadline).await,
+            None => core::future::pending().await,
+        }
+        self.service(Instant::now());
+    }
+
+    /// Run the schedule forever.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.tick().await;
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let retry = Instant::now() + RETRY;
+        self.entries
+            .iter()
+            .map(|e| if e.pending.is_some() { e.due.min(retry) } else { e.due })
+            .min()
+    }
+
+    /// A mailbox now holds a new frame, so it no longer belongs to any entry.
+    fn claim(&mut self, queued: Queued<T::Mailbox>) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.mailbox == Some(queued.mailbox)) {
+            entry.mailbox = None;
+            if queued.displaced {
+                entry.dropped += 1;
+            }
+        }
+    }
+
+    fn service(&mut self, now: Instant) {
+        for entry in self.entries.iter_mut().filter(|e| e.due <= now) {
+            let frame = match entry.trigger {
+                Trigger::Periodic(period) => {
+                    entry.due += period;
+                    (entry.build)()
+                }
+                Trigger::OnChange { check, .. } => {
+                    entry.due += check;
+                    (entry.build)().filter(|frame| {
+                        let changed = !entry.last.as_ref().is_some_and(|last| same_frame(last, frame));
+                        changed || entry.refresh_due <= now
+                    })
+                }
+            };
+            // Do not try to catch up after a long stall.
+            if entry.due < now {
+                entry.due = now;
+            }
+            if let Some(frame) = frame {
+                if let Trigger::OnChange { refresh, .. } = entry.trigger {
+                    entry.refresh_due = now + refresh;
+                }
+                if entry.pending.replace(frame).is_some() {
+                    entry.dropped += 1;
+                }
+                if let Some(mailbox) = entry.mailbox.take()
+                    && self.tx.abort(mailbox)
+                {
+                    entry.dropped += 1;
+                }
+            }
+        }
+
+        for index in 0..self.entries.len() {
+            let Some(frame) = self.entries[index].pending.take() else {
+                continue;
+            };
+            match self.tx.try_write(&frame) {
+                Some(queued) => {
+                    self.claim(queued);
+                    let entry = &mut self.entries[index];
+                    entry.mailbox = Some(queued.mailbox);
+                    entry.last = Some(frame);
+                }
+                // Keep it for a retry, unless superseded by then.
+                None => self.entries[index].pending = Some(frame),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "embassy")]
+mod embassy {
+    use embassy_stm32::can::{CanTx, Frame, Mailbox};
+    use super::{Queued, TxMailboxes};
+
+    impl TxMailboxes for CanTx<'_> {
+        type Frame = Frame;
+        type Mailbox = Mailbox;
+
+        fn try_write(&mut self, frame: &Frame) -> Option<Queued<Mailbox>> {
+            let status = CanTx::try_write(self, frame).ok()?;
+            Some(Queued {
+                mailbox: status.mailbox(),
+                displaced: status.dequeued_frame().is_some(),
+            })
+        }
+
+        async fn write(&mut self, frame: &Frame) -> Queued<Mailbox> {
+            let status = CanTx::write(self, frame).await;
+            Queued {
+                mailbox: status.mailbox(),
+                displaced: status.dequeued_frame().is_some(),
+            }
+        }
+
+        fn abort(&mut self, mailbox: Mailbox) -> bool {
+            CanTx::abort(self, mailbox)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU8, Ordering};
+    use embassy_futures::block_on;
+    use embedded_can::{Id, StandardId};
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct TestFrame {
+        id: Id,
+        data: u8,
+    }
+
+    impl Frame for TestFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            let &[data] = data else { return None };
+            Some(Self { id: id.into(), data })
+        }
+
+        fn new_remote(_: impl Into<Id>, _: usize) -> Option<Self> {
+            None
+        }
+
+        fn is_extended(&self) -> bool {
+            false
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            false
+        }
+
+        fn id(&self) -> Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            1
+        }
+
+        fn data(&self) -> &[u8] {
+            core::slice::from_ref(&self.data)
+        }
+    }
+
+    fn frame(id: u16, data: u8) -> TestFrame {
+        TestFrame { id: StandardId::new(id).unwrap().into(), data }
+    }
+
+    /// Two mailboxes that only empty when the test says the bus sent them.
+    #[derive(Default)]
+    struct Mock {
+        mailboxes: [Option<TestFrame>; 2],
+        /// Push the frame out of mailbox 0 when both are full, like a
+        /// controller making room for a higher priority frame.
+        displace: bool,
+        written: Vec<u8, 16>,
+    }
+
+    impl Mock {
+        fn transmit(&mut self) {
+            self.mailboxes = Default::default();
+        }
+
+        fn busy(&mut self) {
+            self.mailboxes = [Some(frame(0x7FF, 0xFF)), Some(frame(0x7FF, 0xFF))];
+        }
+    }
+
+    impl TxMailboxes for Mock {
+        type Frame = TestFrame;
+        type Mailbox = usize;
+
+        fn try_write(&mut self, frame: &TestFrame) -> Option<Queued<usize>> {
+            let (mailbox, displaced) = match self.mailboxes.iter().position(Option::is_none) {
+                Some(mailbox) => (mailbox, false),
+                None if self.displace => (0, true),
+                None => return None,
+            };
+            self.mailboxes[mailbox] = Some(frame.clone());
+            self.written.push(frame.data).unwrap();
+            Some(Queued { mailbox, displaced })
+        }
+
+        async fn write(&mut self, frame: &TestFrame) -> Queued<usize> {
+            self.try_write(frame).expect("no room in the mock")
+        }
+
+        fn abort(&mut self, mailbox: usize) -> bool {
+            self.mailboxes[mailbox].take().is_some()
+        }
+    }
+
+    /// Scheduler with one entry, and the time it is first due.
+    fn scheduler(trigger: Trigger, build: fn() -> Option<TestFrame>) -> (Scheduler<Mock, 2>, Instant) {
+        let mut scheduler = Scheduler::new(Mock::default());
+        let index = scheduler.add(trigger, build);
+        let start = scheduler.entries[index].due;
+        (scheduler, start)
+    }
+
+    #[test]
+    fn superseded() {
+        let (mut scheduler, start) = scheduler(Trigger::Periodic(PERIOD), || Some(frame(1, 1)));
+        scheduler.tx.busy();
+        scheduler.service(start);
+        assert_eq!(scheduler.dropped(), 0);
+        scheduler.service(start + PERIOD);
+        assert_eq!(scheduler.dropped_of(0), 1);
+
+        // The retry gets through once there is room.
+        scheduler.tx.transmit();
+        scheduler.service(start + PERIOD);
+        assert_eq!(scheduler.tx.written, [1]);
+        assert_eq!(scheduler.dropped(), 1);
+    }
+
+    #[test]
+    fn aborted() {
+        let (mut scheduler, start) = scheduler(Trigger::Periodic(PERIOD), || Some(frame(1, 1)));
+        scheduler.service(start);
+        scheduler.tx.transmit();
+        scheduler.service(start + PERIOD);
+        assert_eq!(scheduler.dropped(), 0);
+
+        // Still in its mailbox when the next one is due.
+        scheduler.service(start + PERIOD * 2);
+        assert_eq!(scheduler.dropped(), 1);
+        assert_eq!(scheduler.tx.written, [1, 1, 1]);
+        assert_eq!(scheduler.tx.mailboxes, [Some(frame(1, 1)), None]);
+    }
+
+    #[test]
+    fn displaced() {
+        let (mut scheduler, start) = scheduler(Trigger::Periodic(PERIOD), || Some(frame(1, 1)));
+        scheduler.service(start);
+        assert_eq!(scheduler.entries[0].mailbox, Some(0));
+        scheduler.tx.mailboxes[1] = Some(frame(0x7FF, 0xFF));
+
+        scheduler.tx.displace = true;
+        block_on(scheduler.send(&frame(2, 2)));
+        assert_eq!(scheduler.dropped_of(0), 1);
+        assert_eq!(scheduler.entries[0].mailbox, None);
+
+        // The mailbox now holds the one-off frame, which must not be aborted.
+        scheduler.tx.displace = false;
+        scheduler.service(start + PERIOD);
+        assert_eq!(scheduler.tx.mailboxes[0], Some(frame(2, 2)));
+        assert_eq!(scheduler.dropped_of(0), 1);
+    }
+
+    #[test]
+    fn on_change() {
+        static VALUE: AtomicU8 = AtomicU8::new(1);
+        let trigger = Trigger::OnChange { check: PERIOD, refresh: PERIOD * 10 };
+        let (mut scheduler, start) = scheduler(trigger, || Some(frame(1, VALUE.load(Ordering::Relaxed))));
+
+        scheduler.service(start);
+        scheduler.tx.transmit();
+        scheduler.service(start + PERIOD);
+        assert_eq!(scheduler.tx.written, [1]);
+
+        VALUE.store(2, Ordering::Relaxed);
+        scheduler.service(start + PERIOD * 2);
+        scheduler.tx.transmit();
+        assert_eq!(scheduler.tx.written, [1, 2]);
+
+        for tick in 3..12 {
+            scheduler.service(start + PERIOD * tick);
+        }
+        assert_eq!(scheduler.tx.written, [1, 2]);
+        scheduler.service(start + PERIOD * 12);
+        assert_eq!(scheduler.tx.written, [1, 2, 2]);
+        assert_eq!(scheduler.dropped(), 0);
+    }
+
+    #[test]
+    fn stall() {
+        let (mut scheduler, start) = scheduler(Trigger::Periodic(PERIOD), || Some(frame(1, 1)));
+        scheduler.service(start);
+        let late = start + Duration::from_secs(1);
+        scheduler.service(late);
+        assert_eq!(scheduler.entries[0].due, late);
+
+        // One frame for the stall, then back on the period.
+        scheduler.service(late);
+        scheduler.service(late + PERIOD / 2);
+        assert_eq!(scheduler.tx.written.len(), 3);
+        scheduler.service(late + PERIOD);
+        assert_eq!(scheduler.tx.written.len(), 4);
+    }
+}
@@ -0,0 +1,199 @@
+//! Error state of the CAN controller and lost frame statistics.
+//!
+//! The [`Supervisor`] polls the controller error register, the CAN tasks add
+//! what they notice themselves, and everything ends up in [`CanStats`] for
+//! the node to publish.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes};
+use crate::SignalValue;
+
+/// How often `supervise` checks the controller error state.
+pub const SUPERVISOR_PERIOD_MS: u64 = 100;
+/// Pause of a receive loop after an error. The error state stays reported
+/// until it clears, retrying right away would spin on it.
+pub const RX_ERROR_BACKOFF_MS: u64 = 1;
+
+/// Fault confinement state of a CAN controller.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusState {
+    #[default]
+    ErrorActive = 0,
+    /// An error counter reached 96.
+    ErrorWarning = 1,
+    /// An error counter reached 128, the node no longer sends error flags.
+    ErrorPassive = 2,
+    /// Transmit errors reached 256, the node is off the bus until it recovers.
+    BusOff = 3,
+}
+
+impl SignalValue for BusState {
+    const SIGNED: bool = false;
+}
+
+/// Error state with the transmit and receive error counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BusStatus {
+    pub state: BusState,
+    pub tec: u8,
+    pub rec: u8,
+}
+
+/// Events since startup, saturating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counters {
+    /// Times the controller went bus-off.
+    pub bus_off: u16,
+    /// Error frames seen, sampled: a burst between two polls counts once.
+    pub bus_errors: u16,
+    /// Received frames lost to a full receive FIFO.
+    pub rx_lost: u16,
+    /// Frames aborted or pushed out before they made it to the bus.
+    pub tx_dropped: u16,
+}
+
+#[cfg(feature = "supervisor")]
+pub use stats::*;
+
+#[cfg(feature = "supervisor")]
+mod stats {
+    use core::cell::Cell;
+    use embassy_sync::blocking_mutex::{Mutex, raw::RawMutex};
+    use super::{BusStatus, Counters};
+
+    /// Bus status and counters, shared between the CAN tasks.
+    pub struct CanStats<M: RawMutex> {
+        inner: Mutex<M, Cell<(BusStatus, Counters)>>,
+    }
+
+    impl<M: RawMutex> CanStats<M> {
+        pub const fn new() -> Self {
+            Self {
+                inner: Mutex::new(Cell::new((
+                    BusStatus { state: super::BusState::ErrorActive, tec: 0, rec: 0 },
+                    Counters { bus_off: 0, bus_errors: 0, rx_lost: 0, tx_dropped: 0 },
+                ))),
+            }
+        }
+
+        pub fn status(&self) -> BusStatus {
+            self.inner.lock(|inner| inner.get().0)
+        }
+
+        pub fn counters(&self) -> Counters {
+            self.inner.lock(|inner| inner.get().1)
+        }
+
+        pub(crate) fn update(&self, f: impl FnOnce(&mut BusStatus, &mut Counters)) {
+            self.inner.lock(|inner| {
+                let (mut status, mut counters) = inner.get();
+                f(&mut status, &mut counters);
+                inner.set((status, counters));
+            });
+        }
+
+        /// Count frames that never made it to the bus.
+        pub fn record_tx_dropped(&self, frames: u32) {
+            let frames = frames.try_into().unwrap_or(u16::MAX);
+            self.update(|_, counters| counters.tx_dropped = counters.tx_dropped.saturating_add(frames));
+        }
+    }
+
+    impl<M: RawMutex> Default for CanStats<M> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+#[cfg(all(feature = "supervisor", feature = "embassy"))]
+pub use embassy::{Supervisor, supervise};
+
+#[cfg(all(feature = "supervisor", feature = "embassy"))]
+mod embassy {
+    use embassy_stm32::pac::{self, can::vals::Lec};
+    use embassy_sync::blocking_mutex::raw::RawMutex;
+    use embassy_time::{Duration, Ticker};
+    use super::{BusState, BusStatus, CanStats};
+
+    /// Watches the error state of the CAN controller.
+    pub struct Supervisor<'a, M: RawMutex> {
+        stats: &'a CanStats<M>,
+        ticker: Ticker,
+    }
+
+    impl<'a, M: RawMutex> Supervisor<'a, M> {
+        /// Poll every `period` and enable automatic bus-off recovery, which
+        /// brings the node back after 128 × 11 recessive bits on the bus.
+        pub fn new(stats: &'a CanStats<M>, period: Duration) -> Self {
+            pac::CAN.mcr().modify(|w| w.set_abom(true));
+            Self { stats, ticker: Ticker::every(period) }
+        }
+
+        /// Poll the controller once and update the statistics.
+        pub fn poll(&mut self) -> BusStatus {
+            let can = pac::CAN;
+            let esr = can.esr().read();
+            let status = BusStatus {
+                state: if esr.boff() {
+                    BusState::BusOff
+                } else if esr.epvf() {
+                    BusState::ErrorPassive
+                } else if esr.ewgf() {
+                    BusState::ErrorWarning
+                } else {
+                    BusState::ErrorActive
+                },
+                tec: esr.tec(),
+                rec: esr.rec(),
+            };
+            // Consume the last error code, so the next poll only sees new errors.
+            let bus_error = esr.lec() != Lec::NO_ERROR;
+            if bus_error {
+                can.esr().modify(|w| w.set_lec(Lec::NO_ERROR));
+            }
+            let mut rx_lost = 0;
+            for fifo in 0..2 {
+                if can.rfr(fifo).read().fovr() {
+                    can.rfr(fifo).write(|w| w.set_fovr(true));
+                    rx_lost += 1;
+                }
+            }
+
+            self.stats.update(|last, counters| {
+                if status.state == BusState::BusOff && last.state != BusState::BusOff {
+                    counters.bus_off = counters.bus_off.saturating_add(1);
+                }
+                counters.bus_errors = counters.bus_errors.saturating_add(bus_error as u16);
+                counters.rx_lost = counters.rx_lost.saturating_add(rx_lost);
+                *last = status;
+            });
+            status
+        }
+
+        /// Wait for the next change of the bus state, return the old and new status.
+        pub async fn changed(&mut self) -> (BusStatus, BusStatus) {
+            loop {
+                let last = self.stats.status();
+                self.ticker.next().await;
+                let status = self.poll();
+                if status.state != last.state {
+                    return (last, status);
+                }
+            }
+        }
+    }
+
+    /// Watch the controller for good, logging every change of the bus state.
+    pub async fn supervise<M: RawMutex>(stats: &CanStats<M>, period: Duration) {
+        let mut supervisor = Supervisor::new(stats, period);
+        loop {
+            let (old, new) = supervisor.changed().await;
+            #[cfg(feature = "defmt")]
+            defmt::info!("CAN bus {} -> {}, TEC {} REC {}", old.state, new.state, new.tec, new.rec);
+            #[cfg(not(feature = "defmt"))]
+            let _ = (old, new);
+        }
+    }
+}
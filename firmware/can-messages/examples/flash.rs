@@ -0,0 +1,153 @@
+//! Update the firmware of a node over a SocketCAN interface.
+//!
+//! Usage:
+//!
+//! - `cargo run --example flash --features socketcan,isotp -- <iface> <node> <firmware.elf>`
+//!   sends an application built with the `bootloader` feature to `node`.
+//! - `cargo run --example flash --features socketcan,isotp -- <iface> <node> --simulate`
+//!   acts as the bootloader of `node` with flash in memory, e.g. on `vcan0`.
+//!
+//! `node` is one of `power-supply`, `coolbox`, the nodes the bootloader builds for.
+
+use can_messages::{
+    boot::{
+        self,
+        host::{update, SimulatedFlash, Step},
+        Request, Response, Status, Updater, MAX_REQUEST, MAX_RESPONSE,
+    },
+    isotp::{FrameRx, FrameTx, IsoTp},
+    prelude::*,
+    EnterBootloader, NodeId,
+};
+use can_messages_trait::embedded_can::Frame;
+use embassy_executor::Spawner;
+use socketcan::{CanFrame, CanSocket, Socket};
+use std::{io, time::SystemTime};
+
+fn invalid(what: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, what.into())
+}
+
+/// Flash image of the loadable segments of an ELF file, starting at [`boot::APP_START`].
+fn load_elf(elf: &[u8]) -> io::Result<Vec<u8>> {
+    const PT_LOAD: u32 = 1;
+    if elf.get(..6) != Some(&[0x7F, b'E', b'L', b'F', 1, 1]) {
+        return Err(invalid("not a 32-bit little-endian ELF file"));
+    }
+    let field = |at: usize, len: usize| -> io::Result<u32> {
+        let bytes = elf.get(at..at + len).ok_or_else(|| invalid("truncated ELF file"))?;
+        Ok(bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32))
+    };
+    let (ph_offset, ph_size, ph_count) = (field(0x1C, 4)?, field(0x2A, 2)?, field(0x2C, 2)?);
+
+    let mut image = Vec::new();
+    for header in (0..ph_count).map(|i| (ph_offset + i * ph_size) as usize) {
+        let (kind, offset, address, size) = (field(header, 4)?, field(header + 4, 4)?, field(header + 12, 4)?, field(header + 16, 4)?);
+        if kind != PT_LOAD || size == 0 {
+            continue;
+        }
+        if address < boot::APP_START || address + size > boot::APP_START + boot::APP_CAPACITY {
+            return Err(invalid(format!(
+                "segment at {address:#010x} is outside of the application area, build with the `bootloader` feature"
+            )));
+        }
+        let data = elf.get(offset as usize..(offset + size) as usize).ok_or_else(|| invalid("truncated ELF file"))?;
+        let start = (address - boot::APP_START) as usize;
+        if image.len() < start + data.len() {
+            image.resize(start + data.len(), 0xFF);
+        }
+        image[start..][..data.len()].copy_from_slice(data);
+    }
+    if image.is_empty() {
+        return Err(invalid("no loadable segments"));
+    }
+    // Flash is programmed in half-words.
+    image.resize(image.len().next_multiple_of(2), 0xFF);
+    Ok(image)
+}
+
+/// Answer requests like the bootloader of a node, forever.
+async fn simulate<T, R, F>(isotp: &mut IsoTp<T, R>) -> io::Result<()>
+where
+    T: FrameTx<Frame = F, Error = io::Error>,
+    R: FrameRx<Frame = F, Error = io::Error>,
+    F: Frame,
+{
+    let mut updater = Updater::new(SimulatedFlash::new());
+    let mut request = heapless::Vec::<u8, MAX_REQUEST>::new();
+    loop {
+        if let Err(err) = isotp.receive(&mut request).await {
+            eprintln!("Receive failed: {err:?}");
+            continue;
+        }
+        let response = updater.handle(&request);
+        match Request::parse(&request) {
+            Some(Request::Write { .. }) if response == Response::Status(Status::Ok) => {}
+            Some(Request::Write { offset, data }) => println!("Write {} bytes at {offset:#x}: {response:?}", data.len()),
+            parsed => println!("{parsed:?}: {response:?}"),
+        }
+        let mut buf = [0; MAX_RESPONSE];
+        isotp.send(response.encode(&mut buf)).await.map_err(|err| io::Error::other(format!("Send failed: {err:?}")))?;
+        if updater.boot_requested() {
+            let image = updater.image().ok().flatten();
+            println!("Node restarts with {image:?} and waits for the next update");
+            updater = Updater::new(updater.into_inner());
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    // The executor runs forever, leave once done.
+    let code = match run().await {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+async fn run() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [iface, node, action] = args.as_slice() else {
+        return Err(invalid("usage: flash <iface> <node> <firmware.elf>|--simulate"));
+    };
+    let node = match node.as_str() {
+        "power-supply" => NodeId::POWER_SUPPLY,
+        "coolbox" => NodeId::COOLBOX,
+        _ => return Err(invalid("unknown node")),
+    };
+
+    let socket = CanSocket::open(iface)?;
+    socket.set_nonblocking(true)?;
+    if action == "--simulate" {
+        println!("Bootloader of {node:?} waiting on {iface}");
+        let mut isotp = IsoTp::new(&socket, &socket, boot::node_config(node));
+        return simulate(&mut isotp).await;
+    }
+
+    let image = load_elf(&std::fs::read(action)?)?;
+    // A running application restarts into its bootloader, a waiting bootloader ignores this.
+    let counter = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |time| time.as_secs() as u8);
+    let frame: CanFrame = EnterBootloader::new(node, counter)
+        .try_encode()
+        .ok_or_else(|| invalid("cannot encode request"))?;
+    socket.write_frame(&frame)?;
+
+    let mut isotp = IsoTp::new(&socket, &socket, boot::host_config(node));
+    update(&mut isotp, &image, |step| match step {
+        Step::Unchanged => println!("Image already in place"),
+        Step::Erasing => println!("Erasing"),
+        Step::Writing { written, len } => {
+            print!("\rWriting {:3} %", written * 100 / len);
+            let _ = io::Write::flush(&mut io::stdout());
+            if written == len {
+                println!();
+            }
+        }
+        Step::Started(record) => println!("Started image of {} bytes, CRC {:08x}", record.len, record.crc),
+    })
+    .await
+}
@@ -0,0 +1,51 @@
+//! Ask the nodes on a SocketCAN interface for their firmware and device IDs.
+//!
+//! Usage: `cargo run --example identify --features socketcan -- [vcan0]`
+
+use can_messages::{prelude::*, DeviceUid, FirmwareId, IdentifyRequest, NodeId};
+use socketcan::{CanFrame, CanSocket, Socket};
+use std::{collections::BTreeMap, io, time::{Duration, Instant}};
+
+/// How long to collect answers.
+const LISTEN: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+struct Node {
+    firmware: Option<FirmwareId>,
+    uid: [u8; 12],
+}
+
+fn main() -> io::Result<()> {
+    let iface = std::env::args().nth(1).unwrap_or_else(|| "vcan0".into());
+    let socket = CanSocket::open(&iface)?;
+    socket.set_read_timeout(LISTEN)?;
+    let frame: CanFrame = IdentifyRequest::new(NodeId::SYSTEM)
+        .try_encode()
+        .ok_or_else(|| io::Error::other("cannot encode request"))?;
+    socket.write_frame(&frame)?;
+
+    let mut nodes = BTreeMap::<u8, Node>::new();
+    let start = Instant::now();
+    while start.elapsed() < LISTEN {
+        let frame = match socket.read_frame() {
+            Ok(frame) => frame,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        if let Some(firmware) = FirmwareId::decode(&frame) {
+            nodes.entry(firmware.node).or_default().firmware = Some(firmware.clone());
+        } else if let Some(uid) = DeviceUid::decode(&frame) {
+            uid.merge_into(&mut nodes.entry(uid.node).or_default().uid);
+        }
+    }
+
+    for (node, Node { firmware, uid }) in nodes {
+        let name = NodeId::from_raw(node).map_or_else(|| format!("{node:02X}"), |id| format!("{id:?}"));
+        let uid: String = uid.iter().rev().map(|byte| format!("{byte:02X}")).collect();
+        match firmware {
+            Some(firmware) => println!("{name}: {firmware}, UID {uid}"),
+            None => println!("{name}: UID {uid}"),
+        }
+    }
+    Ok(())
+}
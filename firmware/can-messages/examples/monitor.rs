@@ -0,0 +1,55 @@
+//! Print trailer bus messages received on a SocketCAN interface.
+//!
+//! Usage: `cargo run --example monitor --features socketcan -- [vcan0]`
+
+use can_messages::{
+    AnyMessage, CanDiagnostics, DeviceUid, FirmwareId, Heartbeat, Liveness, LivenessTracker, NodeMessage, HEARTBEAT_PERIOD_MS,
+};
+use socketcan::{CanSocket, Socket};
+use std::{io::ErrorKind, time::{Duration, Instant}};
+
+fn main() -> std::io::Result<()> {
+    let iface = std::env::args().nth(1).unwrap_or_else(|| "vcan0".into());
+    let socket = CanSocket::open(&iface)?;
+    socket.set_read_timeout(Duration::from_millis(HEARTBEAT_PERIOD_MS))?;
+    println!("Listening on {iface}, accepting IDs {:?}", AnyMessage::IDS);
+
+    let start = Instant::now();
+    let mut liveness = LivenessTracker::<16>::new(3 * HEARTBEAT_PERIOD_MS);
+    let mut lost = Vec::new();
+    loop {
+        let now_ms = || start.elapsed().as_millis() as u64;
+        match socket.read_frame() {
+            Ok(frame) => {
+                if let Some(heartbeat) = Heartbeat::decode(&frame) {
+                    if liveness.update(heartbeat, now_ms()) {
+                        println!("node {:02X} up: {heartbeat:?}", heartbeat.node);
+                    }
+                } else if let Some(diag) = CanDiagnostics::decode(&frame) {
+                    println!("node {:02X} CAN status: {diag:?}", diag.node);
+                } else if let Some(firmware) = FirmwareId::decode(&frame) {
+                    println!("node {:02X} firmware: {firmware}", firmware.node);
+                } else if let Some(uid) = DeviceUid::decode(&frame) {
+                    println!("node {:02X} UID part {}: {:02X?}", uid.node, uid.part, uid.uid);
+                } else {
+                    match AnyMessage::decode(&frame) {
+                        Some(msg) => println!("{} {msg:?}", msg.id()),
+                        None => println!("{frame:?}"),
+                    }
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e),
+        }
+
+        for (node, status) in liveness.nodes(now_ms()) {
+            let was_lost = lost.contains(&node);
+            if status == Liveness::Lost && !was_lost {
+                println!("node {:02X} lost: {node:?}", node.raw());
+                lost.push(node);
+            } else if status != Liveness::Lost && was_lost {
+                lost.retain(|n| *n != node);
+            }
+        }
+    }
+}
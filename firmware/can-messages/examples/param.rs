@@ -0,0 +1,67 @@
+//! Read or write a node parameter over a SocketCAN interface.
+//!
+//! Usage: `cargo run --example param --features socketcan -- <iface> <node> <index> [<kind> <value>]`
+//!
+//! `node` is one of `power-supply`, `coolbox`, `display`; `kind` is one of
+//! `bool`, `u16`, `i16`, `u32`, `i32`, `f32`.
+
+use can_messages::{prelude::*, NodeId, ParamRequest, ParamResponse, ParamValue};
+use socketcan::{CanFrame, CanSocket, Socket};
+use std::{io, time::Duration};
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, what)
+}
+
+fn parse_value(kind: &str, value: &str) -> Option<ParamValue> {
+    Some(match kind {
+        "bool" => ParamValue::Bool(value.parse().ok()?),
+        "u16" => ParamValue::U16(value.parse().ok()?),
+        "i16" => ParamValue::I16(value.parse().ok()?),
+        "u32" => ParamValue::U32(value.parse().ok()?),
+        "i32" => ParamValue::I32(value.parse().ok()?),
+        "f32" => ParamValue::F32(value.parse().ok()?),
+        _ => return None,
+    })
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [iface, node, index, rest @ ..] = args.as_slice() else {
+        return Err(invalid("usage: param <iface> <node> <index> [<kind> <value>]"));
+    };
+    let node = match node.as_str() {
+        "power-supply" => NodeId::POWER_SUPPLY,
+        "coolbox" => NodeId::COOLBOX,
+        "display" => NodeId::DISPLAY,
+        _ => return Err(invalid("unknown node")),
+    };
+    let index = index.parse().map_err(|_| invalid("invalid index"))?;
+    let request = match rest {
+        [] => ParamRequest::read(node, index),
+        [kind, value] => {
+            let value = parse_value(kind, value).ok_or_else(|| invalid("invalid value"))?;
+            ParamRequest::write(node, index, value)
+        }
+        _ => return Err(invalid("expected <kind> <value>")),
+    };
+
+    let socket = CanSocket::open(iface)?;
+    socket.set_read_timeout(Duration::from_secs(1))?;
+    let frame: CanFrame = request.try_encode().ok_or_else(|| invalid("cannot encode request"))?;
+    socket.write_frame(&frame)?;
+
+    loop {
+        let frame = socket.read_frame()?;
+        if let Some(response) = frame.try_decode::<ParamResponse>()
+            && response.source == node.raw()
+            && response.index == index
+        {
+            match response.value() {
+                Some(value) => println!("{value:?}"),
+                None => println!("{:?}", response.status),
+            }
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Act as the time master on a SocketCAN interface, sending the wall time.
+//!
+//! Only for a bus without the display, which is the master otherwise.
+//!
+//! Usage: `cargo run --example timesync --features socketcan -- [vcan0]`
+
+use can_messages::{prelude::*, TimeSync, TIME_SYNC_PERIOD_MS};
+use socketcan::{CanFrame, CanSocket, Socket};
+use std::{io, thread, time::{Duration, SystemTime}};
+
+fn main() -> io::Result<()> {
+    let iface = std::env::args().nth(1).unwrap_or_else(|| "vcan0".into());
+    let socket = CanSocket::open(&iface)?;
+    println!("Sending the time on {iface}");
+    loop {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_err(io::Error::other)?;
+        let frame: CanFrame = TimeSync::new(now.as_millis() as u64)
+            .try_encode()
+            .ok_or_else(|| io::Error::other("cannot encode time"))?;
+        socket.write_frame(&frame)?;
+        thread::sleep(Duration::from_millis(TIME_SYNC_PERIOD_MS));
+    }
+}
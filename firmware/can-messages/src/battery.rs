@@ -0,0 +1,124 @@
+//! What a Makita LXT battery pack reports about itself.
+//!
+//! The power supply reads the pack over its data contact and sends
+//! [`BatteryDetail`] and both halves of [`BatteryCells`] periodically,
+//! [`BatteryModel`] now and then. Nothing is sent while no pack answers.
+//! [`BatteryState`] is its own estimate, sent with or without the pack data.
+
+use crate::{CanId, DeciDegrees, DeciPercent, Millivolts, prelude::*};
+
+/// Cells in series of an 18 V pack.
+pub const BATTERY_CELLS: usize = 5;
+
+/// Model name of the pack, e.g. `BL1850B`.
+#[can_message(CanId::BATTERYMODEL)]
+pub struct BatteryModel {
+    /// ASCII, padded with zeros.
+    pub model: [u8; 8],
+}
+
+impl BatteryModel {
+    /// Longer names are cut off.
+    pub fn new(model: &str) -> Self {
+        let mut padded = [0; 8];
+        for (byte, c) in padded.iter_mut().zip(model.bytes()) {
+            *byte = c;
+        }
+        Self { model: padded }
+    }
+
+    /// The name without padding, empty if it is not ASCII.
+    pub fn as_str(&self) -> &str {
+        let len = self.model.iter().position(|&b| b == 0).unwrap_or(self.model.len());
+        core::str::from_utf8(&self.model[..len]).ok().filter(|s| s.is_ascii()).unwrap_or("")
+    }
+
+    /// Nominal capacity from the name, `BL18xy` holds x.y Ah.
+    pub fn capacity_mah(&self) -> Option<u16> {
+        let digits = self.as_str().strip_prefix("BL18")?.get(..2)?;
+        let ah10: u16 = digits.parse().ok()?;
+        (ah10 > 0).then_some(ah10 * 100)
+    }
+}
+
+/// State of health the pack keeps track of.
+#[can_message(CanId::BATTERYDETAIL)]
+pub struct BatteryDetail {
+    /// Charge cycles counted by the pack.
+    pub cycles: u16,
+    /// `FLAG_*` bits.
+    pub flags: u8,
+    pub temperature: DeciDegrees<i16>,
+}
+
+impl BatteryDetail {
+    /// The pack locked itself and refuses to discharge until it is reset.
+    pub const FLAG_LOCKED: u8 = 1 << 0;
+    /// The pack has been overdischarged.
+    pub const FLAG_OVERDISCHARGED: u8 = 1 << 1;
+
+    pub fn is_locked(&self) -> bool {
+        self.flags & Self::FLAG_LOCKED != 0
+    }
+
+    pub fn is_overdischarged(&self) -> bool {
+        self.flags & Self::FLAG_OVERDISCHARGED != 0
+    }
+}
+
+/// Up to three cell voltages, as measured by the pack.
+#[can_message(CanId::BATTERYCELLS)]
+pub struct BatteryCells {
+    /// Index of the first cell, 0 or 3.
+    pub first: u8,
+    /// Zero past the last cell.
+    pub cells: [Millivolts<u16>; 3],
+}
+
+impl BatteryCells {
+    /// Both halves of `cells_mv`.
+    pub fn split(cells_mv: &[u16; BATTERY_CELLS]) -> [Self; 2] {
+        [0, 3].map(|first| {
+            let mut cells = [Millivolts::from(0_u16); 3];
+            for (cell, &mv) in cells.iter_mut().zip(&cells_mv[first..]) {
+                *cell = mv.into();
+            }
+            Self { first: first as u8, cells }
+        })
+    }
+
+    /// Put a half into its place in all cell voltages.
+    pub fn merge_into(&self, cells_mv: &mut [u16; BATTERY_CELLS]) {
+        let rest = cells_mv.iter_mut().skip(self.first.into());
+        for (mv, cell) in rest.zip(&self.cells) {
+            *mv = cell.get();
+        }
+    }
+}
+
+/// State of charge, estimated by the power supply.
+#[can_message(CanId::BATTERYSTATE)]
+pub struct BatteryState {
+    pub soc: DeciPercent<u16>,
+    pub remaining_mah: u16,
+    /// At the current load, [`BatteryState::RUNTIME_UNKNOWN`] without load.
+    #[can(unit = "min")]
+    pub runtime_min: u16,
+}
+
+impl BatteryState {
+    pub const RUNTIME_UNKNOWN: u16 = u16::MAX;
+
+    pub fn new(soc_pct10: u16, remaining_mah: u16, runtime_min: Option<u16>) -> Self {
+        Self {
+            soc: soc_pct10.into(),
+            remaining_mah: remaining_mah.into(),
+            runtime_min: runtime_min.unwrap_or(Self::RUNTIME_UNKNOWN).into(),
+        }
+    }
+
+    /// Minutes left at the current load, if there is one.
+    pub fn runtime(&self) -> Option<u16> {
+        Some(self.runtime_min.get()).filter(|&min| min != Self::RUNTIME_UNKNOWN)
+    }
+}
@@ -0,0 +1,35 @@
+//! Print the DBC description of the trailer bus messages.
+//!
+//! Usage: `cargo run -p can-messages --bin can-dbc > anhaenger.dbc`
+
+use can_messages::{AnyMessage, CanDiagnostics, DeviceUid, FirmwareId, Heartbeat, NodeId, NodeMessage};
+use can_messages_trait::{dbc::write_dbc, MessageId, MessageInfo};
+
+/// Nodes sending per-node messages, with their names.
+const NODES: [(&str, NodeId); 3] = [
+    ("PowerSupply", NodeId::POWER_SUPPLY),
+    ("CoolBox", NodeId::COOLBOX),
+    ("Display", NodeId::DISPLAY),
+];
+
+/// One message type on the identifier of every node.
+fn per_node<T: NodeMessage>() -> impl Iterator<Item = MessageInfo> {
+    NODES.into_iter().map(|(node_name, node)| MessageInfo {
+        id: MessageId::Standard(T::id_for(node).raw()),
+        // Leaked once per run, DBC names have to be unique.
+        name: String::leak(format!("{}{node_name}", T::NAME)),
+        ..T::INFO
+    })
+}
+
+fn main() {
+    let mut messages = AnyMessage::MESSAGES.to_vec();
+    messages.extend(per_node::<Heartbeat>());
+    messages.extend(per_node::<CanDiagnostics>());
+    messages.extend(per_node::<FirmwareId>());
+    messages.extend(per_node::<DeviceUid>());
+
+    let mut dbc = String::new();
+    write_dbc(&mut dbc, &messages).expect("DBC formatting error");
+    print!("{dbc}");
+}
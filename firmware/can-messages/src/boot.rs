@@ -0,0 +1,565 @@
+//! Firmware update over CAN.
+//!
+//! Flash layout of the STM32F042F6 nodes:
+//!
+//! ```text
+//! 0x0800_0000  bootloader         6 KiB
+//! 0x0800_1800  application       25 KiB, the boot record in its last 12 bytes
+//! 0x0800_7C00  application data   1 KiB
+//! ```
+//!
+//! The bootloader starts the application only if the [`BootRecord`] matches
+//! its CRC-32. Cortex-M0 cannot relocate the vector table, so the bootloader
+//! copies the one of the application to the start of RAM and maps RAM at
+//! address 0 before jumping. Both keep the first [`RAM_RESERVED`] bytes of RAM
+//! out of their [`memory_x`] for that and for the update flag.
+//!
+//! A running application restarts into the bootloader on [`EnterBootloader`].
+//! The host then talks to the bootloader over ISO-TP on [`request_id`] and
+//! [`response_id`] of the node, one [`Request`] per transfer, each answered by
+//! one [`Response`].
+
+use crate::{CanId, MessageId, NodeId, prelude::*};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+#[cfg(all(feature = "std", feature = "isotp"))]
+pub mod host;
+
+pub const FLASH_BASE: u32 = 0x0800_0000;
+pub const FLASH_SIZE: u32 = 32 * 1024;
+/// Erase granularity.
+pub const PAGE_SIZE: u32 = 1024;
+/// First address of the application, also its vector table.
+pub const APP_START: u32 = FLASH_BASE + 6 * 1024;
+/// Page the application keeps its own data in, left alone by updates.
+pub const APP_DATA: u32 = FLASH_BASE + 31 * 1024;
+/// The [`BootRecord`], in the last bytes of the last application page.
+pub const RECORD: u32 = APP_DATA - BootRecord::LEN as u32;
+/// Room for the application image.
+pub const APP_CAPACITY: u32 = RECORD - APP_START;
+
+pub const RAM_BASE: u32 = 0x2000_0000;
+pub const RAM_SIZE: u32 = 6 * 1024;
+/// Start of RAM used by neither the bootloader nor the application.
+pub const RAM_RESERVED: u32 = 256;
+/// Size of the vector table copied to RAM: 16 system exceptions and 32 interrupts.
+pub const VECTORS_LEN: usize = 48 * 4;
+/// Address of the update flag, right after the copied vector table.
+#[cfg(feature = "cortex-m")]
+const UPDATE_FLAG: u32 = RAM_BASE + VECTORS_LEN as u32;
+#[cfg(feature = "cortex-m")]
+const UPDATE_MAGIC: u32 = 0xB007_10AD;
+
+/// Largest data block of a [`Request::Write`].
+pub const MAX_CHUNK: usize = 256;
+/// Largest encoded [`Request`].
+pub const MAX_REQUEST: usize = 5 + MAX_CHUNK;
+/// Largest encoded [`Response`].
+pub const MAX_RESPONSE: usize = 16;
+
+/// Restart a node into its bootloader.
+#[can_message(CanId::ENTERBOOTLOADER)]
+pub struct EnterBootloader {
+    /// Raw [`NodeId`] of the node to update.
+    pub target: u8,
+    #[can(counter)]
+    pub counter: u8,
+    #[can(crc)]
+    pub crc: u8,
+}
+
+impl EnterBootloader {
+    /// The checksum is filled in when encoding.
+    pub fn new(target: NodeId, counter: u8) -> Self {
+        Self { target: target.raw(), counter, crc: 0 }
+    }
+}
+
+/// Identifier of the ISO-TP frames from the host to the bootloader of `node`.
+pub fn request_id(node: NodeId) -> MessageId {
+    CanId::UPDATEREQUEST.structured().with_node(node).into()
+}
+
+/// Identifier of the ISO-TP frames from the bootloader of `node` to the host.
+pub fn response_id(node: NodeId) -> MessageId {
+    CanId::UPDATERESPONSE.structured().with_node(node).into()
+}
+
+/// ISO-TP configuration of the bootloader of `node`.
+#[cfg(feature = "isotp")]
+pub fn node_config(node: NodeId) -> crate::isotp::Config {
+    crate::isotp::Config::new(response_id(node), request_id(node))
+}
+
+/// ISO-TP configuration of the host updating `node`.
+#[cfg(feature = "isotp")]
+pub fn host_config(node: NodeId) -> crate::isotp::Config {
+    crate::isotp::Config::new(request_id(node), response_id(node))
+}
+
+/// What a firmware image gets linked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Image {
+    /// Application owning the chip but [`APP_DATA`], flashed with a probe.
+    Standalone,
+    Bootloader,
+    /// Application started by the bootloader.
+    Application,
+}
+
+/// Linker `MEMORY` definition for `image`, for the build scripts.
+#[cfg(feature = "std")]
+pub fn memory_x(image: Image) -> String {
+    let (flash, flash_len, ram) = match image {
+        Image::Standalone => (FLASH_BASE, APP_DATA - FLASH_BASE, RAM_BASE),
+        Image::Bootloader => (FLASH_BASE, APP_START - FLASH_BASE, RAM_BASE + RAM_RESERVED),
+        Image::Application => (APP_START, APP_CAPACITY, RAM_BASE + RAM_RESERVED),
+    };
+    let ram_len = RAM_BASE + RAM_SIZE - ram;
+    format!(
+        "MEMORY\n{{\n  FLASH : ORIGIN = {flash:#010x}, LENGTH = {flash_len}\n  RAM : ORIGIN = {ram:#010x}, LENGTH = {ram_len}\n}}\n"
+    )
+}
+
+/// Make the bootloader wait for an update after the next reset.
+///
+/// # Safety
+///
+/// Only for images linked with [`memory_x`], which keeps the flag out of
+/// the way.
+#[cfg(feature = "cortex-m")]
+pub unsafe fn request_update() {
+    unsafe { core::ptr::write_volatile(UPDATE_FLAG as *mut u32, UPDATE_MAGIC) }
+}
+
+/// Restart into the bootloader, which then waits for a new image.
+///
+/// # Safety
+///
+/// See [`request_update`].
+#[cfg(feature = "cortex-m")]
+pub unsafe fn enter_bootloader() -> ! {
+    #[cfg(feature = "defmt")]
+    defmt::info!("Restarting into the bootloader");
+    unsafe { request_update() };
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Check and clear the flag set by [`request_update`].
+///
+/// # Safety
+///
+/// See [`request_update`].
+#[cfg(feature = "cortex-m")]
+pub unsafe fn take_update_request() -> bool {
+    let flag = UPDATE_FLAG as *mut u32;
+    unsafe {
+        let requested = core::ptr::read_volatile(flag) == UPDATE_MAGIC;
+        core::ptr::write_volatile(flag, 0);
+        requested
+    }
+}
+
+/// CRC-32 as used by zlib and Ethernet, fed in pieces.
+///
+/// Bitwise, the bootloader has no room for a table.
+#[derive(Debug, Clone)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                self.0 = if self.0 & 1 != 0 { self.0 >> 1 ^ 0xEDB8_8320 } else { self.0 >> 1 };
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-32 of a whole image.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Length and checksum of the application, written after a complete update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootRecord {
+    pub len: u32,
+    pub crc: u32,
+}
+
+impl BootRecord {
+    pub const LEN: usize = 12;
+    const MAGIC: u32 = 0x5452_4C42;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..4].copy_from_slice(&Self::MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.len.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// Parse a record as stored in flash, `None` if erased or corrupt.
+    pub fn from_bytes(bytes: &[u8; Self::LEN]) -> Option<Self> {
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let record = Self { len: word(4), crc: word(8) };
+        (word(0) == Self::MAGIC && record.len <= APP_CAPACITY).then_some(record)
+    }
+}
+
+const INFO: u8 = 1;
+const ERASE: u8 = 2;
+const WRITE: u8 = 3;
+const COMMIT: u8 = 4;
+const BOOT: u8 = 5;
+
+/// Command from the host to the bootloader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request<'a> {
+    /// Ask for [`Info`].
+    Info,
+    /// Invalidate the boot record and erase room for `len` bytes of image.
+    Erase { len: u32 },
+    /// Program `data` at `offset` from [`APP_START`], both of even length.
+    Write { offset: u32, data: &'a [u8] },
+    /// Check the first `len` bytes of the image against `crc` and write the
+    /// boot record.
+    Commit { len: u32, crc: u32 },
+    /// Start the application.
+    Boot,
+}
+
+impl<'a> Request<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let word = |at: usize| Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+        Some(match (bytes.first()?, bytes.len()) {
+            (&INFO, 1) => Request::Info,
+            (&ERASE, 5) => Request::Erase { len: word(1)? },
+            (&WRITE, 5..=MAX_REQUEST) => Request::Write { offset: word(1)?, data: &bytes[5..] },
+            (&COMMIT, 9) => Request::Commit { len: word(1)?, crc: word(5)? },
+            (&BOOT, 1) => Request::Boot,
+            _ => return None,
+        })
+    }
+
+    /// Encode into `buf`, return the used part.
+    ///
+    /// Panics if the data of a `Write` is longer than [`MAX_CHUNK`].
+    pub fn encode<'b>(&self, buf: &'b mut [u8; MAX_REQUEST]) -> &'b [u8] {
+        let mut put = |command: u8, words: &[u32], data: &[u8]| {
+            buf[0] = command;
+            for (i, word) in words.iter().enumerate() {
+                buf[1 + i * 4..][..4].copy_from_slice(&word.to_le_bytes());
+            }
+            let start = 1 + words.len() * 4;
+            buf[start..][..data.len()].copy_from_slice(data);
+            start + data.len()
+        };
+        let len = match *self {
+            Request::Info => put(INFO, &[], &[]),
+            Request::Erase { len } => put(ERASE, &[len], &[]),
+            Request::Write { offset, data } => put(WRITE, &[offset], data),
+            Request::Commit { len, crc } => put(COMMIT, &[len, crc], &[]),
+            Request::Boot => put(BOOT, &[], &[]),
+        };
+        &buf[..len]
+    }
+}
+
+/// Outcome of a [`Request`].
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, IntoPrimitive, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    /// Unknown command or misaligned write.
+    Invalid = 1,
+    /// Beyond the application area or the erased part of it.
+    OutOfRange = 2,
+    /// Erasing or programming failed.
+    Flash = 3,
+    /// The image does not match the checksum given with `Commit`.
+    Checksum = 4,
+    /// No valid image to boot.
+    NoImage = 5,
+}
+
+/// State of the bootloader, answer to [`Request::Info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    /// Room for the application image.
+    pub capacity: u32,
+    /// Largest data block of a write.
+    pub max_chunk: u16,
+    /// The image in flash, if it is valid.
+    pub image: Option<BootRecord>,
+}
+
+/// Answer of the bootloader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    Status(Status),
+    Info(Info),
+}
+
+impl Response {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let status = Status::try_from(*bytes.first()?).ok()?;
+        if bytes.len() == 1 {
+            return Some(Response::Status(status));
+        }
+        let bytes: &[u8; MAX_RESPONSE] = bytes.try_into().ok()?;
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Some(Response::Info(Info {
+            capacity: word(1),
+            max_chunk: u16::from_le_bytes([bytes[5], bytes[6]]),
+            image: (bytes[7] != 0).then(|| BootRecord { len: word(8), crc: word(12) }),
+        }))
+    }
+
+    /// Encode into `buf`, return the used part.
+    pub fn encode<'b>(&self, buf: &'b mut [u8; MAX_RESPONSE]) -> &'b [u8] {
+        match *self {
+            Response::Status(status) => {
+                buf[0] = status.into();
+                &buf[..1]
+            }
+            Response::Info(info) => {
+                let image = info.image.unwrap_or(BootRecord { len: 0, crc: 0 });
+                buf[0] = Status::Ok.into();
+                buf[1..5].copy_from_slice(&info.capacity.to_le_bytes());
+                buf[5..7].copy_from_slice(&info.max_chunk.to_le_bytes());
+                buf[7] = info.image.is_some() as u8;
+                buf[8..12].copy_from_slice(&image.len.to_le_bytes());
+                buf[12..].copy_from_slice(&image.crc.to_le_bytes());
+                &buf[..]
+            }
+        }
+    }
+}
+
+/// Flash memory of the node, offsets are from [`FLASH_BASE`].
+pub trait Flash {
+    type Error;
+
+    /// Erase the pages in `from..to`.
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error>;
+    /// Program erased memory.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// Bootloader side of the update protocol.
+pub struct Updater<F> {
+    flash: F,
+    /// Bytes of the application area erased since the last `Erase`.
+    erased: u32,
+    boot: bool,
+}
+
+impl<F: Flash> Updater<F> {
+    pub const fn new(flash: F) -> Self {
+        Self { flash, erased: 0, boot: false }
+    }
+
+    /// Execute an encoded [`Request`].
+    pub fn handle(&mut self, request: &[u8]) -> Response {
+        let result = match Request::parse(request) {
+            Some(request) => self.execute(request),
+            None => Err(Status::Invalid),
+        };
+        result.unwrap_or_else(Response::Status)
+    }
+
+    /// Give back the flash.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// A valid image is to be started once the response is out.
+    pub fn boot_requested(&self) -> bool {
+        self.boot
+    }
+
+    fn execute(&mut self, request: Request<'_>) -> Result<Response, Status> {
+        const APP: u32 = APP_START - FLASH_BASE;
+        const DONE: Result<Response, Status> = Ok(Response::Status(Status::Ok));
+        match request {
+            Request::Info => {
+                let image = self.image().map_err(|_| Status::Flash)?;
+                Ok(Response::Info(Info { capacity: APP_CAPACITY, max_chunk: MAX_CHUNK as u16, image }))
+            }
+            Request::Erase { len } => {
+                if len > APP_CAPACITY {
+                    return Err(Status::OutOfRange);
+                }
+                self.erased = 0;
+                self.boot = false;
+                // The record goes first, an interrupted update must not look valid.
+                let record_page = APP_DATA - FLASH_BASE - PAGE_SIZE;
+                self.flash.erase(record_page, record_page + PAGE_SIZE).map_err(|_| Status::Flash)?;
+                let len = len.next_multiple_of(PAGE_SIZE);
+                self.flash.erase(APP, APP + len).map_err(|_| Status::Flash)?;
+                // The last page also holds the record, which is not for writing.
+                self.erased = len.min(APP_CAPACITY);
+                DONE
+            }
+            Request::Write { offset, data } => {
+                if offset % 2 != 0 || data.len() % 2 != 0 {
+                    return Err(Status::Invalid);
+                }
+                match offset.checked_add(data.len() as u32) {
+                    Some(end) if end <= self.erased => {}
+                    _ => return Err(Status::OutOfRange),
+                }
+                self.flash.write(APP + offset, data).map_err(|_| Status::Flash)?;
+                DONE
+            }
+            Request::Commit { len, crc } => {
+                if len > self.erased {
+                    return Err(Status::OutOfRange);
+                }
+                if self.checksum(len).map_err(|_| Status::Flash)? != crc {
+                    return Err(Status::Checksum);
+                }
+                let record = BootRecord { len, crc }.to_bytes();
+                self.flash.write(RECORD - FLASH_BASE, &record).map_err(|_| Status::Flash)?;
+                // Programmed once, the area needs another erase before writing again.
+                self.erased = 0;
+                DONE
+            }
+            Request::Boot => {
+                self.image().map_err(|_| Status::Flash)?.ok_or(Status::NoImage)?;
+                self.boot = true;
+                DONE
+            }
+        }
+    }
+
+    /// Boot record of the image in flash, if it matches the image.
+    pub fn image(&mut self) -> Result<Option<BootRecord>, F::Error> {
+        let mut bytes = [0; BootRecord::LEN];
+        self.flash.read(RECORD - FLASH_BASE, &mut bytes)?;
+        let Some(record) = BootRecord::from_bytes(&bytes) else {
+            return Ok(None);
+        };
+        Ok((self.checksum(record.len)? == record.crc).then_some(record))
+    }
+
+    fn checksum(&mut self, len: u32) -> Result<u32, F::Error> {
+        let mut crc = Crc32::new();
+        let mut buf = [0; 64];
+        let mut offset = 0;
+        while offset < len {
+            let chunk = &mut buf[..(len - offset).min(64) as usize];
+            self.flash.read(APP_START - FLASH_BASE + offset, chunk)?;
+            crc.update(chunk);
+            offset += chunk.len() as u32;
+        }
+        Ok(crc.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let data = [0xA5; MAX_CHUNK];
+        let requests = [
+            Request::Info,
+            Request::Erase { len: 0x1234 },
+            Request::Write { offset: 0x100, data: &data[..4] },
+            Request::Write { offset: 0, data: &data },
+            Request::Commit { len: 3000, crc: 0xDEAD_BEEF },
+            Request::Boot,
+        ];
+        for request in requests {
+            let mut buf = [0; MAX_REQUEST];
+            assert_eq!(Request::parse(request.encode(&mut buf)), Some(request));
+        }
+    }
+
+    #[test]
+    fn request_encoding() {
+        let mut buf = [0; MAX_REQUEST];
+        assert_eq!(Request::Erase { len: 0x0102_0304 }.encode(&mut buf), [ERASE, 4, 3, 2, 1]);
+        let commit = Request::Commit { len: 0x10, crc: 0x0A0B_0C0D };
+        assert_eq!(commit.encode(&mut buf), [COMMIT, 0x10, 0, 0, 0, 0x0D, 0x0C, 0x0B, 0x0A]);
+        assert_eq!(Request::Write { offset: 2, data: &[7, 8] }.encode(&mut buf), [WRITE, 2, 0, 0, 0, 7, 8]);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        assert_eq!(Request::parse(&[]), None);
+        assert_eq!(Request::parse(&[0]), None);
+        assert_eq!(Request::parse(&[INFO, 0]), None);
+        assert_eq!(Request::parse(&[ERASE, 1, 2, 3]), None);
+        assert_eq!(Request::parse(&[COMMIT, 1, 2, 3, 4]), None);
+        assert_eq!(Request::parse(&[WRITE; MAX_REQUEST + 1]), None);
+        assert_eq!(Request::parse(&[BOOT + 1]), None);
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let record = BootRecord { len: 1000, crc: 0x1234_5678 };
+        let mut responses = [Status::Ok, Status::Invalid, Status::OutOfRange, Status::Flash, Status::Checksum, Status::NoImage]
+            .map(Response::Status)
+            .to_vec();
+        for image in [None, Some(record)] {
+            responses.push(Response::Info(Info { capacity: APP_CAPACITY, max_chunk: MAX_CHUNK as u16, image }));
+        }
+        for response in responses {
+            let mut buf = [0; MAX_RESPONSE];
+            assert_eq!(Response::parse(response.encode(&mut buf)), Some(response));
+        }
+    }
+
+    #[test]
+    fn invalid_responses() {
+        assert_eq!(Response::parse(&[]), None);
+        assert_eq!(Response::parse(&[6]), None);
+        assert_eq!(Response::parse(&[0; MAX_RESPONSE - 1]), None);
+        assert_eq!(Response::parse(&[0; MAX_RESPONSE + 1]), None);
+    }
+
+    #[test]
+    fn boot_record() {
+        let record = BootRecord { len: 1000, crc: 0x1234_5678 };
+        assert_eq!(BootRecord::from_bytes(&record.to_bytes()), Some(record));
+        assert_eq!(BootRecord::from_bytes(&[0xFF; BootRecord::LEN]), None);
+
+        let mut corrupt = record.to_bytes();
+        corrupt[0] ^= 1;
+        assert_eq!(BootRecord::from_bytes(&corrupt), None);
+        let too_large = BootRecord { len: APP_CAPACITY + 2, crc: 0 };
+        assert_eq!(BootRecord::from_bytes(&too_large.to_bytes()), None);
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
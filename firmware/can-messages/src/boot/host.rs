@@ -0,0 +1,349 @@
+//! Host side of the update, and a node simulated in memory.
+
+use core::fmt::Debug;
+use std::io;
+use can_messages_trait::embedded_can::Frame;
+use embassy_time::{Duration, with_timeout};
+use crate::isotp::{self, FrameRx, FrameTx, IsoTp};
+use super::{BootRecord, Flash, MAX_REQUEST, MAX_RESPONSE, PAGE_SIZE, Request, Response, Status};
+
+/// Wait for the answer to most requests.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Wait for the answer to an erase of the whole application area.
+const ERASE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts to reach the bootloader while the node restarts.
+const CONNECT_ATTEMPTS: usize = 10;
+
+/// Progress of an [`update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The node already has the image, nothing to write.
+    Unchanged,
+    Erasing,
+    /// `written` of `len` bytes are programmed.
+    Writing { written: u32, len: u32 },
+    /// The image is in place and started.
+    Started(BootRecord),
+}
+
+fn transfer_error<TE: Debug, RE: Debug>(err: isotp::Error<TE, RE>) -> io::Error {
+    match err {
+        isotp::Error::Timeout => io::ErrorKind::TimedOut.into(),
+        err => io::Error::other(format!("ISO-TP transfer failed: {err:?}")),
+    }
+}
+
+async fn request<T, R, F>(isotp: &mut IsoTp<T, R>, request: Request<'_>, timeout: Duration) -> io::Result<Response>
+where
+    T: FrameTx<Frame = F, Error: Debug>,
+    R: FrameRx<Frame = F, Error: Debug>,
+    F: Frame,
+{
+    let mut buf = [0; MAX_REQUEST];
+    isotp.send(request.encode(&mut buf)).await.map_err(transfer_error)?;
+    let mut response = heapless::Vec::<u8, MAX_RESPONSE>::new();
+    with_timeout(timeout, isotp.receive(&mut response))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+        .map_err(transfer_error)?;
+    Response::parse(&response).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid response"))
+}
+
+async fn expect_ok<T, R, F>(isotp: &mut IsoTp<T, R>, req: Request<'_>, timeout: Duration) -> io::Result<()>
+where
+    T: FrameTx<Frame = F, Error: Debug>,
+    R: FrameRx<Frame = F, Error: Debug>,
+    F: Frame,
+{
+    match request(isotp, req, timeout).await? {
+        Response::Status(Status::Ok) => Ok(()),
+        response => Err(io::Error::other(format!("{req:?} failed: {response:?}"))),
+    }
+}
+
+/// Send `image` to a bootloader and start it, reporting each step to `progress`.
+pub async fn update<T, R, F>(isotp: &mut IsoTp<T, R>, image: &[u8], mut progress: impl FnMut(Step)) -> io::Result<()>
+where
+    T: FrameTx<Frame = F, Error: Debug>,
+    R: FrameRx<Frame = F, Error: Debug>,
+    F: Frame,
+{
+    let mut info = None;
+    for _ in 0..CONNECT_ATTEMPTS {
+        match request(isotp, Request::Info, RESPONSE_TIMEOUT).await {
+            Ok(Response::Info(found)) => {
+                info = Some(found);
+                break;
+            }
+            Ok(response) => return Err(io::Error::other(format!("unexpected response {response:?}"))),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    let info = info.ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "no answer from the bootloader"))?;
+    let len = image.len() as u32;
+    if len > info.capacity {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("image of {len} bytes does not fit into {} bytes", info.capacity),
+        ));
+    }
+    let record = BootRecord { len, crc: super::crc32(image) };
+    if info.image == Some(record) {
+        progress(Step::Unchanged);
+    } else {
+        progress(Step::Erasing);
+        expect_ok(isotp, Request::Erase { len }, ERASE_TIMEOUT).await?;
+        for (i, data) in image.chunks(info.max_chunk.into()).enumerate() {
+            let offset = (i * info.max_chunk as usize) as u32;
+            expect_ok(isotp, Request::Write { offset, data }, RESPONSE_TIMEOUT).await?;
+            progress(Step::Writing { written: offset + data.len() as u32, len });
+        }
+        expect_ok(isotp, Request::Commit { len, crc: record.crc }, RESPONSE_TIMEOUT).await?;
+    }
+    expect_ok(isotp, Request::Boot, RESPONSE_TIMEOUT).await?;
+    progress(Step::Started(record));
+    Ok(())
+}
+
+/// Flash in memory, refusing to program what is not erased like the real one.
+pub struct SimulatedFlash(pub Vec<u8>);
+
+impl SimulatedFlash {
+    /// All of the flash, erased.
+    pub fn new() -> Self {
+        Self(vec![0xFF; super::FLASH_SIZE as usize])
+    }
+}
+
+impl Default for SimulatedFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flash for SimulatedFlash {
+    type Error = &'static str;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(PAGE_SIZE) || !to.is_multiple_of(PAGE_SIZE) {
+            return Err("unaligned erase");
+        }
+        self.0.get_mut(from as usize..to as usize).ok_or("erase out of range")?.fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let target = self.0.get_mut(offset as usize..offset as usize + data.len()).ok_or("write out of range")?;
+        if target.iter().any(|&byte| byte != 0xFF) {
+            return Err("write to memory that is not erased");
+        }
+        target.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        buf.copy_from_slice(self.0.get(offset as usize..offset as usize + buf.len()).ok_or("read out of range")?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::{
+        block_on,
+        select::{Either, select},
+    };
+    use crate::{
+        NodeId,
+        boot::{self, APP_CAPACITY, APP_START, FLASH_BASE, MAX_CHUNK, RECORD, Updater},
+        isotp::{Loopback, LoopbackFrame},
+    };
+    use super::*;
+
+    type Link = Loopback<LoopbackFrame, 8>;
+
+    const NODE: NodeId = NodeId::POWER_SUPPLY;
+    const APP: u32 = APP_START - FLASH_BASE;
+    const OK: Response = Response::Status(Status::Ok);
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    fn handle<F: Flash>(updater: &mut Updater<F>, request: Request<'_>) -> Response {
+        let mut buf = [0; MAX_REQUEST];
+        updater.handle(request.encode(&mut buf))
+    }
+
+    fn write_all<F: Flash>(updater: &mut Updater<F>, image: &[u8]) {
+        for (i, data) in image.chunks(MAX_CHUNK).enumerate() {
+            let offset = (i * MAX_CHUNK) as u32;
+            assert_eq!(handle(updater, Request::Write { offset, data }), OK);
+        }
+    }
+
+    /// Put `image` into flash the way the host does, without the transport.
+    fn install(updater: &mut Updater<SimulatedFlash>, image: &[u8]) -> BootRecord {
+        let record = BootRecord { len: image.len() as u32, crc: boot::crc32(image) };
+        assert_eq!(handle(updater, Request::Erase { len: record.len }), OK);
+        write_all(updater, image);
+        assert_eq!(handle(updater, Request::Commit { len: record.len, crc: record.crc }), OK);
+        record
+    }
+
+    #[test]
+    fn write_beyond_erased() {
+        let mut updater = Updater::new(SimulatedFlash::new());
+        let status = |status| Response::Status(status);
+        assert_eq!(handle(&mut updater, Request::Write { offset: 0, data: &[1, 2] }), status(Status::OutOfRange));
+
+        // Erased in whole pages.
+        assert_eq!(handle(&mut updater, Request::Erase { len: 100 }), OK);
+        let last = PAGE_SIZE - 2;
+        assert_eq!(handle(&mut updater, Request::Write { offset: last, data: &[1, 2] }), OK);
+        let beyond = Request::Write { offset: PAGE_SIZE, data: &[1, 2] };
+        assert_eq!(handle(&mut updater, beyond), status(Status::OutOfRange));
+        let overflowing = Request::Write { offset: u32::MAX - 1, data: &[1, 2] };
+        assert_eq!(handle(&mut updater, overflowing), status(Status::OutOfRange));
+
+        let too_large = Request::Erase { len: APP_CAPACITY + 1 };
+        assert_eq!(handle(&mut updater, too_large), status(Status::OutOfRange));
+
+        // The record shares the last page, but not its bytes.
+        assert_eq!(handle(&mut updater, Request::Erase { len: APP_CAPACITY }), OK);
+        let last = Request::Write { offset: APP_CAPACITY - 2, data: &[1, 2] };
+        assert_eq!(handle(&mut updater, last), OK);
+        let into_record = Request::Write { offset: APP_CAPACITY, data: &[1, 2] };
+        assert_eq!(handle(&mut updater, into_record), status(Status::OutOfRange));
+    }
+
+    #[test]
+    fn odd_writes() {
+        let mut updater = Updater::new(SimulatedFlash::new());
+        assert_eq!(handle(&mut updater, Request::Erase { len: PAGE_SIZE }), OK);
+        let odd_offset = Request::Write { offset: 1, data: &[1, 2] };
+        assert_eq!(handle(&mut updater, odd_offset), Response::Status(Status::Invalid));
+        let odd_length = Request::Write { offset: 0, data: &[1, 2, 3] };
+        assert_eq!(handle(&mut updater, odd_length), Response::Status(Status::Invalid));
+        assert!(updater.into_inner().0[APP as usize..][..4].iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn commit_with_bad_crc() {
+        let mut updater = Updater::new(SimulatedFlash::new());
+        let image = image(1000);
+        let crc = boot::crc32(&image);
+        assert_eq!(handle(&mut updater, Request::Erase { len: 1000 }), OK);
+        write_all(&mut updater, &image);
+        let bad = Request::Commit { len: 1000, crc: crc ^ 1 };
+        assert_eq!(handle(&mut updater, bad), Response::Status(Status::Checksum));
+        assert_eq!(updater.image(), Ok(None));
+        assert_eq!(handle(&mut updater, Request::Boot), Response::Status(Status::NoImage));
+
+        assert_eq!(handle(&mut updater, Request::Commit { len: 1000, crc }), OK);
+        assert_eq!(updater.image(), Ok(Some(BootRecord { len: 1000, crc })));
+        // Programmed once, the next commit needs another erase.
+        assert_eq!(handle(&mut updater, Request::Commit { len: 1000, crc }), Response::Status(Status::OutOfRange));
+    }
+
+    #[test]
+    fn boot_without_image() {
+        let mut updater = Updater::new(SimulatedFlash::new());
+        assert_eq!(handle(&mut updater, Request::Boot), Response::Status(Status::NoImage));
+        assert!(!updater.boot_requested());
+
+        let record = install(&mut updater, &image(64));
+        let info = handle(&mut updater, Request::Info);
+        assert_eq!(info, Response::Info(boot::Info { capacity: APP_CAPACITY, max_chunk: 256, image: Some(record) }));
+        assert_eq!(handle(&mut updater, Request::Boot), OK);
+        assert!(updater.boot_requested());
+    }
+
+    /// Fails to erase the application area.
+    struct AppEraseFails(SimulatedFlash);
+
+    impl Flash for AppEraseFails {
+        type Error = &'static str;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            if from == APP { Err("erase failed") } else { self.0.erase(from, to) }
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+            self.0.write(offset, data)
+        }
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+            self.0.read(offset, buf)
+        }
+    }
+
+    #[test]
+    fn erase_invalidates_record_first() {
+        let mut updater = Updater::new(SimulatedFlash::new());
+        install(&mut updater, &image(64));
+        assert_eq!(handle(&mut updater, Request::Boot), OK);
+
+        let mut updater = Updater::new(AppEraseFails(updater.into_inner()));
+        assert_eq!(handle(&mut updater, Request::Erase { len: 64 }), Response::Status(Status::Flash));
+        assert!(!updater.boot_requested());
+        assert_eq!(updater.image(), Ok(None));
+        let flash = updater.into_inner().0;
+        assert!(flash.0[(RECORD - FLASH_BASE) as usize..][..BootRecord::LEN].iter().all(|&byte| byte == 0xFF));
+        // The old image itself is still there.
+        assert_eq!(flash.0[APP as usize..][..64], image(64));
+    }
+
+    /// Answer requests like the bootloader until it is told to start the image.
+    async fn serve(isotp: &mut IsoTp<&Link, &Link>, updater: &mut Updater<SimulatedFlash>) {
+        let mut request = heapless::Vec::<u8, MAX_REQUEST>::new();
+        while !updater.boot_requested() {
+            isotp.receive(&mut request).await.unwrap();
+            let response = updater.handle(&request);
+            let mut buf = [0; MAX_RESPONSE];
+            isotp.send(response.encode(&mut buf)).await.unwrap();
+        }
+    }
+
+    /// Run [`update`] against a simulated node, return its result, the steps and the flash.
+    fn update_node(flash: SimulatedFlash, image: &[u8]) -> (io::Result<()>, Vec<Step>, SimulatedFlash) {
+        let (to_node, to_host) = (Link::new(), Link::new());
+        let mut host = IsoTp::new(&to_node, &to_host, boot::host_config(NODE));
+        let mut node = IsoTp::new(&to_host, &to_node, boot::node_config(NODE));
+        let mut updater = Updater::new(flash);
+        let mut steps = Vec::new();
+        // The node is done before the host got the last response.
+        let node = async {
+            serve(&mut node, &mut updater).await;
+            core::future::pending::<()>().await
+        };
+        let result = match block_on(select(update(&mut host, image, |step| steps.push(step)), node)) {
+            Either::First(result) => result,
+            Either::Second(()) => unreachable!(),
+        };
+        (result, steps, updater.into_inner())
+    }
+
+    #[test]
+    fn update_over_isotp() {
+        let image = image(3000);
+        let record = BootRecord { len: 3000, crc: boot::crc32(&image) };
+
+        let (result, steps, flash) = update_node(SimulatedFlash::new(), &image);
+        result.unwrap();
+        assert_eq!(steps.first(), Some(&Step::Erasing));
+        assert_eq!(steps[steps.len() - 2], Step::Writing { written: 3000, len: 3000 });
+        assert_eq!(steps.last(), Some(&Step::Started(record)));
+        assert_eq!(flash.0[APP as usize..][..3000], image);
+
+        // Only started the next time.
+        let (result, steps, flash) = update_node(flash, &image);
+        result.unwrap();
+        assert_eq!(steps, [Step::Unchanged, Step::Started(record)]);
+
+        let too_large = vec![0; APP_CAPACITY as usize + 2];
+        let (result, _, _) = update_node(flash, &too_large);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
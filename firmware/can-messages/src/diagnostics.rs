@@ -0,0 +1,51 @@
+//! CAN controller health of each node.
+//!
+//! Nodes watch their controller with the [`supervisor`](crate::supervisor)
+//! module and publish what it found in [`CanDiagnostics`].
+
+use can_messages_trait::supervisor::{BusState, BusStatus, Counters};
+use crate::{CanId, NodeId, NodeMessage, prelude::*};
+
+/// How often nodes send their CAN diagnostics, unless something changes.
+pub const CAN_DIAGNOSTICS_PERIOD_MS: u64 = 5000;
+
+/// Error state and statistics of the CAN controller of a node, sent on the
+/// identifier of each node.
+///
+/// Counters saturate at 255.
+#[can_message(CanId::CANDIAG)]
+pub struct CanDiagnostics {
+    /// Raw [`NodeId`] of the sender.
+    pub node: u8,
+    pub state: BusState,
+    /// Transmit error counter.
+    pub tec: u8,
+    /// Receive error counter.
+    pub rec: u8,
+    pub bus_off: u8,
+    pub bus_errors: u8,
+    pub rx_lost: u8,
+    pub tx_dropped: u8,
+}
+
+impl CanDiagnostics {
+    pub fn new(node: NodeId, status: BusStatus, counters: Counters) -> Self {
+        let saturate = |count: u16| count.try_into().unwrap_or(u8::MAX);
+        Self {
+            node: node.raw(),
+            state: status.state,
+            tec: status.tec,
+            rec: status.rec,
+            bus_off: saturate(counters.bus_off),
+            bus_errors: saturate(counters.bus_errors),
+            rx_lost: saturate(counters.rx_lost),
+            tx_dropped: saturate(counters.tx_dropped),
+        }
+    }
+}
+
+impl NodeMessage for CanDiagnostics {
+    fn sender(&self) -> u8 {
+        self.node
+    }
+}
@@ -0,0 +1,230 @@
+//! Node heartbeat and liveness tracking.
+//!
+//! Every node periodically sends [`Heartbeat`] on its own identifier, see
+//! [`NodeMessage`]. Receivers feed them into a [`LivenessTracker`] to
+//! find out which nodes went silent.
+
+use can_messages_trait::SignalValue;
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::{CanId, NodeId, NodeMessage, prelude::*};
+
+/// How often nodes send their heartbeat.
+pub const HEARTBEAT_PERIOD_MS: u64 = 1000;
+
+/// Network management state of a node.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    /// Started, not yet doing its job.
+    Booting = 0,
+    Operational = 1,
+    /// Switching itself off.
+    ShuttingDown = 2,
+    /// Running, but unable to do its job.
+    Fault = 3,
+}
+
+impl SignalValue for NodeState {
+    const SIGNED: bool = false;
+}
+
+/// Current state of the local node, shared between tasks.
+pub struct NodeStateCell(AtomicU8);
+
+impl NodeStateCell {
+    pub const fn new(state: NodeState) -> Self {
+        Self(AtomicU8::new(state as u8))
+    }
+
+    pub fn get(&self) -> NodeState {
+        match self.0.load(Ordering::Relaxed) {
+            0 => NodeState::Booting,
+            1 => NodeState::Operational,
+            2 => NodeState::ShuttingDown,
+            _ => NodeState::Fault,
+        }
+    }
+
+    pub fn set(&self, state: NodeState) {
+        self.0.store(state as u8, Ordering::Relaxed);
+    }
+}
+
+/// Periodic "I am alive" message, sent on the identifier of each node.
+#[can_message(CanId::HEARTBEAT)]
+pub struct Heartbeat {
+    /// Raw [`NodeId`] of the sender.
+    pub node: u8,
+    pub state: NodeState,
+    #[can(unit = "s")]
+    pub uptime_s: u32,
+}
+
+impl Heartbeat {
+    pub fn new(node: NodeId, state: NodeState, uptime_s: u32) -> Self {
+        Self {
+            node: node.raw(),
+            state,
+            uptime_s: uptime_s.into(),
+        }
+    }
+}
+
+impl NodeMessage for Heartbeat {
+    fn sender(&self) -> u8 {
+        self.node
+    }
+}
+
+/// What a [`LivenessTracker`] knows about a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    /// Watched, but never heard of.
+    Unknown,
+    /// Heartbeat received within the timeout.
+    Alive(NodeState),
+    /// No heartbeat for longer than the timeout.
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    node: NodeId,
+    seen: Option<(u64, NodeState, u32)>,
+}
+
+/// Keeps track of up to `N` nodes by their heartbeats.
+///
+/// Timestamps are milliseconds from any monotonic clock.
+#[derive(Debug, Clone)]
+pub struct LivenessTracker<const N: usize> {
+    entries: [Option<Entry>; N],
+    timeout_ms: u64,
+}
+
+impl<const N: usize> LivenessTracker<N> {
+    pub const fn new(timeout_ms: u64) -> Self {
+        Self {
+            entries: [None; N],
+            timeout_ms,
+        }
+    }
+
+    fn entry(&mut self, node: NodeId) -> Option<&mut Entry> {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.node == node))
+            .or_else(|| self.entries.iter().position(Option::is_none))?;
+        Some(self.entries[index].get_or_insert(Entry { node, seen: None }))
+    }
+
+    /// Expect heartbeats from `node`, so it is reported even if never seen.
+    ///
+    /// Returns `false` if the tracker is full.
+    pub fn watch(&mut self, node: NodeId) -> bool {
+        self.entry(node).is_some()
+    }
+
+    /// Record a received heartbeat.
+    ///
+    /// Returns `true` if the node appeared, came back after being lost or
+    /// restarted since the previous heartbeat.
+    pub fn update(&mut self, heartbeat: &Heartbeat, now_ms: u64) -> bool {
+        let Some(node) = NodeId::from_raw(heartbeat.node) else {
+            return false;
+        };
+        let timeout_ms = self.timeout_ms;
+        let uptime_s = heartbeat.uptime_s.get();
+        let Some(entry) = self.entry(node) else {
+            return false;
+        };
+        let news = match entry.seen {
+            None => true,
+            Some((seen_ms, _, seen_uptime_s)) => {
+                now_ms.saturating_sub(seen_ms) > timeout_ms || uptime_s < seen_uptime_s
+            }
+        };
+        entry.seen = Some((now_ms, heartbeat.state, uptime_s));
+        news
+    }
+
+    /// Current knowledge about `node`.
+    pub fn status(&self, node: NodeId, now_ms: u64) -> Liveness {
+        let seen = self.entries.iter().flatten().find(|e| e.node == node).and_then(|e| e.seen);
+        match seen {
+            None => Liveness::Unknown,
+            Some((seen_ms, state, _)) if now_ms.saturating_sub(seen_ms) <= self.timeout_ms => {
+                Liveness::Alive(state)
+            }
+            Some(_) => Liveness::Lost,
+        }
+    }
+
+    /// Every tracked node with its status.
+    pub fn nodes(&self, now_ms: u64) -> impl Iterator<Item = (NodeId, Liveness)> + '_ {
+        self.entries
+            .iter()
+            .flatten()
+            .map(move |e| (e.node, self.status(e.node, now_ms)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT_MS: u64 = 3 * HEARTBEAT_PERIOD_MS;
+
+    fn beat(node: NodeId, uptime_s: u32) -> Heartbeat {
+        Heartbeat::new(node, NodeState::Operational, uptime_s)
+    }
+
+    #[test]
+    fn lost_and_found() {
+        let mut tracker = LivenessTracker::<2>::new(TIMEOUT_MS);
+        let node = NodeId::POWER_SUPPLY;
+        assert!(tracker.watch(node));
+        assert_eq!(tracker.status(node, 0), Liveness::Unknown);
+
+        assert!(tracker.update(&beat(node, 1), 1000));
+        assert_eq!(tracker.status(node, 1000), Liveness::Alive(NodeState::Operational));
+        assert_eq!(tracker.status(node, 1000 + TIMEOUT_MS), Liveness::Alive(NodeState::Operational));
+        assert_eq!(tracker.status(node, 1001 + TIMEOUT_MS), Liveness::Lost);
+
+        // Back after being lost.
+        assert!(tracker.update(&beat(node, 10), 10_000));
+        assert_eq!(tracker.status(node, 10_000), Liveness::Alive(NodeState::Operational));
+    }
+
+    #[test]
+    fn news() {
+        let mut tracker = LivenessTracker::<2>::new(TIMEOUT_MS);
+        let node = NodeId::COOLBOX;
+        assert!(tracker.update(&beat(node, 5), 0), "first sighting");
+        assert!(!tracker.update(&beat(node, 6), 1000));
+        assert!(!tracker.update(&beat(node, 7), 1000 + TIMEOUT_MS));
+        assert!(tracker.update(&beat(node, 1), 2000 + TIMEOUT_MS), "restarted");
+
+        let fault = Heartbeat::new(node, NodeState::Fault, 2);
+        assert!(!tracker.update(&fault, 3000 + TIMEOUT_MS));
+        assert_eq!(tracker.status(node, 3000 + TIMEOUT_MS), Liveness::Alive(NodeState::Fault));
+    }
+
+    #[test]
+    fn unwatched() {
+        let mut tracker = LivenessTracker::<1>::new(TIMEOUT_MS);
+        assert!(tracker.watch(NodeId::POWER_SUPPLY));
+        assert!(tracker.watch(NodeId::POWER_SUPPLY));
+        assert!(!tracker.watch(NodeId::COOLBOX), "tracker is full");
+
+        assert!(!tracker.update(&beat(NodeId::COOLBOX, 1), 0));
+        assert_eq!(tracker.status(NodeId::COOLBOX, 0), Liveness::Unknown);
+        assert!(tracker.nodes(0).eq([(NodeId::POWER_SUPPLY, Liveness::Unknown)]));
+
+        let mut garbage = beat(NodeId::POWER_SUPPLY, 1);
+        garbage.node = 0xFF;
+        assert!(!tracker.update(&garbage, 0));
+        assert_eq!(tracker.status(NodeId::POWER_SUPPLY, 0), Liveness::Unknown);
+    }
+}
@@ -0,0 +1,293 @@
+//! Structured layout of the 11-bit CAN identifiers.
+//!
+//! ```text
+//!  10  9 | 8  7  6  5 | 4  3  2 | 1  0
+//! prio   | class      | kind    | instance
+//! ```
+//!
+//! Lower identifiers win arbitration, so priority comes first. Class and
+//! instance together name a node, kind tells its messages apart.
+
+use can_messages_trait::{CanMessage, MessageId};
+#[cfg(feature = "embedded-can")]
+use can_messages_trait::embedded_can::{Frame, Id, StandardId};
+use num_enum::{TryFromPrimitive, IntoPrimitive};
+
+const PRIORITY_SHIFT: u16 = 9;
+const CLASS_SHIFT: u16 = 5;
+const KIND_SHIFT: u16 = 2;
+const KIND_MASK: u8 = 0b111;
+const INSTANCE_MASK: u8 = 0b11;
+
+/// Arbitration priority of a message.
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, IntoPrimitive, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    Command = 0,
+    Control = 1,
+    Telemetry = 2,
+    Diagnostic = 3,
+}
+
+/// Kind of device on the trailer bus.
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, IntoPrimitive, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceClass {
+    /// Bus-wide messages not owned by any particular device.
+    System = 0,
+    /// Makita battery power supply.
+    PowerSupply = 1,
+    /// Cool box temperature controller.
+    CoolBox = 2,
+    /// Display and control panel.
+    Display = 3,
+    /// Computer attached to the bus.
+    Host = 15,
+}
+
+/// Address of a single node: device class and instance number.
+///
+/// Instance 0 addresses every node of the class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    pub class: DeviceClass,
+    pub instance: u8,
+}
+
+impl NodeId {
+    pub const SYSTEM: Self = Self::new(DeviceClass::System, 0);
+    pub const POWER_SUPPLY: Self = Self::new(DeviceClass::PowerSupply, 1);
+    pub const COOLBOX: Self = Self::new(DeviceClass::CoolBox, 1);
+    pub const DISPLAY: Self = Self::new(DeviceClass::Display, 1);
+    pub const HOST: Self = Self::new(DeviceClass::Host, 1);
+
+    pub const fn new(class: DeviceClass, instance: u8) -> Self {
+        assert!(instance <= INSTANCE_MASK, "node instance out of range");
+        Self { class, instance }
+    }
+
+    /// Pack into one byte for use in message payloads.
+    pub const fn raw(self) -> u8 {
+        (self.class as u8) << 2 | self.instance & INSTANCE_MASK
+    }
+
+    /// Unpack from a message payload byte.
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        let class = DeviceClass::try_from(raw >> 2).ok()?;
+        Some(Self::new(class, raw & INSTANCE_MASK))
+    }
+
+    /// Identifier of the node message `M` sent by this node, like
+    /// [`NodeMessage::id_for`] but usable in constants.
+    pub const fn message_id<M: NodeMessage>(self) -> MessageId {
+        let MessageId::Standard(template) = M::ID else {
+            panic!("node message identifiers follow the layout");
+        };
+        let node = (self.class as u16) << CLASS_SHIFT | (self.instance & INSTANCE_MASK) as u16;
+        MessageId::Standard(template & !(0b1111 << CLASS_SHIFT | INSTANCE_MASK as u16) | node)
+    }
+
+    /// Check if a message addressed to `self` is meant for `node`.
+    pub fn addresses(self, node: NodeId) -> bool {
+        self.class == node.class && (self.instance == 0 || self.instance == node.instance)
+    }
+}
+
+/// CAN identifier split into its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StructuredId {
+    pub priority: Priority,
+    pub node: NodeId,
+    /// Message kind, unique within the device class.
+    pub kind: u8,
+}
+
+impl StructuredId {
+    pub const fn new(priority: Priority, node: NodeId, kind: u8) -> Self {
+        assert!(kind <= KIND_MASK, "message kind out of range");
+        Self { priority, node, kind }
+    }
+
+    /// Same message for another instance of the node.
+    pub const fn with_instance(self, instance: u8) -> Self {
+        Self::new(self.priority, NodeId::new(self.node.class, instance), self.kind)
+    }
+
+    /// Same message sent by another node.
+    pub const fn with_node(self, node: NodeId) -> Self {
+        Self::new(self.priority, node, self.kind)
+    }
+
+    /// Raw 11-bit identifier.
+    ///
+    /// Fields set out of range through the public members are cut to their
+    /// width instead of spilling into their neighbours.
+    pub const fn raw(self) -> u16 {
+        (self.priority as u16) << PRIORITY_SHIFT
+            | (self.node.class as u16) << CLASS_SHIFT
+            | ((self.kind & KIND_MASK) as u16) << KIND_SHIFT
+            | (self.node.instance & INSTANCE_MASK) as u16
+    }
+
+    /// Split a raw identifier, if it follows the layout.
+    pub fn from_raw(raw: u16) -> Option<Self> {
+        if raw > 0x7FF {
+            return None;
+        }
+        let priority = Priority::try_from((raw >> PRIORITY_SHIFT) as u8).ok()?;
+        let class = DeviceClass::try_from((raw >> CLASS_SHIFT) as u8 & 0b1111).ok()?;
+        let kind = (raw >> KIND_SHIFT) as u8 & KIND_MASK;
+        let instance = raw as u8 & INSTANCE_MASK;
+        Some(Self::new(priority, NodeId::new(class, instance), kind))
+    }
+}
+
+impl From<StructuredId> for u16 {
+    fn from(id: StructuredId) -> u16 {
+        id.raw()
+    }
+}
+
+impl From<StructuredId> for MessageId {
+    fn from(id: StructuredId) -> MessageId {
+        MessageId::Standard(id.raw())
+    }
+}
+
+/// Message that every node sends on its own identifier.
+///
+/// The identifier given to `#[can_message]` is only a template: two nodes
+/// sending the same identifier at once would destroy each other's frames, so
+/// each one uses [`NodeMessage::id_for`] itself. Use [`NodeMessage::encode`]
+/// and [`NodeMessage::decode`] instead of the generic `try_encode`/`try_decode`.
+pub trait NodeMessage: CanMessage {
+    /// Raw [`NodeId`] of the sender, as carried in the payload.
+    fn sender(&self) -> u8;
+
+    /// Identifier of the message sent by `node`.
+    fn id_for(node: NodeId) -> StructuredId {
+        let template = StructuredId::from_raw(Self::ID.raw() as u16);
+        template.expect("node message identifiers follow the layout").with_node(node)
+    }
+
+    /// Build a frame on the identifier of the sending node.
+    #[cfg(feature = "embedded-can")]
+    fn encode<F: Frame>(&self) -> Option<F> {
+        let id = Self::id_for(NodeId::from_raw(self.sender())?);
+        F::new(Id::Standard(StandardId::new(id.raw())?), zerocopy::IntoBytes::as_bytes(self))
+    }
+
+    /// Decode the message of any node.
+    #[cfg(feature = "embedded-can")]
+    fn decode<F: Frame>(frame: &F) -> Option<&Self> {
+        let MessageId::Standard(raw) = MessageId::from(frame.id()) else {
+            return None;
+        };
+        let id = StructuredId::from_raw(raw)?;
+        if frame.is_remote_frame() || id != Self::id_for(id.node) {
+            return None;
+        }
+        let msg = Self::try_ref_from_bytes(frame.data()).ok()?;
+        (msg.sender() == id.node.raw()).then_some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CanId, Heartbeat};
+
+    #[test]
+    fn round_trip() {
+        let node = NodeId::new(DeviceClass::CoolBox, 3);
+        assert_eq!(NodeId::from_raw(node.raw()), Some(node));
+        assert_eq!(node.raw(), 0b1011);
+
+        let id = StructuredId::new(Priority::Diagnostic, node, 5);
+        assert_eq!(id.raw(), 0x657);
+        assert_eq!(StructuredId::from_raw(id.raw()), Some(id));
+        assert_eq!(u16::from(id), id.raw());
+        assert_eq!(MessageId::from(id), MessageId::Standard(id.raw()));
+        assert_eq!(id.with_instance(1).raw(), 0x655);
+        assert_eq!(id.with_node(NodeId::HOST).raw(), 0x7F5);
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(StructuredId::from_raw(0x800), None);
+        // No device class 4.
+        assert_eq!(StructuredId::from_raw(0x080), None);
+        assert_eq!(NodeId::from_raw(4 << 2), None);
+
+        let mut id = StructuredId::new(Priority::Command, NodeId::POWER_SUPPLY, 0);
+        id.kind = 0xFF;
+        id.node.instance = 0xFF;
+        assert_eq!(id.raw(), 0x03F);
+        assert_eq!(id.node.raw(), 0b0111);
+    }
+
+    #[test]
+    #[should_panic(expected = "message kind out of range")]
+    fn kind_out_of_range() {
+        StructuredId::new(Priority::Command, NodeId::SYSTEM, 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "node instance out of range")]
+    fn instance_out_of_range() {
+        NodeId::new(DeviceClass::Display, 4);
+    }
+
+    #[test]
+    fn addresses() {
+        let all = NodeId::new(DeviceClass::PowerSupply, 0);
+        let second = NodeId::new(DeviceClass::PowerSupply, 2);
+        assert!(all.addresses(NodeId::POWER_SUPPLY));
+        assert!(all.addresses(second));
+        assert!(NodeId::POWER_SUPPLY.addresses(NodeId::POWER_SUPPLY));
+        assert!(!NodeId::POWER_SUPPLY.addresses(second));
+        assert!(!all.addresses(NodeId::COOLBOX));
+    }
+
+    #[test]
+    fn node_message() {
+        assert_eq!(Heartbeat::id_for(NodeId::POWER_SUPPLY).raw(), 0x63D);
+        assert_eq!(Heartbeat::id_for(NodeId::COOLBOX).raw(), 0x65D);
+        assert_eq!(Heartbeat::id_for(NodeId::DISPLAY).raw(), 0x67D);
+        assert_eq!(Heartbeat::id_for(NodeId::HOST).raw(), 0x7FD);
+        for node in [NodeId::POWER_SUPPLY, NodeId::COOLBOX, NodeId::DISPLAY, NodeId::HOST] {
+            assert_eq!(node.message_id::<Heartbeat>(), Heartbeat::id_for(node).into());
+        }
+    }
+
+    /// Identifiers on the wire, changing any of them breaks deployed nodes.
+    #[test]
+    fn can_ids() {
+        let ids = [
+            (CanId::POWEROFF, 0x021),
+            (CanId::ENTERBOOTLOADER, 0x004),
+            (CanId::BATTERY, 0x421),
+            (CanId::BATTERYDETAIL, 0x425),
+            (CanId::BATTERYCELLS, 0x429),
+            (CanId::BATTERYMODEL, 0x42D),
+            (CanId::BATTERYSTATE, 0x431),
+            (CanId::COOLBOX, 0x441),
+            (CanId::PROTECTION, 0x221),
+            (CanId::PARAMREQUEST, 0x200),
+            (CanId::PARAMRESPONSE, 0x204),
+            (CanId::IDENTIFYREQUEST, 0x208),
+            (CanId::UPDATEREQUEST, 0x20C),
+            (CanId::UPDATERESPONSE, 0x210),
+            (CanId::TIMESYNC, 0x214),
+            (CanId::LASTSHUTDOWN, 0x621),
+            (CanId::HEARTBEAT, 0x61C),
+            (CanId::CANDIAG, 0x618),
+            (CanId::FIRMWAREID, 0x614),
+            (CanId::DEVICEUID, 0x610),
+        ];
+        for (id, raw) in ids {
+            assert_eq!(u16::from(id), raw, "{id:?}");
+            assert_eq!(id.structured().raw(), raw, "{id:?}");
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Firmware and hardware identification.
+//!
+//! Every node sends [`FirmwareId`] and both halves of [`DeviceUid`] at startup
+//! and whenever it receives an [`IdentifyRequest`] meant for it. Build
+//! [`FirmwareId`] with [`firmware_id!`](crate::firmware_id), which picks up
+//! the version and git hash of the calling crate, once its build script
+//! called [`emit_git_env`].
+
+use crate::{CanId, NodeId, NodeMessage, prelude::*};
+#[cfg(feature = "embedded-can")]
+use can_messages_trait::embedded_can::Frame;
+
+/// Ask nodes to identify themselves.
+#[can_message(CanId::IDENTIFYREQUEST)]
+pub struct IdentifyRequest {
+    /// Raw [`NodeId`] of the node to answer, [`NodeId::SYSTEM`] for all.
+    pub target: u8,
+}
+
+impl IdentifyRequest {
+    pub fn new(target: NodeId) -> Self {
+        Self { target: target.raw() }
+    }
+
+    /// Check if `node` has to answer.
+    pub fn is_for(&self, node: NodeId) -> bool {
+        self.target == NodeId::SYSTEM.raw() || NodeId::from_raw(self.target).is_some_and(|t| t.addresses(node))
+    }
+}
+
+/// Build of the firmware running on a node, sent on the identifier of each node.
+#[can_message(CanId::FIRMWAREID)]
+pub struct FirmwareId {
+    /// Raw [`NodeId`] of the sender.
+    pub node: u8,
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    /// `FLAG_*` bits.
+    pub flags: u8,
+    /// First six hex digits of the git commit, zero if unknown.
+    pub git_hash: [u8; 3],
+}
+
+impl FirmwareId {
+    /// Built without optimizations and with debug assertions.
+    pub const FLAG_DEBUG: u8 = 1 << 0;
+    /// Built from a work tree with uncommitted changes.
+    pub const FLAG_DIRTY: u8 = 1 << 1;
+
+    /// Parse a `major.minor.patch` crate version and a hex git hash.
+    ///
+    /// Parts that do not parse end up as zero. A const fn, so that
+    /// [`firmware_id!`](crate::firmware_id) leaves no parser in the firmware.
+    pub const fn new(node: NodeId, version: &str, git_hash: &str, flags: u8) -> Self {
+        let mut rest = version.as_bytes();
+        let mut version = [0; 3];
+        let mut part = 0;
+        while part < version.len() {
+            let mut end = 0;
+            while end < rest.len() && !matches!(rest[end], b'.' | b'-' | b'+') {
+                end += 1;
+            }
+            let (digits, tail) = rest.split_at(end);
+            version[part] = match parse_decimal(digits) {
+                Some(value) => value,
+                None => 0,
+            };
+            part += 1;
+            if tail.is_empty() {
+                break;
+            }
+            rest = tail.split_at(1).1;
+        }
+        let digits = git_hash.as_bytes();
+        let mut hash = [0; 3];
+        let mut byte = 0;
+        while byte < hash.len() && 2 * byte + 1 < digits.len() {
+            hash[byte] = match (hex_digit(digits[2 * byte]), hex_digit(digits[2 * byte + 1])) {
+                (Some(high), Some(low)) => high << 4 | low,
+                _ => 0,
+            };
+            byte += 1;
+        }
+        let [major, minor, patch] = version;
+        Self { node: node.raw(), major, minor, patch, flags, git_hash: hash }
+    }
+
+    pub fn is_debug(&self) -> bool {
+        self.flags & Self::FLAG_DEBUG != 0
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.flags & Self::FLAG_DIRTY != 0
+    }
+}
+
+/// Value of a decimal number up to 255.
+const fn parse_decimal(digits: &[u8]) -> Option<u8> {
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: u8 = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        let digit = match digits[i] {
+            digit @ b'0'..=b'9' => digit - b'0',
+            _ => return None,
+        };
+        value = match value.checked_mul(10) {
+            Some(tens) => match tens.checked_add(digit) {
+                Some(value) => value,
+                None => return None,
+            },
+            None => return None,
+        };
+        i += 1;
+    }
+    Some(value)
+}
+
+const fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl NodeMessage for FirmwareId {
+    fn sender(&self) -> u8 {
+        self.node
+    }
+}
+
+impl core::fmt::Display for FirmwareId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c] = self.git_hash;
+        write!(f, "{}.{}.{} {a:02x}{b:02x}{c:02x}", self.major, self.minor, self.patch)?;
+        if self.is_dirty() {
+            f.write_str("-dirty")?;
+        }
+        f.write_str(if self.is_debug() { " debug" } else { " release" })
+    }
+}
+
+/// `FirmwareId` of the calling crate for `node`.
+///
+/// The git hash comes from `GIT_HASH` and `GIT_DIRTY` set by the build script
+/// of the calling crate, see [`emit_git_env`].
+#[macro_export]
+macro_rules! firmware_id {
+    ($node:expr) => {
+        const {
+            let mut flags = 0;
+            if cfg!(debug_assertions) {
+                flags |= $crate::FirmwareId::FLAG_DEBUG;
+            }
+            if matches!(option_env!("GIT_DIRTY"), Some(dirty) if matches!(dirty.as_bytes(), b"1")) {
+                flags |= $crate::FirmwareId::FLAG_DIRTY;
+            }
+            let git_hash = match option_env!("GIT_HASH") {
+                Some(hash) => hash,
+                None => "",
+            };
+            $crate::FirmwareId::new($node, env!("CARGO_PKG_VERSION"), git_hash, flags)
+        }
+    };
+}
+
+/// Output of a git command, `None` outside of a work tree.
+#[cfg(feature = "std")]
+fn git(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("git").args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Set `GIT_HASH` and `GIT_DIRTY` for [`firmware_id!`](crate::firmware_id),
+/// call from the build script.
+///
+/// Reruns the build script when the commit or the index changes.
+#[cfg(feature = "std")]
+pub fn emit_git_env() {
+    let hash = git(&["rev-parse", "--short=6", "HEAD"]).unwrap_or_default();
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    println!("cargo:rustc-env=GIT_HASH={hash}");
+    println!("cargo:rustc-env=GIT_DIRTY={}", dirty as u8);
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/logs/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+    }
+}
+
+/// Half of the 96-bit unique ID of the microcontroller, sent on the
+/// identifier of each node.
+#[can_message(CanId::DEVICEUID)]
+pub struct DeviceUid {
+    /// Raw [`NodeId`] of the sender.
+    pub node: u8,
+    /// 0 for the lower, 1 for the upper six bytes.
+    pub part: u8,
+    pub uid: [u8; 6],
+}
+
+impl DeviceUid {
+    /// Both halves of `uid`.
+    pub fn split(node: NodeId, uid: &[u8; 12]) -> [Self; 2] {
+        Self::split_raw(node.raw(), uid)
+    }
+
+    fn split_raw(node: u8, uid: &[u8; 12]) -> [Self; 2] {
+        [0, 1].map(|part| Self {
+            node,
+            part,
+            uid: uid[part as usize * 6..][..6].try_into().unwrap(),
+        })
+    }
+
+    /// Put a half into its place in the full ID.
+    pub fn merge_into(&self, uid: &mut [u8; 12]) {
+        if let Some(half) = uid.chunks_exact_mut(6).nth(self.part.into()) {
+            half.copy_from_slice(&self.uid);
+        }
+    }
+}
+
+impl NodeMessage for DeviceUid {
+    fn sender(&self) -> u8 {
+        self.node
+    }
+}
+
+/// Frames a node identifies itself with: `firmware`, then both halves of `uid`.
+///
+/// ```ignore
+/// for frame in identification::<Frame>(firmware_id!(NODE_ID), uid::uid()) {
+///     tx.write(&frame).await;
+/// }
+/// ```
+#[cfg(feature = "embedded-can")]
+pub fn identification<F: Frame>(firmware: FirmwareId, uid: &[u8; 12]) -> impl Iterator<Item = F> {
+    let [low, high] = DeviceUid::split_raw(firmware.node, uid);
+    [firmware.encode(), low.encode(), high.encode()].into_iter().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_version() {
+        let id = FirmwareId::new(NodeId::COOLBOX, "1.12.3", "0a1B2c3", 0);
+        assert_eq!((id.major, id.minor, id.patch), (1, 12, 3));
+        assert_eq!(id.git_hash, [0x0a, 0x1b, 0x2c]);
+        assert_eq!(id.node, NodeId::COOLBOX.raw());
+
+        let id = FirmwareId::new(NodeId::COOLBOX, "2.0.1-rc.1+build", "", 0);
+        assert_eq!((id.major, id.minor, id.patch), (2, 0, 1));
+        assert_eq!(id.git_hash, [0; 3]);
+    }
+
+    #[test]
+    fn unparsed_parts_are_zero() {
+        let id = FirmwareId::new(NodeId::COOLBOX, "1.256.x", "zz12", 0);
+        assert_eq!((id.major, id.minor, id.patch), (1, 0, 0));
+        assert_eq!(id.git_hash, [0, 0x12, 0]);
+
+        let id = FirmwareId::new(NodeId::COOLBOX, "3", "1", 0);
+        assert_eq!((id.major, id.minor, id.patch), (3, 0, 0));
+        assert_eq!(id.git_hash, [0; 3]);
+    }
+
+    #[test]
+    fn in_constants() {
+        const ID: FirmwareId = crate::firmware_id!(NodeId::POWER_SUPPLY);
+        assert_eq!(ID.node, NodeId::POWER_SUPPLY.raw());
+        assert_eq!(ID.major, env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap());
+        assert_eq!(ID.minor, env!("CARGO_PKG_VERSION_MINOR").parse().unwrap());
+    }
+}
@@ -1,40 +1,160 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 use num_enum::{TryFromPrimitive, IntoPrimitive};
 
-pub use can_messages_trait::prelude::*;
+mod id;
+pub use id::*;
+
+mod params;
+pub use params::*;
+
+mod heartbeat;
+pub use heartbeat::*;
+
+mod units;
+pub use units::*;
+
+mod diagnostics;
+pub use diagnostics::*;
+
+mod ident;
+pub use ident::*;
+
+mod time;
+pub use time::*;
+
+mod battery;
+pub use battery::*;
+
+mod protection;
+pub use protection::*;
+
+pub mod boot;
+pub use boot::EnterBootloader;
+
+pub use can_messages_trait::{prelude::*, CounterCheck, MessageId, filter, supervisor};
+pub use can_messages_trait::supervisor::{BusState, BusStatus, Counters};
+#[cfg(feature = "cache")]
+pub use can_messages_trait::cache;
+#[cfg(feature = "clock")]
+pub use can_messages_trait::clock;
+#[cfg(feature = "scheduler")]
+pub use can_messages_trait::scheduler;
+#[cfg(feature = "isotp")]
+pub use can_messages_trait::isotp;
 
 pub mod prelude {
     pub use can_messages_trait::prelude::*;
+    pub use crate::NodeMessage;
 }
 
 pub const BITRATE: u32 = 1_000_000;
 
+/// [`BITRATE`] from the 48 MHz APB clock of the nodes, in 8 time quanta
+/// sampling at 87.5 %. `Can::set_bitrate` comes to the same, but works it
+/// out at run time in a few hundred bytes of flash.
+#[cfg(feature = "stm32f042f6")]
+pub const BIT_TIMING: embassy_stm32::can::util::NominalBitTiming = {
+    use core::num::{NonZeroU16, NonZeroU8};
+    const APB_HZ: u32 = 48_000_000;
+    const QUANTA: u32 = 8;
+    assert!(APB_HZ % (BITRATE * QUANTA) == 0, "bit rate not reachable from the APB clock");
+    embassy_stm32::can::util::NominalBitTiming {
+        prescaler: NonZeroU16::new((APB_HZ / BITRATE / QUANTA) as u16).unwrap(),
+        seg1: NonZeroU8::new(6).unwrap(),
+        seg2: NonZeroU8::new(1).unwrap(),
+        sync_jump_width: NonZeroU8::MIN,
+    }
+};
+
 #[repr(u16)]
 #[derive(Debug, TryFromPrimitive, IntoPrimitive, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CanId {
-    POWEROFF = 0b_000_0000_0001,
-    BATTERY = 0b_001_0001_0001,
-    COOLBOX = 0b_001_0010_0001,
+    POWEROFF = StructuredId::new(Priority::Command, NodeId::POWER_SUPPLY, 0).raw(),
+    ENTERBOOTLOADER = StructuredId::new(Priority::Command, NodeId::SYSTEM, 1).raw(),
+    BATTERY = StructuredId::new(Priority::Telemetry, NodeId::POWER_SUPPLY, 0).raw(),
+    BATTERYDETAIL = StructuredId::new(Priority::Telemetry, NodeId::POWER_SUPPLY, 1).raw(),
+    BATTERYCELLS = StructuredId::new(Priority::Telemetry, NodeId::POWER_SUPPLY, 2).raw(),
+    BATTERYMODEL = StructuredId::new(Priority::Telemetry, NodeId::POWER_SUPPLY, 3).raw(),
+    BATTERYSTATE = StructuredId::new(Priority::Telemetry, NodeId::POWER_SUPPLY, 4).raw(),
+    COOLBOX = StructuredId::new(Priority::Telemetry, NodeId::COOLBOX, 0).raw(),
+    PROTECTION = StructuredId::new(Priority::Control, NodeId::POWER_SUPPLY, 0).raw(),
+    PARAMREQUEST = StructuredId::new(Priority::Control, NodeId::SYSTEM, 0).raw(),
+    PARAMRESPONSE = StructuredId::new(Priority::Control, NodeId::SYSTEM, 1).raw(),
+    IDENTIFYREQUEST = StructuredId::new(Priority::Control, NodeId::SYSTEM, 2).raw(),
+    /// Template only, see [`boot::request_id`].
+    UPDATEREQUEST = StructuredId::new(Priority::Control, NodeId::SYSTEM, 3).raw(),
+    /// Template only, see [`boot::response_id`].
+    UPDATERESPONSE = StructuredId::new(Priority::Control, NodeId::SYSTEM, 4).raw(),
+    TIMESYNC = StructuredId::new(Priority::Control, NodeId::SYSTEM, 5).raw(),
+    LASTSHUTDOWN = StructuredId::new(Priority::Diagnostic, NodeId::POWER_SUPPLY, 0).raw(),
+    /// Template only, see [`Heartbeat`].
+    HEARTBEAT = StructuredId::new(Priority::Diagnostic, NodeId::SYSTEM, 7).raw(),
+    /// Template only, see [`CanDiagnostics`].
+    CANDIAG = StructuredId::new(Priority::Diagnostic, NodeId::SYSTEM, 6).raw(),
+    /// Template only, see [`FirmwareId`].
+    FIRMWAREID = StructuredId::new(Priority::Diagnostic, NodeId::SYSTEM, 5).raw(),
+    /// Template only, see [`DeviceUid`].
+    DEVICEUID = StructuredId::new(Priority::Diagnostic, NodeId::SYSTEM, 4).raw(),
+}
+
+impl CanId {
+    /// Fields of this identifier.
+    pub fn structured(self) -> StructuredId {
+        StructuredId::from_raw(self.into()).expect("CanId follows the identifier layout")
+    }
 }
 
+/// Switch a node off.
 #[can_message(CanId::POWEROFF)]
-pub struct PowerOff;
+pub struct PowerOff {
+    /// Raw [`NodeId`] of the node to switch off.
+    pub target: u8,
+    #[can(counter)]
+    pub counter: u8,
+    #[can(crc)]
+    pub crc: u8,
+}
+
+impl PowerOff {
+    /// The checksum is filled in when encoding.
+    pub fn new(target: NodeId, counter: u8) -> Self {
+        Self { target: target.raw(), counter, crc: 0 }
+    }
+}
 
 #[can_message(CanId::BATTERY)]
 pub struct BatteryData {
-    pub battery_voltage_mv: u16,
-    pub output_voltage_mv: i16,
-    pub output_current_ma: i16,
+    pub battery_voltage: Millivolts<u16>,
+    pub output_voltage: Millivolts<i16>,
+    pub output_current: Milliamps<i16>,
 }
 
 #[can_message(CanId::COOLBOX)]
 pub struct CoolBox {
-    pub box_temperature_deg10: i16,
+    pub box_temperature: DeciDegrees<i16>,
 }
 
-/*
-can_variant!{BatterySignals {
+can_variant!{
+/// Every message on the trailer bus.
+pub AnyMessage {
+    PowerOff(PowerOff),
+    Battery(BatteryData),
+    BatteryDetail(BatteryDetail),
+    BatteryCells(BatteryCells),
+    BatteryModel(BatteryModel),
+    BatteryState(BatteryState),
+    Protection(ProtectionEvent),
+    LastShutdown(LastShutdown),
+    CoolBox(CoolBox),
+    ParamRequest(ParamRequest),
+    ParamResponse(ParamResponse),
+    IdentifyRequest(IdentifyRequest),
+    EnterBootloader(EnterBootloader),
+    TimeSync(TimeSync),
+}}
+
+can_variant!{pub BatterySignals {
     Pow(PowerOff),
     Bat(BatteryData),
-}}*/
+}}
@@ -0,0 +1,315 @@
+//! Parameter read/write service.
+//!
+//! A requester sends [`ParamRequest`] to a node, the node looks the parameter
+//! up in its [`ParamTable`] and answers with [`ParamResponse`].
+
+use can_messages_trait::SignalValue;
+use crate::{CanId, NodeId, prelude::*};
+
+/// Requested operation.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq)]
+pub enum ParamOp {
+    Read = 0,
+    Write = 1,
+}
+
+/// Type of a parameter value.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Bool = 0,
+    U16 = 1,
+    I16 = 2,
+    U32 = 3,
+    I32 = 4,
+    F32 = 5,
+}
+
+/// Outcome of a request.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq)]
+pub enum ParamStatus {
+    Ok = 0,
+    /// No parameter with this index.
+    UnknownParam = 1,
+    /// The parameter cannot be written.
+    ReadOnly = 2,
+    /// The value type does not match the parameter type.
+    TypeMismatch = 3,
+    /// The value is outside of the allowed range.
+    OutOfRange = 4,
+}
+
+impl SignalValue for ParamOp {
+    const SIGNED: bool = false;
+}
+
+impl SignalValue for ParamKind {
+    const SIGNED: bool = false;
+}
+
+impl SignalValue for ParamStatus {
+    const SIGNED: bool = false;
+}
+
+/// Typed parameter value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamValue {
+    Bool(bool),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+}
+
+impl ParamValue {
+    pub fn kind(self) -> ParamKind {
+        match self {
+            ParamValue::Bool(_) => ParamKind::Bool,
+            ParamValue::U16(_) => ParamKind::U16,
+            ParamValue::I16(_) => ParamKind::I16,
+            ParamValue::U32(_) => ParamKind::U32,
+            ParamValue::I32(_) => ParamKind::I32,
+            ParamValue::F32(_) => ParamKind::F32,
+        }
+    }
+
+    /// Raw 32-bit wire representation.
+    pub fn raw(self) -> u32 {
+        match self {
+            ParamValue::Bool(v) => v as u32,
+            ParamValue::U16(v) => v as u32,
+            ParamValue::I16(v) => v as i32 as u32,
+            ParamValue::U32(v) => v,
+            ParamValue::I32(v) => v as u32,
+            ParamValue::F32(v) => v.to_bits(),
+        }
+    }
+
+    /// Decode from the wire representation.
+    pub fn from_raw(kind: ParamKind, raw: u32) -> Self {
+        match kind {
+            ParamKind::Bool => ParamValue::Bool(raw != 0),
+            ParamKind::U16 => ParamValue::U16(raw as u16),
+            ParamKind::I16 => ParamValue::I16(raw as i16),
+            ParamKind::U32 => ParamValue::U32(raw),
+            ParamKind::I32 => ParamValue::I32(raw as i32),
+            ParamKind::F32 => ParamValue::F32(f32::from_bits(raw)),
+        }
+    }
+}
+
+#[can_message(CanId::PARAMREQUEST)]
+pub struct ParamRequest {
+    /// Raw [`NodeId`] of the addressed node.
+    pub target: u8,
+    pub op: ParamOp,
+    pub index: u8,
+    pub kind: ParamKind,
+    /// Value to write, ignored on read.
+    pub value: u32,
+}
+
+#[can_message(CanId::PARAMRESPONSE)]
+pub struct ParamResponse {
+    /// Raw [`NodeId`] of the answering node.
+    pub source: u8,
+    pub status: ParamStatus,
+    pub index: u8,
+    pub kind: ParamKind,
+    /// Current value after the operation.
+    pub value: u32,
+}
+
+impl ParamRequest {
+    pub fn read(target: NodeId, index: u8) -> Self {
+        Self {
+            target: target.raw(),
+            op: ParamOp::Read,
+            index,
+            kind: ParamKind::U32,
+            value: 0.into(),
+        }
+    }
+
+    pub fn write(target: NodeId, index: u8, value: ParamValue) -> Self {
+        Self {
+            target: target.raw(),
+            op: ParamOp::Write,
+            index,
+            kind: value.kind(),
+            value: value.raw().into(),
+        }
+    }
+}
+
+impl ParamResponse {
+    /// Value carried by the response, if the request succeeded.
+    pub fn value(&self) -> Option<ParamValue> {
+        (self.status == ParamStatus::Ok).then(|| ParamValue::from_raw(self.kind, self.value.get()))
+    }
+}
+
+/// One entry of a node parameter table.
+pub struct Param {
+    pub index: u8,
+    pub name: &'static str,
+    pub get: fn() -> ParamValue,
+    /// Setter, `None` for read-only parameters.
+    pub set: Option<fn(ParamValue) -> Result<(), ParamStatus>>,
+}
+
+/// All parameters of one node.
+pub struct ParamTable {
+    node: NodeId,
+    params: &'static [Param],
+}
+
+impl ParamTable {
+    pub const fn new(node: NodeId, params: &'static [Param]) -> Self {
+        Self { node, params }
+    }
+
+    pub fn params(&self) -> &'static [Param] {
+        self.params
+    }
+
+    fn respond(&self, index: u8, result: Result<ParamValue, ParamStatus>) -> ParamResponse {
+        let (status, value) = match result {
+            Ok(value) => (ParamStatus::Ok, value),
+            Err(status) => (status, ParamValue::U32(0)),
+        };
+        ParamResponse {
+            source: self.node.raw(),
+            status,
+            index,
+            kind: value.kind(),
+            value: value.raw().into(),
+        }
+    }
+
+    /// Answer a request, or `None` if it is addressed to another node.
+    pub fn handle(&self, req: &ParamRequest) -> Option<ParamResponse> {
+        if !NodeId::from_raw(req.target)?.addresses(self.node) {
+            return None;
+        }
+        let result = match self.params.iter().find(|p| p.index == req.index) {
+            None => Err(ParamStatus::UnknownParam),
+            Some(param) => match req.op {
+                ParamOp::Read => Ok((param.get)()),
+                ParamOp::Write => {
+                    let current = (param.get)();
+                    match param.set {
+                        None => Err(ParamStatus::ReadOnly),
+                        Some(_) if current.kind() != req.kind => Err(ParamStatus::TypeMismatch),
+                        Some(set) => set(ParamValue::from_raw(req.kind, req.value.get()))
+                            .map(|()| (param.get)()),
+                    }
+                }
+            },
+        };
+        Some(self.respond(req.index, result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU16, Ordering};
+    use crate::DeviceClass;
+
+    static LIMIT: AtomicU16 = AtomicU16::new(100);
+
+    static TABLE: ParamTable = ParamTable::new(
+        NodeId::POWER_SUPPLY,
+        &[
+            Param {
+                index: 0,
+                name: "version",
+                get: || ParamValue::U32(7),
+                set: None,
+            },
+            Param {
+                index: 1,
+                name: "limit",
+                get: || ParamValue::U16(LIMIT.load(Ordering::Relaxed)),
+                set: Some(|value| match value {
+                    ParamValue::U16(v @ 10..=1000) => {
+                        LIMIT.store(v, Ordering::Relaxed);
+                        Ok(())
+                    }
+                    _ => Err(ParamStatus::OutOfRange),
+                }),
+            },
+        ],
+    );
+
+    fn status(req: &ParamRequest) -> ParamStatus {
+        TABLE.handle(req).expect("request is for this node").status
+    }
+
+    #[test]
+    fn other_node() {
+        assert!(TABLE.handle(&ParamRequest::read(NodeId::COOLBOX, 0)).is_none());
+        let second = NodeId::new(DeviceClass::PowerSupply, 2);
+        assert!(TABLE.handle(&ParamRequest::read(second, 0)).is_none());
+        let mut garbage = ParamRequest::read(NodeId::POWER_SUPPLY, 0);
+        garbage.target = 0xFF;
+        assert!(TABLE.handle(&garbage).is_none());
+
+        // Instance 0 addresses every power supply.
+        let all = NodeId::new(DeviceClass::PowerSupply, 0);
+        assert!(TABLE.handle(&ParamRequest::read(all, 0)).is_some());
+    }
+
+    #[test]
+    fn read() {
+        let resp = TABLE.handle(&ParamRequest::read(NodeId::POWER_SUPPLY, 0)).unwrap();
+        assert_eq!(resp.source, NodeId::POWER_SUPPLY.raw());
+        assert_eq!(resp.index, 0);
+        assert_eq!(resp.value(), Some(ParamValue::U32(7)));
+    }
+
+    #[test]
+    fn errors() {
+        let node = NodeId::POWER_SUPPLY;
+        assert_eq!(status(&ParamRequest::read(node, 9)), ParamStatus::UnknownParam);
+        assert_eq!(status(&ParamRequest::write(node, 9, ParamValue::U16(1))), ParamStatus::UnknownParam);
+        assert_eq!(status(&ParamRequest::write(node, 0, ParamValue::U32(8))), ParamStatus::ReadOnly);
+        assert_eq!(status(&ParamRequest::write(node, 1, ParamValue::I16(50))), ParamStatus::TypeMismatch);
+        assert_eq!(status(&ParamRequest::write(node, 1, ParamValue::U16(5000))), ParamStatus::OutOfRange);
+
+        let resp = TABLE.handle(&ParamRequest::write(node, 1, ParamValue::U16(5000))).unwrap();
+        assert_eq!(resp.index, 1);
+        assert_eq!(resp.value(), None);
+    }
+
+    #[test]
+    fn write() {
+        assert_eq!(status(&ParamRequest::write(NodeId::POWER_SUPPLY, 1, ParamValue::U16(5))), ParamStatus::OutOfRange);
+        assert_eq!(LIMIT.load(Ordering::Relaxed), 100);
+
+        let resp = TABLE.handle(&ParamRequest::write(NodeId::POWER_SUPPLY, 1, ParamValue::U16(250))).unwrap();
+        assert_eq!(resp.status, ParamStatus::Ok);
+        assert_eq!(resp.index, 1);
+        assert_eq!(resp.value(), Some(ParamValue::U16(250)));
+        assert_eq!(LIMIT.load(Ordering::Relaxed), 250);
+    }
+
+    #[test]
+    fn value_round_trip() {
+        let values = [
+            ParamValue::Bool(true),
+            ParamValue::U16(0xFFFF),
+            ParamValue::I16(-2),
+            ParamValue::U32(0xDEAD_BEEF),
+            ParamValue::I32(i32::MIN),
+            ParamValue::F32(-1.5),
+        ];
+        for value in values {
+            assert_eq!(ParamValue::from_raw(value.kind(), value.raw()), value);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Protection of the power supply and why it switched off.
+//!
+//! The power supply sends [`ProtectionEvent`] whenever a protection changes
+//! its level, and repeats warnings until they clear or trip. After a restart
+//! it reports why it switched off last time with [`LastShutdown`], at
+//! startup and when asked to identify itself.
+
+use can_messages_trait::SignalValue;
+use crate::{CanId, Millivolts, prelude::*};
+
+/// What is being protected against.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protection {
+    /// Battery voltage, in mV.
+    Undervoltage = 0,
+    /// Output current over time, in mA.
+    Overcurrent = 1,
+    /// Output current, in mA.
+    ShortCircuit = 2,
+}
+
+/// How close a protection is to switching off.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtectionLevel {
+    Normal = 0,
+    /// Switching off soon unless things improve.
+    Warning = 1,
+    /// Switching off now.
+    Tripped = 2,
+    /// Tripped too often, off until reset.
+    Latched = 3,
+}
+
+/// Why the power supply switched off.
+#[repr(u8)]
+#[derive(Debug, TryFromBytes, IntoBytes, Immutable, KnownLayout, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShutdownReason {
+    /// Nothing recorded, e.g. the pack was pulled.
+    Unknown = 0,
+    /// Power button held.
+    Button = 1,
+    /// [`PowerOff`](crate::PowerOff) received.
+    Command = 2,
+    Undervoltage = 3,
+}
+
+impl SignalValue for Protection {
+    const SIGNED: bool = false;
+}
+
+impl SignalValue for ProtectionLevel {
+    const SIGNED: bool = false;
+}
+
+impl SignalValue for ShutdownReason {
+    const SIGNED: bool = false;
+}
+
+impl ShutdownReason {
+    pub fn from_raw(raw: u8) -> Self {
+        match raw {
+            1 => Self::Button,
+            2 => Self::Command,
+            3 => Self::Undervoltage,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// A protection changed its level or still warns.
+///
+/// The output protections switch only the 12 V output off, and back on by
+/// themselves a few times before they latch.
+#[can_message(CanId::PROTECTION)]
+pub struct ProtectionEvent {
+    pub protection: Protection,
+    pub level: ProtectionLevel,
+    /// Measured value, unit depending on `protection`.
+    pub value: u16,
+    /// Threshold of `level`, or of the warning when back to normal.
+    pub limit: u16,
+}
+
+impl ProtectionEvent {
+    pub fn new(protection: Protection, level: ProtectionLevel, value: u16, limit: u16) -> Self {
+        Self { protection, level, value: value.into(), limit: limit.into() }
+    }
+}
+
+/// Why the power supply switched off before its last start.
+#[can_message(CanId::LASTSHUTDOWN)]
+pub struct LastShutdown {
+    pub reason: ShutdownReason,
+    pub battery_voltage: Millivolts<u16>,
+    /// Bus time of the shutdown, 0 if the clock was not synchronised.
+    #[can(unit = "s")]
+    pub time_s: u32,
+}
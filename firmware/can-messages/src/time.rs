@@ -0,0 +1,24 @@
+//! Bus-wide time.
+//!
+//! A single node on the bus, the time master, sends [`TimeSync`] every
+//! [`TIME_SYNC_PERIOD_MS`]. That is the display, or a host on a bus without
+//! one. The other nodes keep a [`SyncedClock`](crate::clock::SyncedClock)
+//! from it, so what they log and report can be lined up.
+
+use crate::{CanId, prelude::*};
+
+/// How often the time master sends its time.
+pub const TIME_SYNC_PERIOD_MS: u64 = 1000;
+
+/// Current time of the master.
+#[can_message(CanId::TIMESYNC)]
+pub struct TimeSync {
+    /// Since the Unix epoch if the master knows the wall time, since its start otherwise.
+    pub time_ms: u64,
+}
+
+impl TimeSync {
+    pub fn new(time_ms: u64) -> Self {
+        Self { time_ms: time_ms.into() }
+    }
+}
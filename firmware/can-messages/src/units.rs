@@ -0,0 +1,161 @@
+//! Physical unit newtypes for message fields.
+//!
+//! Each unit wraps the integer that goes on the wire, e.g. `Millivolts<u16>`.
+//! Inside `#[can_message]` the integer is turned into its little-endian
+//! wire type like any other field.
+
+use can_messages_trait::SignalValue;
+use core::fmt;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, little_endian as le};
+
+/// Integer that can carry a unit value.
+pub trait UnitValue: Copy {
+    /// Native integer type.
+    type Native: Copy;
+
+    fn native(self) -> Self::Native;
+    fn to_i64(self) -> i64;
+}
+
+macro_rules! unit_value {
+    ($($wire:ty => $native:ty),* $(,)?) => {
+        $(
+            impl UnitValue for $wire {
+                type Native = $native;
+
+                fn native(self) -> $native {
+                    self.into()
+                }
+
+                fn to_i64(self) -> i64 {
+                    <$native>::from(self).into()
+                }
+            }
+        )*
+    }
+}
+
+unit_value!(
+    u8 => u8, i8 => i8, u16 => u16, i16 => i16, u32 => u32, i32 => i32,
+    le::U16 => u16, le::I16 => i16, le::U32 => u32, le::I32 => i32,
+);
+
+/// Write `raw / 10^decimals` as a fixed-point decimal number.
+fn write_fixed(f: &mut fmt::Formatter<'_>, raw: i64, decimals: u32, unit: &str) -> fmt::Result {
+    let scale = 10_u64.pow(decimals);
+    let sign = if raw < 0 { "-" } else { "" };
+    let abs = raw.unsigned_abs();
+    write!(f, "{sign}{}.{:0width$} {unit}", abs / scale, abs % scale, width = decimals as usize)
+}
+
+macro_rules! unit {
+    ($(#[$attr:meta])* $name:ident, $unit:literal, $display_unit:literal, $decimals:literal) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        #[derive(FromBytes, IntoBytes, Immutable, KnownLayout)]
+        pub struct $name<T>(pub T);
+
+        impl<T: UnitValue> $name<T> {
+            /// Raw value in the unit of the type name.
+            pub fn get(self) -> T::Native {
+                self.0.native()
+            }
+
+            /// Value in the SI base unit.
+            pub fn to_si(self) -> f32 {
+                self.0.to_i64() as f32 / 10_u32.pow($decimals) as f32
+            }
+        }
+
+        impl<T: SignalValue> SignalValue for $name<T> {
+            const SIGNED: bool = T::SIGNED;
+            const UNIT: &'static str = $unit;
+            const FACTOR: f32 = 1.0 / 10_u32.pow($decimals) as f32;
+        }
+
+        impl<T: UnitValue> fmt::Display for $name<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write_fixed(f, self.0.to_i64(), $decimals, $display_unit)
+            }
+        }
+
+        #[cfg(feature = "defmt")]
+        impl<T: UnitValue> defmt::Format for $name<T> {
+            fn format(&self, f: defmt::Formatter<'_>) {
+                defmt::write!(f, "{=f32} {=str}", self.to_si(), $display_unit)
+            }
+        }
+
+        unit!(@from $name, u8 => u8, i8 => i8, u16 => u16, i16 => i16, u32 => u32, i32 => i32);
+        unit!(@from $name, u16 => le::U16, i16 => le::I16, u32 => le::U32, i32 => le::I32);
+    };
+    (@from $name:ident, $($native:ty => $wire:ty),*) => {
+        $(
+            impl From<$native> for $name<$wire> {
+                fn from(raw: $native) -> Self {
+                    Self(raw.into())
+                }
+            }
+        )*
+    };
+}
+
+unit!(
+    /// Electric potential in mV.
+    Millivolts, "V", "V", 3
+);
+unit!(
+    /// Electric current in mA.
+    Milliamps, "A", "A", 3
+);
+unit!(
+    /// Temperature in 0.1 °C.
+    DeciDegrees, "degC", "°C", 1
+);
+unit!(
+    /// Ratio in 0.1 %.
+    DeciPercent, "%", "%", 1
+);
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+    use heapless::String;
+    use super::*;
+
+    fn show(value: impl fmt::Display) -> String<32> {
+        let mut s = String::new();
+        write!(s, "{value}").unwrap();
+        s
+    }
+
+    #[test]
+    fn display() {
+        let cases: [(String<32>, &str); 12] = [
+            (show(Millivolts(0_u16)), "0.000 V"),
+            (show(Millivolts(12_345_u16)), "12.345 V"),
+            (show(Millivolts(-7_i16)), "-0.007 V"),
+            (show(Millivolts(-500_i16)), "-0.500 V"),
+            (show(Millivolts(i16::MIN)), "-32.768 V"),
+            (show(Millivolts(i16::MAX)), "32.767 V"),
+            (show(Millivolts(u16::MAX)), "65.535 V"),
+            (show(Milliamps::<le::I16>::from(-1_500)), "-1.500 A"),
+            (show(Milliamps::<le::I32>::from(i32::MIN)), "-2147483.648 A"),
+            (show(DeciDegrees(-5_i16)), "-0.5 °C"),
+            (show(DeciDegrees(0_i16)), "0.0 °C"),
+            (show(DeciPercent::<le::U16>::from(1000)), "100.0 %"),
+        ];
+        for (shown, expected) in cases {
+            assert_eq!(shown, expected);
+        }
+    }
+
+    #[test]
+    fn to_si() {
+        assert_eq!(Millivolts(-500_i16).to_si(), -0.5);
+        assert_eq!(Milliamps::<le::U16>::from(u16::MAX).to_si(), 65.535);
+        assert_eq!(DeciDegrees(i16::MIN).to_si(), -3276.8);
+        assert_eq!(Millivolts::<le::I16>::from(-2).get(), -2_i16);
+    }
+}
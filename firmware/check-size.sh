@@ -0,0 +1,46 @@
+#!/bin/sh
+# Build every firmware image and show its flash use against its region.
+#
+# The regions come from `can_messages::boot` and the linker refuses images
+# that overflow them, so a failed build here is an image that does not fit.
+#
+# Needs `llvm-size`, e.g. from `rustup component add llvm-tools`, or set SIZE.
+
+set -e
+cd "$(dirname "$0")"
+SIZE=${SIZE:-llvm-size}
+
+# Bootloader, application, standalone image and whole chip, in bytes.
+BOOTLOADER=6144
+APPLICATION=25588
+STANDALONE=31744
+CHIP=32768
+
+status=0
+
+check() {
+    crate=$1 limit=$2
+    shift 2
+    if ! (cd "$crate" && cargo build --release --quiet "$@"); then
+        printf '%-16s %-54s does not link\n' "$crate" "$*"
+        status=1
+        return
+    fi
+    # Flash holds text (with the vector table and rodata) and the initial data.
+    used=$("$SIZE" "target/thumbv6m-none-eabi/release/$crate" | awk 'NR == 2 { print $1 + $2 }')
+    printf '%-16s %-54s %6d / %6d bytes\n' "$crate" "$*" "$used" "$limit"
+    if [ "$used" -gt "$limit" ]; then
+        status=1
+    fi
+}
+
+check bootloader $BOOTLOADER --features power-supply
+check bootloader $BOOTLOADER --features coolbox
+check makita-ps $STANDALONE
+check makita-ps $STANDALONE --no-default-features --features soc,lxt,diagnostics
+check makita-ps $APPLICATION --no-default-features --features bootloader
+check temp-controller $STANDALONE
+check temp-controller $APPLICATION --features bootloader
+check test-board $CHIP
+
+exit $status
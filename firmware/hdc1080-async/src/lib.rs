@@ -17,7 +17,7 @@ const EXTRA_DELAY: u32 = 8000; // microseconds
 const RESET_DELAY: u32 = 10000; // microseconds
 
 /// Chip identification.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
 pub struct Identification {
     /// Chip manufacturer. Always 0x5449 (Texas Instruments).
     pub manufacturer: u16,
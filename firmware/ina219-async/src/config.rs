@@ -0,0 +1,172 @@
+//! INA219 configuration handling.
+
+/// Chip configuration.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub bus_range: BusRange,
+    pub shunt_range: ShuntRange,
+    pub bus_adc: AdcMode,
+    pub shunt_adc: AdcMode,
+    pub mode: OperatingMode,
+}
+
+impl Config {
+    pub(crate) fn as_bits(&self) -> u16 {
+        self.bus_range.as_config_bits()
+            | self.shunt_range.as_config_bits()
+            | self.bus_adc.as_config_bits() << 7 // config register bits 7..=10
+            | self.shunt_adc.as_config_bits() << 3 // config register bits 3..=6
+            | self.mode.as_config_bits()
+    }
+
+    /// Time until both registers hold a new result in continuous mode.
+    pub fn conversion_us(&self) -> u32 {
+        self.bus_adc.conversion_us() + self.shunt_adc.conversion_us()
+    }
+}
+
+/// Power-on default of the chip.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bus_range: BusRange::V32,
+            shunt_range: ShuntRange::Mv320,
+            bus_adc: AdcMode::Bits12,
+            shunt_adc: AdcMode::Bits12,
+            mode: OperatingMode::ShuntBusContinuous,
+        }
+    }
+}
+
+/// Full scale of the bus voltage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusRange {
+    V16,
+    V32,
+}
+
+impl BusRange {
+    fn as_config_bits(&self) -> u16 {
+        (match self {
+            BusRange::V16 => 0,
+            BusRange::V32 => 1,
+        }) << 13 // config register bit 13
+    }
+}
+
+/// Full scale of the shunt voltage, set by the PGA gain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShuntRange {
+    /// ±40 mV, gain 1
+    Mv40,
+    /// ±80 mV, gain /2
+    Mv80,
+    /// ±160 mV, gain /4
+    Mv160,
+    /// ±320 mV, gain /8
+    Mv320,
+}
+
+impl ShuntRange {
+    fn as_config_bits(&self) -> u16 {
+        (match self {
+            ShuntRange::Mv40 => 0,
+            ShuntRange::Mv80 => 1,
+            ShuntRange::Mv160 => 2,
+            ShuntRange::Mv320 => 3,
+        }) << 11 // config register bits 11, 12
+    }
+
+    pub fn full_scale_uv(&self) -> u32 {
+        match self {
+            ShuntRange::Mv40 => 40_000,
+            ShuntRange::Mv80 => 80_000,
+            ShuntRange::Mv160 => 160_000,
+            ShuntRange::Mv320 => 320_000,
+        }
+    }
+}
+
+/// Resolution or number of 12-bit samples averaged, for either ADC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdcMode {
+    Bits9,
+    Bits10,
+    Bits11,
+    Bits12,
+    Samples2,
+    Samples4,
+    Samples8,
+    Samples16,
+    Samples32,
+    Samples64,
+    Samples128,
+}
+
+impl AdcMode {
+    fn as_config_bits(&self) -> u16 {
+        match self {
+            AdcMode::Bits9 => 0b0000,
+            AdcMode::Bits10 => 0b0001,
+            AdcMode::Bits11 => 0b0010,
+            AdcMode::Bits12 => 0b0011,
+            AdcMode::Samples2 => 0b1001,
+            AdcMode::Samples4 => 0b1010,
+            AdcMode::Samples8 => 0b1011,
+            AdcMode::Samples16 => 0b1100,
+            AdcMode::Samples32 => 0b1101,
+            AdcMode::Samples64 => 0b1110,
+            AdcMode::Samples128 => 0b1111,
+        }
+    }
+
+    /// Maximum conversion time.
+    pub fn conversion_us(&self) -> u32 {
+        match self {
+            AdcMode::Bits9 => 84,
+            AdcMode::Bits10 => 148,
+            AdcMode::Bits11 => 276,
+            AdcMode::Bits12 => 532,
+            AdcMode::Samples2 => 1_060,
+            AdcMode::Samples4 => 2_130,
+            AdcMode::Samples8 => 4_260,
+            AdcMode::Samples16 => 8_510,
+            AdcMode::Samples32 => 17_020,
+            AdcMode::Samples64 => 34_050,
+            AdcMode::Samples128 => 68_100,
+        }
+    }
+}
+
+/// What the chip converts, and when.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OperatingMode {
+    PowerDown,
+    ShuntTriggered,
+    BusTriggered,
+    ShuntBusTriggered,
+    AdcOff,
+    ShuntContinuous,
+    BusContinuous,
+    ShuntBusContinuous,
+}
+
+impl OperatingMode {
+    fn as_config_bits(&self) -> u16 {
+        match self {
+            OperatingMode::PowerDown => 0,
+            OperatingMode::ShuntTriggered => 1,
+            OperatingMode::BusTriggered => 2,
+            OperatingMode::ShuntBusTriggered => 3,
+            OperatingMode::AdcOff => 4,
+            OperatingMode::ShuntContinuous => 5,
+            OperatingMode::BusContinuous => 6,
+            OperatingMode::ShuntBusContinuous => 7,
+        } // config register bits 0..=2
+    }
+}
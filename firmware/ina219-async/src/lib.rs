@@ -0,0 +1,111 @@
+#![deny(unsafe_code)]
+#![no_std]
+
+use embedded_hal_async::i2c::I2c;
+
+mod config;
+mod values;
+pub use config::*;
+pub use values::*;
+
+/// Address with A0 and A1 on ground.
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+
+/// INA219 chip registers.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Configuration = 0x00,
+    ShuntVoltage = 0x01,
+    BusVoltage = 0x02,
+    Power = 0x03,
+    Current = 0x04,
+    Calibration = 0x05,
+}
+
+const RESET_COMMAND: u16 = 1 << 15;
+
+/// The INA219 current and power monitor driver.
+///
+/// Current and power are only valid once calibrated, see [`Ina219::configure`].
+pub struct Ina219<I2C> {
+    i2c: I2C,
+    address: u8,
+    calibration: Option<Calibration>,
+}
+
+impl<I2C> Ina219<I2C> {
+    /// New INA219 device at `address`, see [`DEFAULT_ADDRESS`].
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            calibration: None,
+        }
+    }
+
+    pub fn calibration(&self) -> Option<Calibration> {
+        self.calibration
+    }
+}
+
+impl<I2C: I2c> Ina219<I2C> {
+    /// Fetch 16-bit register value.
+    async fn fetch_register(&mut self, reg: Register) -> Result<u16, I2C::Error> {
+        let mut buf = [0; 2];
+        self.i2c.write_read(self.address, &[reg as u8], &mut buf).await?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    /// Write value to register.
+    async fn write_register(&mut self, reg: Register, data: u16) -> Result<(), I2C::Error> {
+        let [high, low] = data.to_be_bytes();
+        self.i2c.write(self.address, &[reg as u8, high, low]).await
+    }
+
+    /// Perform device reset, back to the default config and uncalibrated.
+    pub async fn reset(&mut self) -> Result<(), I2C::Error> {
+        self.calibration = None;
+        self.write_register(Register::Configuration, RESET_COMMAND).await
+    }
+
+    /// Write config and calibration.
+    pub async fn configure(&mut self, config: &Config, calibration: Calibration) -> Result<(), I2C::Error> {
+        self.write_register(Register::Configuration, config.as_bits()).await?;
+        self.write_register(Register::Calibration, calibration.register()).await?;
+        self.calibration = Some(calibration);
+        Ok(())
+    }
+
+    /// Whether the chip still holds the calibration, it loses it on a brown-out.
+    pub async fn is_calibrated(&mut self) -> Result<bool, I2C::Error> {
+        let Some(calibration) = self.calibration else {
+            return Ok(false);
+        };
+        Ok(self.fetch_register(Register::Calibration).await? == calibration.register())
+    }
+
+    /// Read shunt voltage.
+    pub async fn shunt_voltage(&mut self) -> Result<ShuntVoltage, I2C::Error> {
+        Ok(ShuntVoltage(self.fetch_register(Register::ShuntVoltage).await? as i16))
+    }
+
+    /// Read bus voltage.
+    pub async fn bus_voltage(&mut self) -> Result<BusVoltage, I2C::Error> {
+        Ok(BusVoltage(self.fetch_register(Register::BusVoltage).await?))
+    }
+
+    /// Read all of the latest conversion, `None` while not calibrated.
+    ///
+    /// Reading the power register clears the conversion ready flag.
+    pub async fn read(&mut self) -> Result<Option<Measurement>, I2C::Error> {
+        let Some(calibration) = self.calibration else {
+            return Ok(None);
+        };
+        let bus = self.bus_voltage().await?;
+        let shunt = self.shunt_voltage().await?;
+        let current = self.fetch_register(Register::Current).await?;
+        let power = self.fetch_register(Register::Power).await?;
+        Ok(Some(Measurement::from_registers(&calibration, shunt, bus, current, power)))
+    }
+}
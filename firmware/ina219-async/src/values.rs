@@ -0,0 +1,193 @@
+//! Readings and their conversion to physical units.
+//!
+//! Plain arithmetic on register values, no bus access, so it runs on the
+//! host as well.
+
+use crate::ShuntRange;
+
+/// 0.04096 of the calibration equation, for µA and µΩ.
+const CALIBRATION_SCALE: u64 = 40_960_000_000;
+/// Bit 0 of the calibration register always reads 0.
+const CALIBRATION_MAX: u64 = 0xFFFE;
+/// Power register LSB in current register LSBs.
+const POWER_LSB_RATIO: u64 = 20;
+
+/// Shunt voltage reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShuntVoltage(pub i16);
+
+impl ShuntVoltage {
+    /// Get shunt voltage in µV.
+    pub fn uv(&self) -> i32 {
+        self.0 as i32 * 10
+    }
+}
+
+/// Bus voltage reading, including the status flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusVoltage(pub u16);
+
+impl BusVoltage {
+    /// Get bus voltage in mV.
+    pub fn mv(&self) -> u32 {
+        (self.0 >> 3) as u32 * 4
+    }
+
+    /// A conversion finished since the power register was last read.
+    pub fn conversion_ready(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    /// Current or power got out of range, both are meaningless then.
+    pub fn overflow(&self) -> bool {
+        self.0 & 1 != 0
+    }
+}
+
+/// Calibration register value and the current LSB it amounts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    register: u16,
+    /// In nA, the µA the register is based on would lose too much to rounding.
+    current_lsb_na: u32,
+}
+
+impl Calibration {
+    /// Finest calibration that still fits the full `shunt_range` across a shunt of `shunt_uohm` into the current register.
+    ///
+    /// `None` for a shunt of 0 µΩ.
+    pub fn new(shunt_uohm: u32, shunt_range: ShuntRange) -> Option<Self> {
+        if shunt_uohm == 0 {
+            return None;
+        }
+        let shunt_uohm = shunt_uohm as u64;
+        let max_current_ua = shunt_range.full_scale_uv() as u64 * 1_000_000 / shunt_uohm;
+        let current_lsb_ua = max_current_ua.div_ceil(i16::MAX as u64).max(1);
+        // Rounding down keeps the full range, clamping makes the LSB coarser.
+        let register = (CALIBRATION_SCALE / (current_lsb_ua * shunt_uohm)).min(CALIBRATION_MAX) & !1;
+        Some(Self {
+            register: register as u16,
+            current_lsb_na: (CALIBRATION_SCALE * 1000 / (register * shunt_uohm)) as u32,
+        })
+    }
+
+    /// Value of the calibration register.
+    pub fn register(&self) -> u16 {
+        self.register
+    }
+
+    pub fn current_lsb_na(&self) -> u32 {
+        self.current_lsb_na
+    }
+
+    /// Get current in mA from the current register.
+    pub fn current_ma(&self, raw: u16) -> i32 {
+        (raw as i16 as i64 * self.current_lsb_na as i64 / 1_000_000) as i32
+    }
+
+    /// Get power in mW from the power register.
+    pub fn power_mw(&self, raw: u16) -> u32 {
+        (raw as u64 * POWER_LSB_RATIO * self.current_lsb_na as u64 / 1_000_000) as u32
+    }
+}
+
+/// All readings of one conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Measurement {
+    pub bus_mv: u32,
+    pub shunt_uv: i32,
+    pub current_ma: i32,
+    pub power_mw: u32,
+    /// Current and power are out of range and meaningless.
+    pub overflow: bool,
+}
+
+impl Measurement {
+    /// Convert register words, `current` and `power` as calibrated with `calibration`.
+    pub fn from_registers(calibration: &Calibration, shunt: ShuntVoltage, bus: BusVoltage, current: u16, power: u16) -> Self {
+        Self {
+            bus_mv: bus.mv(),
+            shunt_uv: shunt.uv(),
+            current_ma: calibration.current_ma(current),
+            power_mw: calibration.power_mw(power),
+            overflow: bus.overflow(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration() {
+        // ±80 mV is 40 A across 2 mΩ, in steps of just over 1.22 mA.
+        let calibration = Calibration::new(2_000, ShuntRange::Mv80).unwrap();
+        assert_eq!(calibration.register(), 16772);
+        assert_eq!(calibration.current_lsb_na(), 1_221_082);
+        assert_eq!(calibration.current_ma(i16::MAX as u16), 40_011);
+
+        let calibration = Calibration::new(1_000, ShuntRange::Mv80).unwrap();
+        assert_eq!((calibration.register(), calibration.current_lsb_na()), (16772, 2_442_165));
+        let calibration = Calibration::new(1_000_000, ShuntRange::Mv80).unwrap();
+        assert_eq!((calibration.register(), calibration.current_lsb_na()), (13652, 3_000));
+        // Rounded down to an even register.
+        assert_eq!(Calibration::new(100, ShuntRange::Mv40).unwrap().register(), 33550);
+
+        assert_eq!(Calibration::new(0, ShuntRange::Mv80), None);
+    }
+
+    #[test]
+    fn negative_current() {
+        let calibration = Calibration::new(2_000, ShuntRange::Mv80).unwrap();
+        assert_eq!(calibration.current_ma(-100i16 as u16), -122);
+        assert_eq!(calibration.current_ma(i16::MIN as u16), -40_012);
+    }
+
+    #[test]
+    fn power() {
+        let calibration = Calibration::new(2_000, ShuntRange::Mv80).unwrap();
+        assert_eq!(calibration.power_mw(1_000), 24_421);
+        assert_eq!(calibration.power_mw(u16::MAX), 1_600_472);
+    }
+
+    #[test]
+    fn bus_voltage() {
+        let bus = BusVoltage(3_000 << 3);
+        assert_eq!(bus.mv(), 12_000);
+        assert!(!bus.conversion_ready() && !bus.overflow());
+        let bus = BusVoltage(3_000 << 3 | 0b10);
+        assert_eq!(bus.mv(), 12_000);
+        assert!(bus.conversion_ready() && !bus.overflow());
+        let bus = BusVoltage(0xFFFF);
+        assert_eq!(bus.mv(), 32_764);
+        assert!(bus.conversion_ready() && bus.overflow());
+
+        assert_eq!(ShuntVoltage(-3_200i16).uv(), -32_000);
+    }
+
+    /// Worked example of the datasheet: 0.1 Ω, a current LSB of 100 µA and a power LSB of 2 mW.
+    #[test]
+    fn datasheet_example() {
+        let calibration = Calibration { register: 4096, current_lsb_na: 100_000 };
+        // 32 mV across the shunt at 12 V on the bus. The chip computes
+        // current = shunt × calibration / 4096 and power = current × bus / 5000.
+        let (shunt, bus) = (3_200, 3_000);
+        let measurement = Measurement::from_registers(
+            &calibration,
+            ShuntVoltage(shunt),
+            BusVoltage((bus << 3) as u16 | 0b10),
+            (shunt as i32 * 4096 / 4096) as u16,
+            (shunt as i32 * bus / 5000) as u16,
+        );
+        let expected = Measurement { bus_mv: 12_000, shunt_uv: 32_000, current_ma: 320, power_mw: 3_840, overflow: false };
+        assert_eq!(measurement, expected);
+
+        // Without rounding to 100 µA, the same shunt over the full ±320 mV.
+        let calibration = Calibration::new(100_000, ShuntRange::Mv320).unwrap();
+        assert_eq!((calibration.register(), calibration.current_lsb_na()), (4178, 98_037));
+        assert!(calibration.current_ma(i16::MAX as u16) >= 3_200);
+    }
+}
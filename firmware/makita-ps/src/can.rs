@@ -1,65 +1,254 @@
+#[cfg(feature = "lxt")]
+use crate::lxt::{self, PACK};
+#[cfg(feature = "soc")]
+use crate::soc::SOC;
 use crate::{
     adc::BATTERY_VOLTAGE_MV,
+    params::PARAMS,
+    protection,
+    shutdown_log,
     vmon::{OUTPUT_CURRENT_MA, OUTPUT_VOLTAGE_MV},
 };
-use can_messages::{prelude::*, PowerOff, BatteryData, CanId, BITRATE};
+#[cfg(feature = "lxt")]
+use can_messages::{BatteryCells, BatteryDetail, BatteryModel};
+#[cfg(feature = "soc")]
+use can_messages::BatteryState;
+#[cfg(feature = "diagnostics")]
+use can_messages::{
+    supervisor::{supervise, CanStats, SUPERVISOR_PERIOD_MS},
+    CanDiagnostics, CAN_DIAGNOSTICS_PERIOD_MS,
+};
+use can_messages::{
+    prelude::*, boot, firmware_id, BatteryData,
+    CounterCheck, EnterBootloader, Heartbeat, identification, IdentifyRequest, LastShutdown, NodeId, ParamRequest, ParamResponse,
+    PowerOff, ShutdownReason, TimeSync, BIT_TIMING, HEARTBEAT_PERIOD_MS,
+    clock::SyncedClock,
+    filter::{FilterPlan, BXCAN_BANKS},
+    scheduler::{Scheduler, Trigger},
+    supervisor::RX_ERROR_BACKOFF_MS,
+};
 use core::sync::atomic::Ordering;
 use defmt::info;
 use embassy_executor::task;
-use embassy_futures::join::join;
-use embassy_stm32::can::{filter::Mask32, Can, CanRx, CanTx, Fifo, StandardId};
-use embassy_time::Timer;
+use embassy_futures::{
+    join,
+    select::{select4, Either4},
+};
+use embassy_stm32::{can::{Can, CanRx, CanTx, Frame}, uid};
+use embassy_sync::{blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex}, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+
+pub const NODE_ID: NodeId = NodeId::POWER_SUPPLY;
+
+type Responses = Channel<NoopRawMutex, ParamResponse, 4>;
+type Identify = Signal<NoopRawMutex, ()>;
+type CanScheduler = Scheduler<CanTx<'static>, 8>;
+
+/// The model is sent when a pack answers, and then only this often.
+#[cfg(feature = "lxt")]
+const BATTERY_MODEL_REFRESH: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "diagnostics")]
+static CAN_STATS: CanStats<CriticalSectionRawMutex> = CanStats::new();
+/// Time of the bus master.
+pub static CLOCK: SyncedClock<CriticalSectionRawMutex> = SyncedClock::new();
+
+can_variant!{
+/// Messages this node acts on, all others are filtered out.
+Subscribed {
+    PowerOff(PowerOff),
+    ParamRequest(ParamRequest),
+    Identify(IdentifyRequest),
+    EnterBootloader(EnterBootloader),
+    TimeSync(TimeSync),
+}}
+
+const FILTERS: FilterPlan<BXCAN_BANKS> = FilterPlan::new(Subscribed::IDS);
 
 #[task]
 pub async fn process(mut can: Can<'static>) {
-    can.set_bitrate(BITRATE);
+    can.modify_config().set_bit_timing(BIT_TIMING);
     can.set_tx_fifo_scheduling(true);
     can.enable().await;
     info!("CAN initialized.");
     let (tx, rx) = can.split();
-    join(transmit(tx), receive(rx)).await;
+    let responses = Responses::new();
+    let identify = Identify::new();
+    #[cfg(feature = "diagnostics")]
+    join::join3(
+        transmit(tx, &responses, &identify),
+        receive(rx, &responses, &identify),
+        supervise(&CAN_STATS, Duration::from_millis(SUPERVISOR_PERIOD_MS)),
+    )
+    .await;
+    #[cfg(not(feature = "diagnostics"))]
+    join::join(transmit(tx, &responses, &identify), receive(rx, &responses, &identify)).await;
 }
 
-async fn receive(mut rx: CanRx<'static>) {
-    let filter = Mask32::frames_with_std_id(
-        StandardId::new(CanId::POWEROFF.into()).unwrap(),
-        StandardId::MAX,
-    );
-    rx.modify_filters().enable_bank(0, Fifo::Fifo0, filter);
+async fn receive(mut rx: CanRx<'static>, responses: &Responses, identify: &Identify) {
+    FILTERS.apply(&mut rx);
+    let mut poweroff_counter = CounterCheck::new();
+    let mut bootloader_counter = CounterCheck::new();
     loop {
-        if let Ok(msg) = rx.read().await {
-            info!("CAN message received");
+        let Ok(msg) = rx.read().await else {
+            Timer::after_millis(RX_ERROR_BACKOFF_MS).await;
+            continue;
+        };
+        info!("CAN message received");
 
-            if let Some(PowerOff) = msg.try_decode() {
-                crate::SHUTDOWN.store(true, Ordering::Relaxed);
+        match Subscribed::decode(&msg.frame) {
+            // Only an intact, fresh command for this very node cuts the power.
+            Some(Subscribed::PowerOff(cmd)) => {
+                if cmd.target == NODE_ID.raw() && poweroff_counter.accept(&cmd) {
+                    crate::shutdown(ShutdownReason::Command);
+                }
             }
+            Some(Subscribed::ParamRequest(req)) => {
+                if let Some(response) = PARAMS.handle(&req) {
+                    if responses.try_send(response).is_err() {
+                        info!("CAN parameter response dropped");
+                    }
+                }
+            }
+            Some(Subscribed::Identify(req)) => {
+                if req.is_for(NODE_ID) {
+                    identify.signal(());
+                }
+            }
+            // Without the bootloader there is nothing to restart into.
+            Some(Subscribed::EnterBootloader(cmd)) => {
+                if cfg!(feature = "bootloader") && cmd.target == NODE_ID.raw() && bootloader_counter.accept(&cmd) {
+                    // SAFETY: only built with the `bootloader` feature, whose memory layout keeps the flag free.
+                    unsafe { boot::enter_bootloader() }
+                }
+            }
+            Some(Subscribed::TimeSync(sync)) => CLOCK.update(sync.time_ms.get(), msg.ts),
+            None => {}
         }
     }
 }
 
-async fn transmit(mut tx: CanTx<'static>) {
-    let mut mailbox = None;
+async fn transmit(tx: CanTx<'static>, responses: &Responses, identify: &Identify) {
+    let mut scheduler = CanScheduler::new(tx);
+    scheduler.add(Trigger::Periodic(Duration::from_millis(100)), battery_data);
+    #[cfg(feature = "soc")]
+    scheduler.add(Trigger::Periodic(Duration::from_secs(1)), battery_state);
+    #[cfg(feature = "lxt")]
+    {
+        scheduler.add(Trigger::Periodic(lxt::READ_PERIOD), battery_detail);
+        scheduler.add(Trigger::Periodic(lxt::READ_PERIOD), battery_cells::<0>);
+        scheduler.add(Trigger::Periodic(lxt::READ_PERIOD), battery_cells::<1>);
+        scheduler.add(
+            Trigger::OnChange {
+                check: lxt::READ_PERIOD,
+                refresh: BATTERY_MODEL_REFRESH,
+            },
+            battery_model,
+        );
+    }
+    scheduler.add(Trigger::Periodic(Duration::from_millis(HEARTBEAT_PERIOD_MS)), heartbeat);
+    #[cfg(feature = "diagnostics")]
+    scheduler.add(
+        Trigger::OnChange {
+            check: Duration::from_millis(SUPERVISOR_PERIOD_MS),
+            refresh: Duration::from_millis(CAN_DIAGNOSTICS_PERIOD_MS),
+        },
+        can_diagnostics,
+    );
+    send_identification(&mut scheduler).await;
+    #[cfg(feature = "diagnostics")]
+    let mut dropped = 0;
     loop {
-        let battery_voltage_mv = BATTERY_VOLTAGE_MV.load(Ordering::Relaxed);
-        let output_current_ma = OUTPUT_CURRENT_MA.load(Ordering::Relaxed);
-        let output_voltage_mv = OUTPUT_VOLTAGE_MV.load(Ordering::Relaxed);
-
-        let data = BatteryData {
-            battery_voltage_mv,
-            output_voltage_mv,
-            output_current_ma,
+        let frame = match select4(scheduler.tick(), responses.receive(), identify.wait(), protection::EVENTS.receive()).await {
+            Either4::First(()) => None,
+            Either4::Second(response) => response.try_encode(),
+            Either4::Third(()) => {
+                send_identification(&mut scheduler).await;
+                None
+            }
+            Either4::Fourth(event) => event.try_encode(),
         };
+        if let Some(frame) = frame {
+            scheduler.send(&frame).await;
+        }
+        #[cfg(feature = "diagnostics")]
+        {
+            CAN_STATS.record_tx_dropped(scheduler.dropped().wrapping_sub(dropped));
+            dropped = scheduler.dropped();
+        }
+    }
+}
 
-        if let Some(frame) = data.try_encode() {
-            if let Some(mbox) = mailbox.take() {
-                let r = tx.abort(mbox);
-                info!("CAN sent: {}", r);
-            }
-            if let Ok(wr) = tx.try_write(&frame) {
-                mailbox = Some(wr.mailbox());
-            }
+async fn send_identification(scheduler: &mut CanScheduler) {
+    let last_shutdown = shutdown_log::LAST.lock(|cell| cell.get()).and_then(|record| {
+        LastShutdown {
+            reason: record.reason,
+            battery_voltage: record.battery_mv.into(),
+            time_s: record.time_s.into(),
         }
+        .try_encode()
+    });
+    for frame in identification(firmware_id!(NODE_ID), uid::uid()).chain(last_shutdown) {
+        scheduler.send(&frame).await;
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+fn can_diagnostics() -> Option<Frame> {
+    CanDiagnostics::new(NODE_ID, CAN_STATS.status(), CAN_STATS.counters()).encode()
+}
+
+fn heartbeat() -> Option<Frame> {
+    let uptime_s = Instant::now().as_secs() as u32;
+    Heartbeat::new(NODE_ID, crate::STATE.get(), uptime_s).encode()
+}
+
+fn battery_data() -> Option<Frame> {
+    let battery_voltage_mv = BATTERY_VOLTAGE_MV.load(Ordering::Relaxed);
+    let output_current_ma = OUTPUT_CURRENT_MA.load(Ordering::Relaxed);
+    let output_voltage_mv = OUTPUT_VOLTAGE_MV.load(Ordering::Relaxed);
+
+    // Saturated to the range of the message.
+    BatteryData {
+        battery_voltage: battery_voltage_mv.into(),
+        output_voltage: i16::try_from(output_voltage_mv).unwrap_or(i16::MAX).into(),
+        output_current: (output_current_ma.clamp(i16::MIN.into(), i16::MAX.into()) as i16).into(),
+    }
+    .try_encode()
+}
 
-        Timer::after_millis(100).await;
+#[cfg(feature = "soc")]
+fn battery_state() -> Option<Frame> {
+    let estimate = SOC.lock(|cell| cell.get())?;
+    BatteryState::new(estimate.soc_pct10, estimate.remaining_mah, estimate.runtime_min).try_encode()
+}
+
+#[cfg(feature = "lxt")]
+fn battery_detail() -> Option<Frame> {
+    let pack = PACK.lock(|cell| cell.get())?;
+    let mut flags = 0;
+    if pack.locked {
+        flags |= BatteryDetail::FLAG_LOCKED;
+    }
+    if pack.overdischarged {
+        flags |= BatteryDetail::FLAG_OVERDISCHARGED;
     }
+    BatteryDetail {
+        cycles: pack.cycles.into(),
+        flags,
+        temperature: pack.temperature_deg10.into(),
+    }
+    .try_encode()
+}
+
+#[cfg(feature = "lxt")]
+fn battery_cells<const PART: usize>() -> Option<Frame> {
+    let pack = PACK.lock(|cell| cell.get())?;
+    BatteryCells::split(&pack.cells_mv)[PART].try_encode()
+}
+
+#[cfg(feature = "lxt")]
+fn battery_model() -> Option<Frame> {
+    let pack = PACK.lock(|cell| cell.get())?;
+    BatteryModel { model: pack.model }.try_encode()
 }
@@ -1,34 +1,121 @@
-use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306Async};
+//! Status display, an SSD1306 OLED of 128x32 pixels on the I²C bus.
+//!
+//! Four lines of 16 characters of 8x8 pixels, in the font and with the
+//! configuration of the `ssd1306` crate's terminal mode, mounted upside
+//! down. Only the few characters the lines use are in the font. Each
+//! character goes out on its own, so `vmon` gets the bus in between.
 
-use core::fmt::Write;
-use core::sync::atomic::Ordering;
+use core::{iter, sync::atomic::Ordering};
 use defmt::error;
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_executor::task;
-use embassy_stm32::{
-    i2c::{mode::Master, I2c},
-    mode::Async,
-};
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_futures::yield_now;
 use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c as _;
 use heapless::String;
 
 use crate::{
     adc::BATTERY_VOLTAGE_MV,
+    soc::SOC,
     vmon::{OUTPUT_CURRENT_MA, OUTPUT_VOLTAGE_MV},
+    I2cBus,
 };
 
-#[task]
-pub async fn process(i2c: &'static Mutex<NoopRawMutex, I2c<'static, Async, Master>>) {
-    let i2c = I2cDevice::new(i2c);
-    let iface = I2CDisplayInterface::new(i2c);
+const ADDRESS: u8 = 0x3C;
+/// Control byte in front of commands and of display data.
+const COMMAND: u8 = 0x00;
+const DATA: u8 = 0x40;
+const COLUMNS: usize = 16;
+
+/// Display off, clock, 32 rows, no offset, charge pump on, page addressing,
+/// COM pins for 32 rows, rotated by 180°, default brightness, display on.
+const INIT: [u8; 27] = [
+    COMMAND, 0xAE, 0xD5, 0x80, 0xA8, 0x1F, 0xD3, 0x00, 0x40, 0x8D, 0x14, 0x20, 0x02, 0xDA, 0x02, 0xA0, 0xC0, 0xD9,
+    0x21, 0x81, 0x5F, 0xDB, 0x40, 0xA4, 0xA6, 0x2E, 0xAF,
+];
+
+type Line = String<COLUMNS>;
+
+/// Columns of a character, the lowest bit at the top.
+fn glyph(c: u8) -> [u8; 6] {
+    match c {
+        b'%' => [0x23, 0x13, 0x08, 0x04, 0x32, 0x31],
+        b'-' => [0x08, 0x08, 0x08, 0x08, 0x08, 0x00],
+        b'0' => [0x1e, 0x31, 0x29, 0x25, 0x23, 0x1e],
+        b'1' => [0x22, 0x21, 0x3f, 0x20, 0x20, 0x20],
+        b'2' => [0x32, 0x29, 0x29, 0x29, 0x29, 0x26],
+        b'3' => [0x12, 0x21, 0x21, 0x25, 0x25, 0x1a],
+        b'4' => [0x18, 0x14, 0x12, 0x3f, 0x10, 0x00],
+        b'5' => [0x17, 0x25, 0x25, 0x25, 0x25, 0x19],
+        b'6' => [0x1e, 0x25, 0x25, 0x25, 0x25, 0x18],
+        b'7' => [0x01, 0x01, 0x31, 0x09, 0x05, 0x03],
+        b'8' => [0x1a, 0x25, 0x25, 0x25, 0x25, 0x1a],
+        b'9' => [0x06, 0x29, 0x29, 0x29, 0x29, 0x1e],
+        b':' => [0x24, 0x00, 0x00, 0x00, 0x00, 0x00],
+        b'?' => [0x02, 0x01, 0x01, 0x29, 0x05, 0x02],
+        b'A' => [0x3e, 0x09, 0x09, 0x09, 0x09, 0x3e],
+        b'B' => [0x3f, 0x25, 0x25, 0x25, 0x25, 0x1a],
+        b'C' => [0x1e, 0x21, 0x21, 0x21, 0x21, 0x12],
+        b'O' => [0x1e, 0x21, 0x21, 0x21, 0x21, 0x1e],
+        b'S' => [0x12, 0x25, 0x25, 0x25, 0x25, 0x18],
+        b'V' => [0x0f, 0x10, 0x20, 0x20, 0x10, 0x0f],
+        b'a' => [0x10, 0x2a, 0x2a, 0x2a, 0x3c, 0x00],
+        b'h' => [0x3f, 0x04, 0x04, 0x04, 0x38, 0x00],
+        b'm' => [0x3e, 0x02, 0x3c, 0x02, 0x3c, 0x00],
+        b'o' => [0x1c, 0x22, 0x22, 0x22, 0x1c, 0x00],
+        b'r' => [0x3e, 0x04, 0x02, 0x02, 0x00, 0x00],
+        b't' => [0x02, 0x1f, 0x22, 0x20, 0x00, 0x00],
+        b'u' => [0x1e, 0x20, 0x20, 0x20, 0x1e, 0x00],
+        _ => [0; 6],
+    }
+}
+
+/// Write `text` to line `row`, blanking the rest of it.
+async fn draw_line<I: embedded_hal_async::i2c::I2c>(i2c: &mut I, row: u8, text: &str) -> Result<(), I::Error> {
+    // Page `row`, column 0.
+    i2c.write(ADDRESS, &[COMMAND, 0xB0 | row, 0x00, 0x10]).await?;
+    for c in text.bytes().chain(iter::repeat(b' ')).take(COLUMNS) {
+        let [g0, g1, g2, g3, g4, g5] = glyph(c);
+        i2c.write(ADDRESS, &[DATA, 0, g0, g1, g2, g3, g4, g5, 0]).await?;
+        // The bus blocks, let the other tasks run between characters.
+        yield_now().await;
+    }
+    Ok(())
+}
+
+/// Append `value` right-aligned in `width` characters, like `{:>width$}`
+/// but without the flash that `core::fmt` takes.
+fn push_number(s: &mut Line, value: i32, width: usize, fill: char) {
+    let mut digits = [0; 10];
+    let mut len = 0;
+    let mut rest = value.unsigned_abs();
+    loop {
+        digits[len] = b'0' + (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    let sign = value < 0;
+    for _ in len + sign as usize..width {
+        let _ = s.push(fill);
+    }
+    if sign {
+        let _ = s.push('-');
+    }
+    for &digit in digits[..len].iter().rev() {
+        let _ = s.push(digit as char);
+    }
+}
 
-    let mut display = Ssd1306Async::new(iface, DisplaySize128x32, DisplayRotation::Rotate180)
-        .into_terminal_mode();
+#[task]
+pub async fn process(i2c: &'static I2cBus) {
+    let mut i2c = I2cDevice::new(i2c);
 
     let mut init_count = 10;
     loop {
-        if display.init().await.is_ok() {
+        if i2c.write(ADDRESS, &INIT).await.is_ok() {
             break;
         }
         init_count -= 1;
@@ -39,18 +126,41 @@ pub async fn process(i2c: &'static Mutex<NoopRawMutex, I2c<'static, Async, Maste
         Timer::after_millis(10).await;
     }
 
-    let _ = display.clear().await;
-
     loop {
-        let _ = display.set_position(0, 0).await;
         let batt_voltage = BATTERY_VOLTAGE_MV.load(Ordering::Relaxed);
         let output_voltage = OUTPUT_VOLTAGE_MV.load(Ordering::Relaxed);
         let output_current = OUTPUT_CURRENT_MA.load(Ordering::Relaxed);
-        let power = output_voltage as i32 * output_current as i32 / 1000;
+        let soc = SOC.lock(|cell| cell.get());
 
-        let mut s = String::<128>::new();
-        let _ = write!(s, "Bat: {batt_voltage:>5} mV\nOut: {output_voltage:>5} mV\nCur: {output_current:>5} mA\nP: {power:>7} mW");
-        let _ = display.write_str(&s).await;
+        let mut lines = [Line::new(), Line::new(), Line::new(), Line::new()];
+        let _ = lines[0].push_str("Bat: ");
+        push_number(&mut lines[0], batt_voltage.into(), 5, ' ');
+        let _ = lines[0].push_str(" mV");
+        let _ = lines[1].push_str("Out: ");
+        push_number(&mut lines[1], output_voltage as i32, 5, ' ');
+        let _ = lines[1].push_str(" mV");
+        let _ = lines[2].push_str("Cur: ");
+        push_number(&mut lines[2], output_current, 5, ' ');
+        let _ = lines[2].push_str(" mA");
+        // `SoC  85%  2h05`
+        let s = &mut lines[3];
+        let _ = s.push_str("SoC ");
+        match soc {
+            Some(soc) => push_number(s, ((soc.soc_pct10 + 5) / 10).into(), 3, ' '),
+            None => {
+                let _ = s.push_str("  ?");
+            }
+        }
+        let _ = s.push('%');
+        if let Some(min) = soc.and_then(|soc| soc.runtime_min) {
+            let _ = s.push(' ');
+            push_number(s, (min / 60).min(99).into(), 2, ' ');
+            let _ = s.push('h');
+            push_number(s, (min % 60).into(), 2, '0');
+        }
+        for (row, line) in (0..).zip(&lines) {
+            let _ = draw_line(&mut i2c, row, line).await;
+        }
 
         Timer::after_millis(300).await;
     }
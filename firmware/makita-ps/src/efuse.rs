@@ -0,0 +1,156 @@
+//! Electronic fuse on the 12 V output
+//!
+//! Above the rated current the output heats up like `I²t`, below it cools
+//! off at the same rate. The fuse trips once the heat reaches what twice the
+//! rated current builds up in `OVERLOAD_TRIP_MS`, and on the first sample
+//! above the short-circuit current. Either switches `EN_12V` off. The output
+//! comes back after a backoff, and after too many trips in a row the fuse
+//! latches until the power button is pressed briefly. The 15 A fuse on the
+//! board blows only if all of this fails.
+//!
+//! Samples come from `vmon` every few ms, unless the display holds the I²C
+//! bus at that moment or the transfer fails.
+
+use crate::{protection, STATE};
+use can_messages::{NodeState, Protection, ProtectionLevel};
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicU16, Ordering},
+};
+use defmt::info;
+use embassy_executor::task;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use portable_atomic::AtomicBool;
+
+/// Current the output may carry for good.
+pub static RATED_MA: AtomicU16 = AtomicU16::new(10_000);
+/// Trips on the first sample at or above it, at most [`BOARD_FUSE_MA`].
+pub static SHORT_CIRCUIT_MA: AtomicU16 = AtomicU16::new(BOARD_FUSE_MA);
+/// Rating of the fuse on the board. `vmon` reads up to 80 mV of shunt
+/// voltage, 40 A across the 2 mΩ shunt in steps of about 1.22 mA, so the
+/// limit stays in range for any shunt up to 5 mΩ.
+pub const BOARD_FUSE_MA: u16 = 15_000;
+
+/// Time to trip at twice the rated current, from cold.
+const OVERLOAD_TRIP_MS: u64 = 500;
+/// Before the first retry, doubled for every further one.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRIES: u32 = 3;
+/// On for this long without a trip forgets earlier trips.
+const STABLE_TIME: Duration = Duration::from_secs(60);
+
+static FUSE: Mutex<CriticalSectionRawMutex, Cell<Fuse>> = Mutex::new(Cell::new(Fuse::new()));
+static OUTPUT_OFF: AtomicBool = AtomicBool::new(false);
+static LATCHED: AtomicBool = AtomicBool::new(false);
+static TRIPPED: Signal<CriticalSectionRawMutex, Trip> = Signal::new();
+static RESET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Debug, Clone, Copy)]
+struct Trip {
+    protection: Protection,
+    current_ma: u16,
+    limit_ma: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fuse {
+    /// Above the rated current, in mA²·µs.
+    heat: u64,
+    last: Option<Instant>,
+}
+
+impl Fuse {
+    const fn new() -> Self {
+        Self { heat: 0, last: None }
+    }
+
+    fn sample(&mut self, current_ma: u16, rated_ma: u16, short_circuit_ma: u16, now: Instant) -> Option<Trip> {
+        let elapsed_us = self.last.replace(now).map_or(0, |last| (now - last).as_micros());
+        let (current_sq, rated_sq) = ((current_ma as u64).pow(2), (rated_ma as u64).pow(2));
+        if current_sq > rated_sq {
+            self.heat = self.heat.saturating_add((current_sq - rated_sq) * elapsed_us);
+        } else {
+            self.heat = self.heat.saturating_sub((rated_sq - current_sq) * elapsed_us);
+        }
+
+        let trip = |protection, limit_ma| Some(Trip { protection, current_ma, limit_ma });
+        if current_ma >= short_circuit_ma {
+            trip(Protection::ShortCircuit, short_circuit_ma)
+        } else if self.heat >= 3 * rated_sq * OVERLOAD_TRIP_MS * 1000 {
+            trip(Protection::Overcurrent, rated_ma)
+        } else {
+            None
+        }
+    }
+}
+
+/// Account for an output current sample, switches the output off on a trip.
+pub fn sample(current_ma: i32) {
+    let rated_ma = RATED_MA.load(Ordering::Relaxed);
+    let short_circuit_ma = SHORT_CIRCUIT_MA.load(Ordering::Relaxed).max(rated_ma);
+    let trip = FUSE.lock(|cell| {
+        let mut fuse = cell.get();
+        let trip = fuse.sample(current_ma.clamp(0, u16::MAX.into()) as u16, rated_ma, short_circuit_ma, Instant::now());
+        cell.set(fuse);
+        trip
+    });
+    if let Some(trip) = trip {
+        if !OUTPUT_OFF.swap(true, Ordering::Relaxed) {
+            TRIPPED.signal(trip);
+        }
+    }
+}
+
+/// Whether the fuse lets the output on.
+pub fn output_allowed() -> bool {
+    !OUTPUT_OFF.load(Ordering::Relaxed)
+}
+
+pub fn is_latched() -> bool {
+    LATCHED.load(Ordering::Relaxed)
+}
+
+/// Let a latched output on again.
+pub fn reset() {
+    if is_latched() {
+        RESET.signal(());
+    }
+}
+
+#[task]
+pub async fn process() {
+    let mut retries = 0;
+    let mut on_since = Instant::now();
+    loop {
+        let trip = TRIPPED.wait().await;
+        if Instant::now() - on_since >= STABLE_TIME {
+            retries = 0;
+        }
+        info!("Output {} at {} mA, switched off", trip.protection, trip.current_ma);
+        if retries < MAX_RETRIES {
+            protection::report(trip.protection, ProtectionLevel::Tripped, trip.current_ma, trip.limit_ma);
+            Timer::after(RETRY_BACKOFF * (1 << retries)).await;
+            retries += 1;
+        } else {
+            info!("Output fuse latched");
+            protection::report(trip.protection, ProtectionLevel::Latched, trip.current_ma, trip.limit_ma);
+            RESET.reset();
+            LATCHED.store(true, Ordering::Relaxed);
+            STATE.set(NodeState::Fault);
+            RESET.wait().await;
+            LATCHED.store(false, Ordering::Relaxed);
+            if STATE.get() == NodeState::Fault {
+                STATE.set(NodeState::Operational);
+            }
+            retries = 0;
+        }
+        info!("Output back on");
+        protection::report(trip.protection, ProtectionLevel::Normal, 0, trip.limit_ma);
+        on_since = Instant::now();
+        OUTPUT_OFF.store(false, Ordering::Relaxed);
+    }
+}
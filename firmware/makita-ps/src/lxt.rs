@@ -0,0 +1,209 @@
+//! Makita LXT battery data over the data contact of the pack
+//!
+//! This board revision does not route the data contact to the MCU: the `M`
+//! pads of the terminal footprint are its mounting pins on ground, and `T`
+//! goes to `SMON_BAT`. Wire the data contact to `AUX` (J3 pin 3, PA5) and
+//! pull it up with 4.7 kΩ to 3.3 V (J3 pin 4).
+//!
+//! The pack speaks 1-Wire at standard speed. Commands and data layout are
+//! reverse engineered; packs with other controllers may not answer at all.
+
+use can_messages::BATTERY_CELLS;
+use core::cell::Cell;
+use cortex_m::interrupt;
+use defmt::{info, Format};
+use embassy_executor::task;
+use embassy_stm32::gpio::{Flex, Speed};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Ticker, Timer};
+
+/// How often the pack is read.
+pub const READ_PERIOD: Duration = Duration::from_secs(1);
+
+/// Core clock, for the busy waits within a bit.
+const CYCLES_PER_US: u32 = 48;
+
+// 1-Wire standard speed timing in µs.
+const RESET_LOW_US: u64 = 480;
+const PRESENCE_SAMPLE_US: u32 = 70;
+const RESET_RECOVERY_US: u64 = 410;
+const WRITE_ONE_LOW_US: u32 = 6;
+const WRITE_ZERO_LOW_US: u32 = 60;
+const SLOT_US: u32 = 70;
+const READ_LOW_US: u32 = 6;
+const READ_SAMPLE_US: u32 = 9;
+/// Pause the pack needs after every byte.
+const BYTE_GAP_US: u64 = 90;
+
+const READ_ROM: u8 = 0x33;
+const SKIP_ROM: u8 = 0xCC;
+const CMD_MODEL: [u8; 2] = [0xDC, 0x0C];
+const CMD_STATUS: [u8; 4] = [0xD7, 0x00, 0x00, 0xFF];
+
+// Offsets into the answer to `READ_ROM`, 8 bytes ROM code and 32 data bytes.
+const INFO_LEN: usize = 40;
+/// Charge cycles, 12 bits big endian.
+const INFO_CYCLES: usize = 34;
+/// Low nibble non-zero when the pack locked itself.
+const INFO_LOCK: usize = 30;
+/// Overdischarge events.
+const INFO_OVERDISCHARGE: usize = 31;
+
+// Offsets into the answer to `CMD_STATUS`, little endian.
+const STATUS_LEN: usize = 29;
+/// Cell voltages in mV, one after the other.
+const STATUS_CELLS: usize = 2;
+/// Pack temperature in 0.1 K.
+const STATUS_TEMPERATURE: usize = 14;
+
+/// Cell voltages outside of this range are read errors.
+const CELL_RANGE_MV: core::ops::RangeInclusive<u16> = 1000..=5000;
+
+/// Everything read from the pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Pack {
+    /// ASCII, padded with zeros.
+    pub model: [u8; 8],
+    pub cycles: u16,
+    pub locked: bool,
+    pub overdischarged: bool,
+    pub cells_mv: [u16; BATTERY_CELLS],
+    pub temperature_deg10: i16,
+}
+
+/// Latest read of the pack, `None` while it does not answer.
+pub static PACK: Mutex<CriticalSectionRawMutex, Cell<Option<Pack>>> = Mutex::new(Cell::new(None));
+
+/// The pack did not answer the reset pulse or sent nonsense.
+#[derive(Debug)]
+struct NoAnswer;
+
+fn delay_us(us: u32) {
+    cortex_m::asm::delay(us * CYCLES_PER_US);
+}
+
+/// Bit-banged 1-Wire master on an open-drain pin with external pull-up.
+///
+/// Only single bit slots run with interrupts off, the line idles high in
+/// between, which 1-Wire allows for as long as it takes.
+struct OneWire<'d> {
+    pin: Flex<'d>,
+}
+
+impl<'d> OneWire<'d> {
+    fn new(mut pin: Flex<'d>) -> Self {
+        pin.set_high();
+        pin.set_as_input_output(Speed::Low);
+        Self { pin }
+    }
+
+    /// Reset pulse, `true` if a device answered with a presence pulse.
+    async fn reset(&mut self) -> bool {
+        // Held low by something else, nobody could answer.
+        if self.pin.is_low() {
+            return false;
+        }
+        self.pin.set_low();
+        Timer::after_micros(RESET_LOW_US).await;
+        let present = interrupt::free(|_| {
+            self.pin.set_high();
+            delay_us(PRESENCE_SAMPLE_US);
+            self.pin.is_low()
+        });
+        Timer::after_micros(RESET_RECOVERY_US).await;
+        present
+    }
+
+    async fn write(&mut self, byte: u8) {
+        for bit in 0..8 {
+            let low_us = if byte >> bit & 1 != 0 { WRITE_ONE_LOW_US } else { WRITE_ZERO_LOW_US };
+            interrupt::free(|_| {
+                self.pin.set_low();
+                delay_us(low_us);
+                self.pin.set_high();
+                delay_us(SLOT_US - low_us);
+            });
+        }
+        Timer::after_micros(BYTE_GAP_US).await;
+    }
+
+    async fn read(&mut self) -> u8 {
+        let mut byte = 0;
+        for bit in 0..8 {
+            let one = interrupt::free(|_| {
+                self.pin.set_low();
+                delay_us(READ_LOW_US);
+                self.pin.set_high();
+                delay_us(READ_SAMPLE_US);
+                let one = self.pin.is_high();
+                delay_us(SLOT_US - READ_LOW_US - READ_SAMPLE_US);
+                one
+            });
+            byte |= (one as u8) << bit;
+        }
+        Timer::after_micros(BYTE_GAP_US).await;
+        byte
+    }
+
+    /// Reset, send `command` after the ROM command `rom` and read `response`.
+    async fn transfer(&mut self, rom: u8, command: &[u8], response: &mut [u8]) -> Result<(), NoAnswer> {
+        if !self.reset().await {
+            return Err(NoAnswer);
+        }
+        self.write(rom).await;
+        for &byte in command {
+            self.write(byte).await;
+        }
+        for byte in response.iter_mut() {
+            *byte = self.read().await;
+        }
+        // A pack that lets go of the line in the middle reads as all ones.
+        if response.iter().all(|&b| b == 0xFF) {
+            return Err(NoAnswer);
+        }
+        Ok(())
+    }
+}
+
+async fn read_pack(bus: &mut OneWire<'_>) -> Result<Pack, NoAnswer> {
+    let mut model = [0; 16];
+    bus.transfer(SKIP_ROM, &CMD_MODEL, &mut model).await?;
+    let mut info = [0; INFO_LEN];
+    bus.transfer(READ_ROM, &[], &mut info).await?;
+    let mut status = [0; STATUS_LEN];
+    bus.transfer(SKIP_ROM, &CMD_STATUS, &mut status).await?;
+
+    let le = |at: usize| u16::from_le_bytes([status[at], status[at + 1]]);
+    let cells_mv: [u16; BATTERY_CELLS] = core::array::from_fn(|i| le(STATUS_CELLS + 2 * i));
+    if !cells_mv.iter().all(|mv| CELL_RANGE_MV.contains(mv)) {
+        return Err(NoAnswer);
+    }
+    let mut name = [0; 8];
+    for (byte, &c) in name.iter_mut().zip(model.iter().take_while(|c| c.is_ascii_graphic())) {
+        *byte = c;
+    }
+    Ok(Pack {
+        model: name,
+        cycles: u16::from_be_bytes([info[INFO_CYCLES], info[INFO_CYCLES + 1]]) & 0x0FFF,
+        locked: info[INFO_LOCK] & 0x0F != 0,
+        overdischarged: info[INFO_OVERDISCHARGE] != 0,
+        cells_mv,
+        temperature_deg10: (le(STATUS_TEMPERATURE) as i32 - 2731) as i16,
+    })
+}
+
+#[task]
+pub async fn process(pin: Flex<'static>) {
+    let mut bus = OneWire::new(pin);
+    let mut ticker = Ticker::every(READ_PERIOD);
+    loop {
+        let pack = read_pack(&mut bus).await.ok();
+        let previous = PACK.lock(|cell| cell.replace(pack));
+        match (previous, pack) {
+            (None, Some(pack)) => info!("Battery pack: {}", pack),
+            (Some(_), None) => info!("Battery pack stopped answering"),
+            _ => {}
+        }
+        ticker.next().await;
+    }
+}
@@ -4,21 +4,43 @@
 
 mod adc;
 mod can;
+#[cfg(feature = "display")]
 mod display;
+mod efuse;
 mod led;
+#[cfg(feature = "lxt")]
+mod lxt;
+mod params;
+mod protection;
+mod shutdown_log;
+#[cfg(feature = "soc")]
+mod soc;
 mod vmon;
 
 use {defmt_rtt as _, panic_probe as _};
 
+#[cfg(all(feature = "display", feature = "lxt"))]
+compile_error!("the display and `lxt` do not fit in flash together, build `lxt` with `--no-default-features --features soc,lxt,diagnostics`");
+
 use crate::{
     adc::process as adc_process,
     can::process as can_process,
-    display::process as display_process,
+    efuse::process as efuse_process,
     led::{Color, Led},
+    protection::process as protection_process,
+    shutdown_log::{Record, ShutdownLog},
     vmon::process as voltage_monitor_process,
 };
+#[cfg(feature = "display")]
+use crate::display::process as display_process;
+#[cfg(feature = "lxt")]
+use crate::lxt::process as lxt_process;
+#[cfg(feature = "soc")]
+use crate::soc::process as soc_process;
+use can_messages::{NodeState, NodeStateCell, ShutdownReason};
 use core::sync::atomic::{AtomicBool, Ordering};
 use defmt::info;
+use embassy_embedded_hal::adapter::BlockingAsync;
 use embassy_executor::{main, task, Spawner};
 use embassy_futures::{join::join, select::select};
 use embassy_stm32::{
@@ -26,9 +48,10 @@ use embassy_stm32::{
     bind_interrupts,
     can::{self as stm32_can, Can},
     exti::ExtiInput,
+    flash::Flash,
     gpio::{Flex, Input, Level, Output, Pull, Speed},
-    i2c::{self, mode::Master, I2c, Config as I2cConfig},
-    mode::Async,
+    i2c::{mode::Master, I2c, Config as I2cConfig},
+    mode::Blocking,
     pac, peripherals,
     time::khz,
     wdg::IndependentWatchdog,
@@ -36,10 +59,10 @@ use embassy_stm32::{
 };
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
 use embassy_time::{Duration, Timer};
+use portable_atomic::AtomicU8;
 use static_cell::StaticCell;
 
 bind_interrupts!(struct Irqs {
-    I2C1 => i2c::EventInterruptHandler<peripherals::I2C1>, i2c::ErrorInterruptHandler<peripherals::I2C1>;
     ADC1 => stm32_adc::InterruptHandler<peripherals::ADC1>;
     CEC_CAN => stm32_can::Rx0InterruptHandler<peripherals::CAN>, stm32_can::Rx1InterruptHandler<peripherals::CAN>,
                stm32_can::TxInterruptHandler<peripherals::CAN>, stm32_can::SceInterruptHandler<peripherals::CAN>;
@@ -47,6 +70,23 @@ bind_interrupts!(struct Irqs {
 
 static WANT_12V: AtomicBool = AtomicBool::new(false);
 static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_REASON: AtomicU8 = AtomicU8::new(ShutdownReason::Unknown as u8);
+static STATE: NodeStateCell = NodeStateCell::new(NodeState::Booting);
+
+/// Shared by `vmon` and the display. Their transfers are a few bytes each,
+/// which the blocking driver handles as well as the DMA one in about 2 KB
+/// less flash.
+pub type I2cBus = Mutex<NoopRawMutex, BlockingAsync<I2c<'static, Blocking, Master>>>;
+
+// Logs carry the bus time once synchronised, before that the uptime marked with `~`.
+defmt::timestamp!("{}", can::CLOCK.log_time());
+
+/// Switch off, keeping the first reason given for the next start.
+fn shutdown(reason: ShutdownReason) {
+    let unknown = ShutdownReason::Unknown as u8;
+    let _ = SHUTDOWN_REASON.compare_exchange(unknown, reason as u8, Ordering::Relaxed, Ordering::Relaxed);
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
 
 #[task]
 async fn power_process(mut btn_sense: ExtiInput<'static>) {
@@ -55,10 +95,11 @@ async fn power_process(mut btn_sense: ExtiInput<'static>) {
         select(
             async {
                 btn_sense.wait_for_low().await;
+                efuse::reset();
             },
             async {
                 Timer::after(Duration::from_millis(1000)).await;
-                SHUTDOWN.store(true, Ordering::Relaxed);
+                shutdown(ShutdownReason::Button);
             },
         )
         .await;
@@ -70,6 +111,7 @@ async fn delayed_12v_on() {
     Timer::after(Duration::from_secs(1)).await;
     info!("Turning on 12V");
     WANT_12V.store(true, Ordering::Relaxed);
+    STATE.set(NodeState::Operational);
 }
 
 #[main]
@@ -99,6 +141,21 @@ async fn main(spawner: Spawner) {
     pwr_enable.set_high();
     pwr_enable.set_as_output(Speed::Low);
 
+    // Why it switched off last time. Until it switches off properly, the
+    // latest record says nothing is known, in case the pack gets pulled.
+    let mut shutdown_log = ShutdownLog::new(Flash::new_blocking(dev.FLASH));
+    let last_shutdown = shutdown_log.last();
+    if let Some(record) = last_shutdown {
+        info!("Last shutdown: {} at {} mV", record.reason, record.battery_mv);
+    }
+    shutdown_log::LAST.lock(|cell| cell.set(last_shutdown));
+    if last_shutdown.map(|record| record.reason) != Some(ShutdownReason::Unknown) {
+        let running = Record { reason: ShutdownReason::Unknown, battery_mv: 0, time_s: 0 };
+        if shutdown_log.append(running).is_err() {
+            info!("Shutdown log not writable");
+        }
+    }
+
     // Configure watchdog
     let mut dog = IndependentWatchdog::new(dev.IWDG, 100_000);
     dog.unleash();
@@ -127,36 +184,38 @@ async fn main(spawner: Spawner) {
             dev.PA1.degrade_adc(),
         ))
         .unwrap();
+    spawner.spawn(protection_process(last_shutdown.map(|record| record.reason))).unwrap();
+
+    // Battery data contact on AUX PA5, see `lxt`
+    #[cfg(feature = "lxt")]
+    spawner.spawn(lxt_process(Flex::new(dev.PA5))).unwrap();
 
-    // AUX PA5
     // CAN_RX PA9/PA11
     // CAN_TX PA10/PA12
 
     // I²C bus
     let scl = dev.PF1;
     let sda = dev.PF0;
-    let i2c = I2c::new(
-        dev.I2C1,
-        scl,
-        sda,
-        Irqs,
-        dev.DMA1_CH2,
-        dev.DMA1_CH3,
-        {
-            let mut cfg = I2cConfig::default();
-            cfg.frequency = khz(400);
-            cfg
-        },
-    );
-    static I2C_BUS: StaticCell<Mutex<NoopRawMutex, I2c<'_, Async, Master>>> = StaticCell::new();
-    let i2c = Mutex::new(i2c);
+    let i2c = I2c::new_blocking(dev.I2C1, scl, sda, {
+        let mut cfg = I2cConfig::default();
+        cfg.frequency = khz(400);
+        cfg
+    });
+    static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+    let i2c = Mutex::new(BlockingAsync::new(i2c));
     let i2c = I2C_BUS.init(i2c);
 
     spawner.spawn(voltage_monitor_process(i2c)).unwrap();
+    spawner.spawn(efuse_process()).unwrap();
+    #[cfg(feature = "soc")]
+    spawner.spawn(soc_process()).unwrap();
     dog.pet();
 
-    spawner.spawn(display_process(i2c)).unwrap();
-    dog.pet();
+    #[cfg(feature = "display")]
+    {
+        spawner.spawn(display_process(i2c)).unwrap();
+        dog.pet();
+    }
 
     let can = Can::new(dev.CAN, dev.PA11, dev.PA12, Irqs);
     spawner.spawn(can_process(can)).unwrap();
@@ -164,13 +223,15 @@ async fn main(spawner: Spawner) {
     info!("System startup");
     spawner.spawn(delayed_12v_on()).unwrap();
     while !SHUTDOWN.load(Ordering::Relaxed) {
-        if pg_12v.is_low() {
+        if efuse::is_latched() {
+            led.set_color(Color::Yellow);
+        } else if pg_12v.is_low() {
             led.set_color(Color::Blue);
         } else {
             led.set_color(Color::Green);
         }
 
-        if WANT_12V.load(Ordering::Relaxed) {
+        if WANT_12V.load(Ordering::Relaxed) && efuse::output_allowed() {
             en_12v.set_high();
         } else {
             en_12v.set_low();
@@ -181,6 +242,17 @@ async fn main(spawner: Spawner) {
     }
 
     info!("Powering down");
+    STATE.set(NodeState::ShuttingDown);
+    en_12v.set_low();
+    dog.pet();
+    let record = Record {
+        reason: ShutdownReason::from_raw(SHUTDOWN_REASON.load(Ordering::Relaxed)),
+        battery_mv: adc::BATTERY_VOLTAGE_MV.load(Ordering::Relaxed),
+        time_s: can::CLOCK.now().map_or(0, |ms| (ms / 1000) as u32),
+    };
+    if shutdown_log.append(record).is_err() {
+        info!("Shutdown reason not recorded");
+    }
     join(
         async {
             for _ in 0..3 {
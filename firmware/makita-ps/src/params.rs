@@ -0,0 +1,88 @@
+#[cfg(feature = "soc")]
+use crate::soc::BATTERY_CAPACITY_MAH;
+use crate::{can::NODE_ID, efuse, protection, vmon::SHUNT_RESISTANCE_MILLIS};
+use can_messages::{Param, ParamStatus, ParamTable, ParamValue};
+use core::sync::atomic::Ordering;
+
+pub static PARAMS: ParamTable = ParamTable::new(
+    NODE_ID,
+    &[
+        Param {
+            index: 0,
+            name: "shunt_resistance_mohm",
+            get: || ParamValue::I16(SHUNT_RESISTANCE_MILLIS.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::I16(v @ 1..=1000) => {
+                    SHUNT_RESISTANCE_MILLIS.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+        #[cfg(feature = "soc")]
+        Param {
+            // Only used if the pack does not tell its model.
+            index: 2,
+            name: "battery_capacity_mah",
+            get: || ParamValue::U16(BATTERY_CAPACITY_MAH.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::U16(v @ 100..=60_000) => {
+                    BATTERY_CAPACITY_MAH.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+        Param {
+            // Raised to the cutoff if set below it.
+            index: 3,
+            name: "uvlo_warning_mv",
+            get: || ParamValue::U16(protection::WARNING_MV.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::U16(v @ 12_000..=21_000) => {
+                    protection::WARNING_MV.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+        Param {
+            index: 4,
+            name: "uvlo_cutoff_mv",
+            get: || ParamValue::U16(protection::CUTOFF_MV.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::U16(v @ 12_000..=18_000) => {
+                    protection::CUTOFF_MV.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+        Param {
+            // Below the 15 A of the board fuse, so the output trips first.
+            index: 5,
+            name: "efuse_rated_ma",
+            get: || ParamValue::U16(efuse::RATED_MA.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::U16(v @ 500..=14_000) => {
+                    efuse::RATED_MA.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+        Param {
+            // Raised to the rated current if set below it.
+            index: 6,
+            name: "efuse_short_circuit_ma",
+            get: || ParamValue::U16(efuse::SHORT_CIRCUIT_MA.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::U16(v @ 1000..=efuse::BOARD_FUSE_MA) => {
+                    efuse::SHORT_CIRCUIT_MA.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+    ],
+);
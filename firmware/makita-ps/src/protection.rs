@@ -0,0 +1,87 @@
+//! Battery undervoltage lockout
+//!
+//! Below the warning threshold the power supply warns on CAN, below the
+//! cutoff it switches itself off before the pack cuts out on its own, which
+//! may leave the pack locked. The levels come from a [`Monitor`].
+
+use crate::adc::BATTERY_VOLTAGE_MV;
+use battery_monitor::uvlo::{Monitor, HYSTERESIS_MV};
+use can_messages::{Protection, ProtectionEvent, ProtectionLevel, ShutdownReason};
+use core::sync::atomic::{AtomicU16, Ordering};
+use defmt::info;
+use embassy_executor::task;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Ticker};
+
+/// 3.2 V per cell.
+pub static WARNING_MV: AtomicU16 = AtomicU16::new(16_000);
+/// 3.0 V per cell.
+pub static CUTOFF_MV: AtomicU16 = AtomicU16::new(15_000);
+
+/// Level changes and repeated warnings of all protections, for CAN.
+pub static EVENTS: Channel<CriticalSectionRawMutex, ProtectionEvent, 4> = Channel::new();
+
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+const WARNING_REPEAT: Duration = Duration::from_secs(5);
+
+/// Queue an event for CAN.
+pub fn report(protection: Protection, level: ProtectionLevel, value: u16, limit: u16) {
+    let event = ProtectionEvent::new(protection, level, value, limit);
+    if EVENTS.try_send(event).is_err() {
+        info!("Protection event dropped");
+    }
+}
+
+fn trip(battery_mv: u16, cutoff_mv: u16) {
+    info!("Battery undervoltage {} mV, switching off", battery_mv);
+    report(Protection::Undervoltage, ProtectionLevel::Tripped, battery_mv, cutoff_mv);
+    crate::shutdown(ShutdownReason::Undervoltage);
+}
+
+/// `last_shutdown` is why the power supply switched off before this start.
+#[task]
+pub async fn process(last_shutdown: Option<ShutdownReason>) {
+    let mut ticker = Ticker::every(CHECK_PERIOD);
+    let battery_mv = loop {
+        ticker.next().await;
+        match BATTERY_VOLTAGE_MV.load(Ordering::Relaxed) {
+            u16::MAX => continue,
+            mv => break mv,
+        }
+    };
+    // A pack recovers a bit once the load is gone, that is no reason to drain it further.
+    let cutoff_mv = CUTOFF_MV.load(Ordering::Relaxed);
+    if last_shutdown == Some(ShutdownReason::Undervoltage) && battery_mv < cutoff_mv.saturating_add(HYSTERESIS_MV) {
+        trip(battery_mv, cutoff_mv);
+        return;
+    }
+
+    let mut monitor = Monitor::new();
+    let mut warned = Instant::now();
+    loop {
+        ticker.next().await;
+        let now = Instant::now();
+        let battery_mv = BATTERY_VOLTAGE_MV.load(Ordering::Relaxed);
+        let cutoff_mv = CUTOFF_MV.load(Ordering::Relaxed);
+        let warning_mv = WARNING_MV.load(Ordering::Relaxed).max(cutoff_mv);
+        let previous = monitor.level();
+        match monitor.update(battery_mv, warning_mv, cutoff_mv, now) {
+            ProtectionLevel::Tripped => {
+                trip(battery_mv, cutoff_mv);
+                return;
+            }
+            ProtectionLevel::Warning if previous != ProtectionLevel::Warning || now - warned >= WARNING_REPEAT => {
+                if previous != ProtectionLevel::Warning {
+                    info!("Battery low {} mV", battery_mv);
+                }
+                report(Protection::Undervoltage, ProtectionLevel::Warning, battery_mv, warning_mv);
+                warned = now;
+            }
+            ProtectionLevel::Normal if previous != ProtectionLevel::Normal => {
+                info!("Battery recovered {} mV", battery_mv);
+                report(Protection::Undervoltage, ProtectionLevel::Normal, battery_mv, warning_mv);
+            }
+            _ => {}
+        }
+    }
+}
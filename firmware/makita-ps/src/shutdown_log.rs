@@ -0,0 +1,91 @@
+//! Why the power supply switched off, kept across power cycles
+//!
+//! Records are appended to the application data page and the page is erased
+//! only once it is full, so the latest record is the last one written.
+
+use can_messages::{boot, ShutdownReason};
+use core::cell::Cell;
+use embassy_stm32::flash::{Blocking, Error, Flash};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+/// Offset of the page in flash.
+const PAGE: u32 = boot::APP_DATA - boot::FLASH_BASE;
+const RECORD_LEN: u32 = 8;
+/// First byte of a written record, erased flash reads as 0xFF.
+const MARK: u8 = 0x5D;
+
+/// Latest record from before this start, for CAN.
+pub static LAST: Mutex<CriticalSectionRawMutex, Cell<Option<Record>>> = Mutex::new(Cell::new(None));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub reason: ShutdownReason,
+    pub battery_mv: u16,
+    /// Bus time, 0 if the clock was not synchronised.
+    pub time_s: u32,
+}
+
+impl Record {
+    fn to_bytes(self) -> [u8; RECORD_LEN as usize] {
+        let [mv0, mv1] = self.battery_mv.to_le_bytes();
+        let [t0, t1, t2, t3] = self.time_s.to_le_bytes();
+        [MARK, self.reason as u8, mv0, mv1, t0, t1, t2, t3]
+    }
+
+    fn from_bytes(bytes: [u8; RECORD_LEN as usize]) -> Self {
+        Self {
+            reason: ShutdownReason::from_raw(bytes[1]),
+            battery_mv: u16::from_le_bytes([bytes[2], bytes[3]]),
+            time_s: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+pub struct ShutdownLog {
+    flash: Flash<'static, Blocking>,
+    /// Offset of the first free slot, `None` when the page needs an erase first.
+    free: Option<u32>,
+    last: Option<Record>,
+}
+
+impl ShutdownLog {
+    pub fn new(mut flash: Flash<'static, Blocking>) -> Self {
+        let mut free = None;
+        let mut last = None;
+        for offset in (PAGE..PAGE + boot::PAGE_SIZE).step_by(RECORD_LEN as usize) {
+            let mut bytes = [0; RECORD_LEN as usize];
+            if flash.blocking_read(offset, &mut bytes).is_err() {
+                break;
+            }
+            match bytes[0] {
+                MARK => last = Some(Record::from_bytes(bytes)),
+                0xFF => {
+                    free = Some(offset);
+                    break;
+                }
+                // Garbage, start over with an erased page.
+                _ => break,
+            }
+        }
+        Self { flash, free, last }
+    }
+
+    /// Latest record, from before this start.
+    pub fn last(&self) -> Option<Record> {
+        self.last
+    }
+
+    /// Blocks for the page erase if the page is full, some 40 ms.
+    pub fn append(&mut self, record: Record) -> Result<(), Error> {
+        let offset = match self.free {
+            Some(offset) => offset,
+            None => {
+                self.flash.blocking_erase(PAGE, PAGE + boot::PAGE_SIZE)?;
+                PAGE
+            }
+        };
+        self.flash.blocking_write(offset, &record.to_bytes())?;
+        self.free = Some(offset + RECORD_LEN).filter(|&next| next < PAGE + boot::PAGE_SIZE);
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+//! Battery state of charge from open-circuit voltage and coulomb counting
+//!
+//! The INA219 measures on the 12 V side of the converter, so the battery
+//! current is estimated from the output power, the battery voltage and the
+//! converter efficiency, ignoring what the board itself draws. Its integral
+//! goes into an [`Estimator`].
+
+#[cfg(feature = "lxt")]
+use crate::lxt::PACK;
+use crate::{adc::BATTERY_VOLTAGE_MV, vmon::OUTPUT_POWER_MW};
+use battery_monitor::soc::{Estimate, Estimator};
+#[cfg(feature = "lxt")]
+use can_messages::BatteryModel;
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicU16, Ordering},
+};
+use defmt::info;
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Ticker};
+
+/// Used unless the pack tells its model.
+pub static BATTERY_CAPACITY_MAH: AtomicU16 = AtomicU16::new(5000);
+
+/// Latest estimate, `None` until the battery voltage is known.
+pub static SOC: Mutex<CriticalSectionRawMutex, Cell<Option<Estimate>>> = Mutex::new(Cell::new(None));
+
+const UPDATE_PERIOD: Duration = Duration::from_millis(100);
+/// Of the 12 V converter, in percent.
+const CONVERTER_EFFICIENCY_PCT: u32 = 90;
+
+/// Battery current from the output power.
+fn battery_current_ma(battery_mv: u16) -> u32 {
+    let output_mw = OUTPUT_POWER_MW.load(Ordering::Relaxed);
+    output_mw * 1000 / battery_mv.max(1) as u32 * 100 / CONVERTER_EFFICIENCY_PCT
+}
+
+fn capacity_mah() -> u32 {
+    #[cfg(feature = "lxt")]
+    if let Some(mah) = PACK.lock(|cell| cell.get()).and_then(|pack| BatteryModel { model: pack.model }.capacity_mah()) {
+        return mah as u32;
+    }
+    BATTERY_CAPACITY_MAH.load(Ordering::Relaxed) as u32
+}
+
+#[task]
+pub async fn process() {
+    let mut ticker = Ticker::every(UPDATE_PERIOD);
+    let mut estimator: Option<Estimator> = None;
+    let mut last = Instant::now();
+    loop {
+        ticker.next().await;
+        let now = Instant::now();
+        let elapsed = now - last;
+        last = now;
+
+        let battery_mv = BATTERY_VOLTAGE_MV.load(Ordering::Relaxed);
+        if battery_mv == u16::MAX {
+            continue;
+        }
+        let capacity_mah = capacity_mah();
+        let estimator = estimator.get_or_insert_with(|| {
+            let estimator = Estimator::new(battery_mv, capacity_mah);
+            info!("Battery state of charge at startup: {}", estimator.estimate());
+            estimator
+        });
+        if estimator.capacity_mah() != capacity_mah {
+            info!("Battery capacity {} mAh", capacity_mah);
+            estimator.set_capacity(capacity_mah);
+        }
+        estimator.update(battery_mv, battery_current_ma(battery_mv), elapsed, now);
+        SOC.lock(|cell| cell.set(Some(estimator.estimate())));
+    }
+}
@@ -1,49 +1,91 @@
+use crate::{efuse, I2cBus};
 use core::sync::atomic::Ordering;
-use defmt::info;
+use defmt::{error, info};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_executor::task;
-use embassy_stm32::{
-    i2c::{mode::Master, I2c},
-    mode::Async,
-};
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
 use embassy_time::{Duration, Timer};
-use ina219::{
-    address::{Address as Ina219Address, Pin as Ina219Pin},
-    AsyncIna219,
+use ina219_async::{
+    AdcMode, BusRange, Calibration, Config as Ina219Config, Ina219, OperatingMode, ShuntRange, DEFAULT_ADDRESS,
 };
-use portable_atomic::AtomicI16;
+use portable_atomic::{AtomicI16, AtomicI32, AtomicU32};
+
+pub static OUTPUT_VOLTAGE_MV: AtomicU32 = AtomicU32::new(0);
+pub static OUTPUT_CURRENT_MA: AtomicI32 = AtomicI32::new(0);
+pub static OUTPUT_POWER_MW: AtomicU32 = AtomicU32::new(0);
 
-pub static OUTPUT_VOLTAGE_MV: AtomicI16 = AtomicI16::new(0);
-pub static OUTPUT_CURRENT_MA: AtomicI16 = AtomicI16::new(0);
+pub static SHUNT_RESISTANCE_MILLIS: AtomicI16 = AtomicI16::new(2); // mOhm
+/// Samples lost to I²C errors, the fuse goes without them.
+pub static MISSED_SAMPLES: AtomicU32 = AtomicU32::new(0);
 
-const SHUNT_RESISTANCE_MILLIS: i16 = 2; // mOhm
+/// ±80 mV is 40 A across the 2 mΩ shunt, well past the board fuse. Two
+/// shunt samples averaged and the bus voltage take 1.6 ms, within a sample.
+const CONFIG: Ina219Config = Ina219Config {
+    bus_range: BusRange::V16,
+    shunt_range: ShuntRange::Mv80,
+    bus_adc: AdcMode::Bits12,
+    shunt_adc: AdcMode::Samples2,
+    mode: OperatingMode::ShuntBusContinuous,
+};
+/// Fast enough for the electronic fuse to catch a short circuit.
+const SAMPLE_PERIOD: Duration = Duration::from_millis(2);
+/// Log and check the calibration only every n-th sample.
+const LOG_EVERY: u32 = 500;
 
 #[task]
-pub async fn process(i2c: &'static Mutex<NoopRawMutex, I2c<'static, Async, Master>>) {
+pub async fn process(i2c: &'static I2cBus) {
     let i2c = I2cDevice::new(i2c);
     info!("Voltage monitor process started.");
-    let mut output_monitor = AsyncIna219::new(
-        i2c,
-        Ina219Address::from_pins(Ina219Pin::Gnd, Ina219Pin::Gnd),
-    )
-    .await
-    .expect("INA219 initialization error");
+    let mut output_monitor = Ina219::new(i2c, DEFAULT_ADDRESS);
+    let mut calibrated_mohm = None;
+    let mut samples: u32 = 0;
+    let mut missing = false;
     loop {
-        let out_i = output_monitor
-            .shunt_voltage()
-            .await
-            .expect("INA219 current measurement error")
-            .shunt_voltage_uv() as i16;
-        let out_i = out_i / SHUNT_RESISTANCE_MILLIS;
-        let out_v = output_monitor
-            .bus_voltage()
-            .await
-            .expect("INA219 voltage measurement error")
-            .voltage_mv() as i16;
-        OUTPUT_VOLTAGE_MV.store(out_v, Ordering::Relaxed);
-        OUTPUT_CURRENT_MA.store(out_i, Ordering::Relaxed);
-        info!("Output: {} mV, {}", out_v, out_i);
-        Timer::after(Duration::from_millis(100)).await;
+        // Again after a parameter change, and after a brown-out of the chip.
+        let shunt_mohm = SHUNT_RESISTANCE_MILLIS.load(Ordering::Relaxed);
+        let check = samples.is_multiple_of(LOG_EVERY);
+        let result = async {
+            if calibrated_mohm != Some(shunt_mohm) || check && !output_monitor.is_calibrated().await? {
+                let calibration =
+                    Calibration::new(shunt_mohm as u32 * 1000, CONFIG.shunt_range).expect("shunt resistance is positive");
+                output_monitor.configure(&CONFIG, calibration).await?;
+                info!("INA219 calibrated for {} mOhm: {}", shunt_mohm, calibration);
+                calibrated_mohm = Some(shunt_mohm);
+            }
+            output_monitor.read().await
+        }
+        .await;
+
+        match result {
+            Ok(Some(out)) => {
+                // Beyond the current register the saturated shunt voltage still trips the fuse.
+                let (out_i, out_p) = if out.overflow {
+                    let out_i = out.shunt_uv / shunt_mohm as i32;
+                    (out_i, out.bus_mv * out_i.max(0) as u32 / 1000)
+                } else {
+                    (out.current_ma, out.power_mw)
+                };
+                OUTPUT_VOLTAGE_MV.store(out.bus_mv, Ordering::Relaxed);
+                OUTPUT_CURRENT_MA.store(out_i, Ordering::Relaxed);
+                OUTPUT_POWER_MW.store(out_p, Ordering::Relaxed);
+                efuse::sample(out_i);
+                if check {
+                    info!("Output: {} mV, {} mA, {} mW", out.bus_mv, out_i, out_p);
+                }
+                missing = false;
+            }
+            Ok(None) => {}
+            Err(_) => {
+                let missed = MISSED_SAMPLES.fetch_add(1, Ordering::Relaxed) + 1;
+                // Once per run of errors, a stuck bus would flood the log otherwise.
+                if !missing {
+                    error!("INA219 sample missed, {} so far", missed);
+                }
+                missing = true;
+                // Whatever the chip got of a configuration, write all of it again.
+                calibrated_mohm = None;
+            }
+        }
+        samples = samples.wrapping_add(1);
+        Timer::after(SAMPLE_PERIOD).await;
     }
 }
@@ -1,5 +1,22 @@
+use can_messages::{
+    boot::{memory_x, Image},
+    emit_git_env,
+};
+use std::{env, fs, path::PathBuf};
+
 fn main() {
+    // Flash layout, see `can_messages::boot`.
+    let image = if env::var_os("CARGO_FEATURE_BOOTLOADER").is_some() { Image::Application } else { Image::Standalone };
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("memory.x"), memory_x(image)).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+    // Build identification, see `can_messages::firmware_id!`.
+    emit_git_env();
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
 }
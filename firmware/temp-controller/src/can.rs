@@ -1,49 +1,127 @@
-use embassy_stm32::can::{Can, CanRx, CanTx};
+use embassy_stm32::{can::{Can, CanRx, CanTx, Frame}, uid};
 use embassy_executor::task;
-use defmt::{info, Debug2Format};
-use embassy_time::Timer;
-use embassy_futures::join::join;
-use can_messages::{prelude::*, BITRATE, CoolBox};
-use crate::temperature::TEMPERATURE;
+use defmt::info;
+use embassy_time::{Duration, Instant, Timer};
+use embassy_futures::{join::join3, select::{select3, Either3}};
+use embassy_sync::{blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex}, channel::Channel, signal::Signal};
+use can_messages::{
+    prelude::*, boot, firmware_id, BIT_TIMING, CanDiagnostics, CoolBox, CounterCheck, EnterBootloader,
+    Heartbeat, identification, IdentifyRequest, NodeId, ParamRequest, ParamResponse, TimeSync, CAN_DIAGNOSTICS_PERIOD_MS,
+    HEARTBEAT_PERIOD_MS,
+};
+use can_messages::clock::SyncedClock;
+use can_messages::filter::{FilterPlan, BXCAN_BANKS};
+use can_messages::scheduler::{Scheduler, Trigger};
+use can_messages::supervisor::{supervise, CanStats, RX_ERROR_BACKOFF_MS, SUPERVISOR_PERIOD_MS};
+use crate::{params::PARAMS, temperature::TEMPERATURE};
 use core::sync::atomic::Ordering;
 
+pub const NODE_ID: NodeId = NodeId::COOLBOX;
+
+type Responses = Channel<NoopRawMutex, ParamResponse, 4>;
+type Identify = Signal<NoopRawMutex, ()>;
+
+static CAN_STATS: CanStats<CriticalSectionRawMutex> = CanStats::new();
+/// Time of the bus master.
+pub static CLOCK: SyncedClock<CriticalSectionRawMutex> = SyncedClock::new();
+
+const FILTERS: FilterPlan<BXCAN_BANKS> =
+    FilterPlan::new(&[ParamRequest::ID, IdentifyRequest::ID, EnterBootloader::ID, TimeSync::ID]);
+
 #[task]
 pub async fn process(mut can: Can<'static>) {
-    can.set_bitrate(BITRATE);
+    can.modify_config().set_bit_timing(BIT_TIMING);
     can.set_tx_fifo_scheduling(true);
     can.enable().await;
     info!("CAN initialized.");
     let (tx, rx) = can.split();
-    join(transmit(tx), receive(rx)).await;
+    let responses = Responses::new();
+    let identify = Identify::new();
+    join3(
+        transmit(tx, &responses, &identify),
+        receive(rx, &responses, &identify),
+        supervise(&CAN_STATS, Duration::from_millis(SUPERVISOR_PERIOD_MS)),
+    )
+    .await;
 }
 
-async fn receive(mut rx: CanRx<'static>) {
+async fn receive(mut rx: CanRx<'static>, responses: &Responses, identify: &Identify) {
+    FILTERS.apply(&mut rx);
+    let mut bootloader_counter = CounterCheck::new();
     loop {
-        let _ = rx.read().await;
+        let Ok(msg) = rx.read().await else {
+            Timer::after_millis(RX_ERROR_BACKOFF_MS).await;
+            continue;
+        };
+        if let Some(sync) = msg.frame.try_decode::<TimeSync>() {
+            CLOCK.update(sync.time_ms.get(), msg.ts);
+        }
+        if let Some(response) = msg.frame.try_decode::<ParamRequest>().and_then(|req| PARAMS.handle(req)) {
+            if responses.try_send(response).is_err() {
+                info!("CAN parameter response dropped");
+            }
+        }
+        if msg.frame.try_decode::<IdentifyRequest>().is_some_and(|req| req.is_for(NODE_ID)) {
+            identify.signal(());
+        }
+        // Without the bootloader there is nothing to restart into.
+        if let Some(cmd) = msg.frame.try_decode::<EnterBootloader>() {
+            if cfg!(feature = "bootloader") && cmd.target == NODE_ID.raw() && bootloader_counter.accept(cmd) {
+                // SAFETY: only built with the `bootloader` feature, whose memory layout keeps the flag free.
+                unsafe { boot::enter_bootloader() }
+            }
+        }
     }
 }
 
-async fn transmit(mut tx: CanTx<'static>) {
-    let mut mailbox = None;
+async fn transmit(tx: CanTx<'static>, responses: &Responses, identify: &Identify) {
+    let mut scheduler = Scheduler::<_, 3>::new(tx);
+    scheduler.add(Trigger::Periodic(Duration::from_millis(100)), coolbox);
+    scheduler.add(Trigger::Periodic(Duration::from_millis(HEARTBEAT_PERIOD_MS)), heartbeat);
+    scheduler.add(
+        Trigger::OnChange {
+            check: Duration::from_millis(SUPERVISOR_PERIOD_MS),
+            refresh: Duration::from_millis(CAN_DIAGNOSTICS_PERIOD_MS),
+        },
+        can_diagnostics,
+    );
+    send_identification(&mut scheduler).await;
+    let mut dropped = 0;
     loop {
-        let box_temperature_deg10 = TEMPERATURE.load(Ordering::Relaxed);
-
-        let data = CoolBox {
-            box_temperature_deg10,
-        };
-
-        if let Some(frame) = data.try_encode() {
-            if let Some(mbox) = mailbox.take() {
-                let r = tx.abort(mbox);
-                info!("CAN send: {}", r);
-            }
-            if let Ok(wr) = tx.try_write(&frame) {
-                mailbox = Some(wr.mailbox());
-            } else {
-                info!("CAN send fail");
+        match select3(scheduler.tick(), responses.receive(), identify.wait()).await {
+            Either3::First(()) => {}
+            Either3::Second(response) => {
+                if let Some(frame) = response.try_encode() {
+                    scheduler.send(&frame).await;
+                }
             }
+            Either3::Third(()) => send_identification(&mut scheduler).await,
         }
+        CAN_STATS.record_tx_dropped(scheduler.dropped().wrapping_sub(dropped));
+        dropped = scheduler.dropped();
+    }
+}
+
+async fn send_identification(scheduler: &mut Scheduler<CanTx<'static>, 3>) {
+    for frame in identification(firmware_id!(NODE_ID), uid::uid()) {
+        scheduler.send(&frame).await;
+    }
+}
+
+fn can_diagnostics() -> Option<Frame> {
+    CanDiagnostics::new(NODE_ID, CAN_STATS.status(), CAN_STATS.counters()).encode()
+}
+
+fn heartbeat() -> Option<Frame> {
+    let uptime_s = Instant::now().as_secs() as u32;
+    Heartbeat::new(NODE_ID, crate::STATE.get(), uptime_s).encode()
+}
+
+fn coolbox() -> Option<Frame> {
+    let box_temperature_deg10 = TEMPERATURE.load(Ordering::Relaxed);
 
-        Timer::after_millis(100).await;
+    CoolBox {
+        box_temperature: box_temperature_deg10.into(),
     }
+    .try_encode()
 }
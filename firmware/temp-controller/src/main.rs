@@ -5,10 +5,12 @@
 mod adc;
 mod temperature;
 mod can;
+mod params;
 
 use {defmt_rtt as _, panic_probe as _};
 
 use crate::{adc::process as adc_process, temperature::process as temperature_process, can::process as can_process};
+use can_messages::{NodeState, NodeStateCell};
 use core::{
     cell::RefCell,
     sync::atomic::{AtomicBool, Ordering},
@@ -24,8 +26,7 @@ use embassy_stm32::{
     can::{self as stm32_can, Can},
     exti::ExtiInput,
     gpio::{Flex, Input, Level, Output, OutputType, Pull, Speed},
-    i2c::{self, I2c, Config as I2cConfig},
-    mode::Async,
+    i2c::{I2c, Config as I2cConfig},
     peripherals,
     time::{khz, mhz},
     timer::{
@@ -39,15 +40,24 @@ use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
 use embassy_time::Timer;
 use num_traits::float::FloatCore;
 use pid::Pid;
+use portable_atomic::AtomicF32;
 use static_cell::StaticCell;
 
 bind_interrupts!(struct Irqs {
-    I2C1 => i2c::EventInterruptHandler<peripherals::I2C1>, i2c::ErrorInterruptHandler<peripherals::I2C1>;
     ADC1 => stm32_adc::InterruptHandler<peripherals::ADC1>;
     CEC_CAN => stm32_can::Rx0InterruptHandler<peripherals::CAN>, stm32_can::Rx1InterruptHandler<peripherals::CAN>,
                stm32_can::TxInterruptHandler<peripherals::CAN>, stm32_can::SceInterruptHandler<peripherals::CAN>;
 });
 
+static SETPOINT: AtomicF32 = AtomicF32::new(20.0);
+static KP: AtomicF32 = AtomicF32::new(10.0);
+static KI: AtomicF32 = AtomicF32::new(0.1);
+static KD: AtomicF32 = AtomicF32::new(0.1);
+static STATE: NodeStateCell = NodeStateCell::new(NodeState::Booting);
+
+// Logs carry the bus time once synchronised, before that the uptime marked with `~`.
+defmt::timestamp!("{}", can::CLOCK.log_time());
+
 #[main]
 async fn main(spawner: Spawner) {
     // HSI oscillator 12 MHz, 64 MHz system frequency
@@ -72,19 +82,11 @@ async fn main(spawner: Spawner) {
 
     let sda = dev.PF0;
     let scl = dev.PB8;
-    let i2c = I2c::new(
-        dev.I2C1,
-        scl,
-        sda,
-        Irqs,
-        dev.DMA1_CH2,
-        dev.DMA1_CH3,
-        {
-            let mut cfg = I2cConfig::default();
-            cfg.frequency = khz(400);
-            cfg
-        },
-    );
+    let i2c = I2c::new_blocking(dev.I2C1, scl, sda, {
+        let mut cfg = I2cConfig::default();
+        cfg.frequency = khz(400);
+        cfg
+    });
 
     let ch0 = PwmPin::new(dev.PA0, OutputType::PushPull);
     let ch1 = PwmPin::new(dev.PA1, OutputType::PushPull);
@@ -128,11 +130,14 @@ async fn main(spawner: Spawner) {
     pwm.ch3.enable();
     pwm.ch4.enable();
 
-    let mut pid = Pid::<f32>::new(20.0, 100.0);
-    pid.p(10.0, 100.0).i(0.1, 50.0).d(0.1, 10.0);
+    let mut pid = Pid::<f32>::new(SETPOINT.load(Ordering::Relaxed), 100.0);
 
     loop {
         Timer::after_millis(100).await;
+        pid.setpoint(SETPOINT.load(Ordering::Relaxed))
+            .p(KP.load(Ordering::Relaxed), 100.0)
+            .i(KI.load(Ordering::Relaxed), 50.0)
+            .d(KD.load(Ordering::Relaxed), 10.0);
         let t = temperature::TEMPERATURE.load(Ordering::Relaxed) as f32 / 10.0;
         let v = pid.next_control_output(t);
 
@@ -0,0 +1,50 @@
+use crate::{can::NODE_ID, KD, KI, KP, SETPOINT};
+use can_messages::{Param, ParamStatus, ParamTable, ParamValue};
+use core::sync::atomic::Ordering;
+use portable_atomic::AtomicF32;
+
+fn set_gain(gain: &AtomicF32, value: ParamValue) -> Result<(), ParamStatus> {
+    match value {
+        ParamValue::F32(v) if v.is_finite() && v >= 0.0 => {
+            gain.store(v, Ordering::Relaxed);
+            Ok(())
+        }
+        _ => Err(ParamStatus::OutOfRange),
+    }
+}
+
+pub static PARAMS: ParamTable = ParamTable::new(
+    NODE_ID,
+    &[
+        Param {
+            index: 0,
+            name: "setpoint_degc",
+            get: || ParamValue::F32(SETPOINT.load(Ordering::Relaxed)),
+            set: Some(|value| match value {
+                ParamValue::F32(v) if (-30.0..=50.0).contains(&v) => {
+                    SETPOINT.store(v, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ParamStatus::OutOfRange),
+            }),
+        },
+        Param {
+            index: 1,
+            name: "kp",
+            get: || ParamValue::F32(KP.load(Ordering::Relaxed)),
+            set: Some(|value| set_gain(&KP, value)),
+        },
+        Param {
+            index: 2,
+            name: "ki",
+            get: || ParamValue::F32(KI.load(Ordering::Relaxed)),
+            set: Some(|value| set_gain(&KI, value)),
+        },
+        Param {
+            index: 3,
+            name: "kd",
+            get: || ParamValue::F32(KD.load(Ordering::Relaxed)),
+            set: Some(|value| set_gain(&KD, value)),
+        },
+    ],
+);
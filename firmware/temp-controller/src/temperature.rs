@@ -1,27 +1,27 @@
+use can_messages::NodeState;
 use core::sync::atomic::{AtomicI16, AtomicU16, Ordering};
 use defmt::{debug, info};
 use embassy_executor::task;
-use embassy_stm32::{i2c::{I2c, mode::Master}, mode::Async};
+use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_stm32::{i2c::{I2c, mode::Master}, mode::Blocking};
 use embassy_time::{Delay, Timer};
 use hdc1080_async::Hdc1080;
 
 use embedded_hal_async::i2c::I2c as I2cAsync;
 
-use defmt::Debug2Format;
-
 pub static TEMPERATURE: AtomicI16 = AtomicI16::new(200);
 
 #[task]
-pub async fn process(i2c: I2c<'static, Async, Master>) {
+pub async fn process(i2c: I2c<'static, Blocking, Master>) {
     Timer::after_millis(1000).await;
 
     info!("Initializing temperature reading");
-    let mut sensor = Hdc1080::new(i2c, Delay);
+    let mut sensor = Hdc1080::new(BlockingAsync::new(i2c), Delay);
     let id = sensor
         .identify_async()
         .await
         .expect("Can't communicate with sensor");
-    info!("Sensor ID: {:?}", Debug2Format(&id));
+    info!("Sensor ID: {}", id);
     info!(
         "Sensor ID is {}valid.",
         if id.is_valid() { "" } else { "NOT " }
@@ -34,5 +34,6 @@ pub async fn process(i2c: I2c<'static, Async, Master>) {
         let (t, h) = sensor.read_async().await.expect("Sensor failure");
         info!("T = {}  H = {}", t.degrees_10(), h.percent_10());
         TEMPERATURE.store(t.degrees_10(), Ordering::Relaxed);
+        crate::STATE.set(NodeState::Operational);
     }
 }
@@ -6,10 +6,10 @@ use {defmt_rtt as _, panic_probe as _};
 
 use defmt::{info, Debug2Format};
 use embassy_executor::{main, task, Spawner};
-use embassy_futures::{join::join, select::select};
+use embassy_futures::{join::join, select::{select, select4, Either, Either4}};
 use embassy_stm32::{
     bind_interrupts,
-    can::{self as stm32_can, Can, Fifo, filter::Mask32, Id, StandardId, CanTx},
+    can::{self as stm32_can, Can, Id, StandardId, CanTx},
     gpio::{Level, Output, Speed, Pull},
     i2c::{self, mode::Master, I2c, Config as I2cConfig},
     mode::Async,
@@ -18,13 +18,21 @@ use embassy_stm32::{
     Config as DeviceConfig,
     pac,
     exti::ExtiInput,
+    uid,
 };
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306Async};
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_sync::{blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex}, mutex::Mutex, signal::Signal};
 use static_cell::StaticCell;
-use embassy_time::{Duration, Timer};
-use can_messages::{prelude::*, BITRATE, PowerOff, BatteryData, CoolBox};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use can_messages::{
+    prelude::*, firmware_id, BIT_TIMING, PowerOff, BatteryData, CoolBox, Heartbeat, Liveness, LivenessTracker, NodeId,
+    BusState, CanDiagnostics, identification, IdentifyRequest, NodeState, TimeSync, CAN_DIAGNOSTICS_PERIOD_MS,
+    HEARTBEAT_PERIOD_MS, TIME_SYNC_PERIOD_MS,
+    cache::Latest,
+    filter::{FilterPlan, BXCAN_BANKS},
+    supervisor::{self, CanStats, RX_ERROR_BACKOFF_MS, SUPERVISOR_PERIOD_MS},
+};
 use heapless::String;
 use core::fmt::Write;
 
@@ -34,14 +42,106 @@ bind_interrupts!(struct Irqs {
                stm32_can::TxInterruptHandler<peripherals::CAN>, stm32_can::SceInterruptHandler<peripherals::CAN>;
 });
 
+const NODE_ID: NodeId = NodeId::DISPLAY;
+
+/// Nodes missing heartbeats for this long are shown as lost.
+const LIVENESS_TIMEOUT_MS: u64 = 3 * HEARTBEAT_PERIOD_MS;
+
+/// Watched nodes and their labels.
+const NODES: [(NodeId, &str); 2] = [
+    (NodeId::POWER_SUPPLY, "Bat"),
+    (NodeId::COOLBOX, "Temp"),
+];
+
+/// Only what is shown: the telemetry, heartbeats and CAN diagnostics of the watched nodes.
+const FILTERS: FilterPlan<BXCAN_BANKS> = FilterPlan::new(&[
+    BatteryData::ID,
+    CoolBox::ID,
+    IdentifyRequest::ID,
+    NODES[0].0.message_id::<Heartbeat>(),
+    NODES[1].0.message_id::<Heartbeat>(),
+    NODES[0].0.message_id::<CanDiagnostics>(),
+    NODES[1].0.message_id::<CanDiagnostics>(),
+]);
+
+/// Values older than this are not shown.
+const DATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+static BATTERY: Latest<CriticalSectionRawMutex, BatteryData> = Latest::new();
+static COOLBOX: Latest<CriticalSectionRawMutex, CoolBox> = Latest::new();
+static CAN_STATS: CanStats<CriticalSectionRawMutex> = CanStats::new();
+/// Send the identification, at startup and on request.
+static IDENTIFY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// The display is the time master, its uptime is the bus time.
+defmt::timestamp!("{=u64:ms}", Instant::now().as_millis());
+
+/// Bus state and error counters in 16 characters, e.g. `Bat  act  0/  0`.
+fn write_bus_status(line: &mut String<16>, label: &str, state: BusState, tec: u8, rec: u8) -> core::fmt::Result {
+    let state = match state {
+        BusState::ErrorActive => "act",
+        BusState::ErrorWarning => "wrn",
+        BusState::ErrorPassive => "pas",
+        BusState::BusOff => "OFF",
+    };
+    write!(line, "{:<4} {} {:>3}/{:>3}", label, state, tec, rec)
+}
+
 #[task]
-async fn send_poweroff(mut tx: CanTx<'static>, mut btn: ExtiInput<'static>) {
+async fn transmit(mut tx: CanTx<'static>, mut btn: ExtiInput<'static>) {
+    let mut heartbeat = Ticker::every(Duration::from_millis(HEARTBEAT_PERIOD_MS));
+    let mut diagnostics = Ticker::every(Duration::from_millis(CAN_DIAGNOSTICS_PERIOD_MS));
+    let mut time_sync = Ticker::every(Duration::from_millis(TIME_SYNC_PERIOD_MS));
+    // Unset until the first press, see below.
+    let mut counter: Option<u8> = None;
+    IDENTIFY.signal(());
     loop {
-        btn.wait_for_falling_edge().await;
-        tx.write(&PowerOff.try_encode().unwrap()).await;
+        let event = select4(
+            btn.wait_for_falling_edge(),
+            heartbeat.next(),
+            diagnostics.next(),
+            select(time_sync.next(), IDENTIFY.wait()),
+        );
+        let frame = match event.await {
+            Either4::First(()) => {
+                // The receiver still remembers the counter from before a reboot and
+                // takes only small steps past it, so the first press after one may
+                // only sync it up. Starting from the time of that press, it can land
+                // in the window instead.
+                let next = counter.map_or(Instant::now().as_ticks() as u8, |counter| counter.wrapping_add(1));
+                counter = Some(next);
+                PowerOff::new(NodeId::POWER_SUPPLY, next).try_encode()
+            }
+            Either4::Second(()) => {
+                let uptime_s = Instant::now().as_secs() as u32;
+                Heartbeat::new(NODE_ID, NodeState::Operational, uptime_s).encode()
+            }
+            Either4::Third(()) => CanDiagnostics::new(NODE_ID, CAN_STATS.status(), CAN_STATS.counters()).encode(),
+            // Taken right before sending, the receivers date it by its arrival.
+            Either4::Fourth(Either::First(())) => TimeSync::new(Instant::now().as_millis()).try_encode(),
+            Either4::Fourth(Either::Second(())) => {
+                for frame in identification(firmware_id!(NODE_ID), uid::uid()) {
+                    write(&mut tx, &frame).await;
+                }
+                continue;
+            }
+        };
+        write(&mut tx, &frame.unwrap()).await;
     }
 }
 
+/// Queue a frame, counting the one it pushes out, if any.
+async fn write(tx: &mut CanTx<'static>, frame: &stm32_can::Frame) {
+    if tx.write(frame).await.dequeued_frame().is_some() {
+        CAN_STATS.record_tx_dropped(1);
+    }
+}
+
+#[task]
+async fn supervise() {
+    supervisor::supervise(&CAN_STATS, Duration::from_millis(SUPERVISOR_PERIOD_MS)).await;
+}
+
 #[main]
 async fn main(spawner: Spawner) {
     // HSI oscillator 12 MHz, 64 MHz system frequency
@@ -107,35 +207,88 @@ async fn main(spawner: Spawner) {
     let _ = display.write_str("It works!").await;
 
     let mut can = Can::new(dev.CAN, dev.PA11, dev.PA12, Irqs);
-    can.set_bitrate(BITRATE);
+    can.modify_config().set_bit_timing(BIT_TIMING);
     can.set_tx_fifo_scheduling(true);
     can.enable().await;
     info!("CAN initialized.");
     let (tx, mut rx) = can.split();
 
-    rx.modify_filters()
-        .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+    FILTERS.apply(&mut rx);
+
+    spawner.spawn(transmit(tx, btn)).unwrap();
+    spawner.spawn(supervise()).unwrap();
 
-    spawner.spawn(send_poweroff(tx, btn)).unwrap();
+    let mut liveness = LivenessTracker::<{ NODES.len() }>::new(LIVENESS_TIMEOUT_MS);
+    for (node, _) in NODES {
+        liveness.watch(node);
+    }
+    let mut bus_status: [Option<CanDiagnostics>; NODES.len()] = Default::default();
+    let mut shown: [String<16>; 7] = Default::default();
 
     info!("System startup");
     loop {
-        if let Ok(msg) = rx.read().await {
-            if let Some(batt) = msg.try_decode::<BatteryData>() {
-                info!("CAN battery: {}", Debug2Format(&batt));
-                let _ = display.set_position(0, 0).await;
-                let mut buf = String::<128>::new();
-                let _ = write!(&mut buf, "Bat: {:>5} mV", batt.battery_voltage_mv);
-                let _ = display.write_str(&buf).await;
-            } else if let Some(cob) = msg.try_decode::<CoolBox>() {
-                info!("CAN coolbox: {}", Debug2Format(&cob));
-                let _ = display.set_position(0, 1).await;
-                let mut buf = String::<128>::new();
-                let _ = write!(&mut buf, "Temp: {:>5} /10C", cob.box_temperature_deg10);
-                let _ = display.write_str(&buf).await;
-            } else {
-                info!("CAN message received: {}", Debug2Format(&msg));
+        match select(rx.read(), Timer::after_millis(500)).await {
+            Either::First(Ok(msg)) => {
+                if let Some(heartbeat) = Heartbeat::decode(&msg.frame) {
+                    if liveness.update(heartbeat, Instant::now().as_millis()) {
+                        info!("CAN node {:02X} is up", heartbeat.node);
+                    }
+                } else if let Some(diag) = CanDiagnostics::decode(&msg.frame) {
+                    if let Some(i) = NODES.iter().position(|(node, _)| node.raw() == diag.node) {
+                        bus_status[i] = Some(diag.clone());
+                    }
+                } else if let Some(req) = msg.frame.try_decode::<IdentifyRequest>() {
+                    if req.is_for(NODE_ID) {
+                        IDENTIFY.signal(());
+                    }
+                } else if !(BATTERY.offer(&msg.frame) || COOLBOX.offer(&msg.frame)) {
+                    info!("CAN message received: {}", Debug2Format(&msg));
+                }
+            }
+            Either::First(Err(_)) => Timer::after_millis(RX_ERROR_BACKOFF_MS).await,
+            Either::Second(()) => {}
+        }
+
+        let mut lines: [String<16>; 7] = Default::default();
+        let _ = match BATTERY.get_fresh(DATA_TIMEOUT) {
+            Some(batt) => write!(&mut lines[0], "Bat: {}", batt.battery_voltage),
+            None => write!(&mut lines[0], "Bat:    timeout"),
+        };
+        let _ = match COOLBOX.get_fresh(DATA_TIMEOUT) {
+            Some(cob) => write!(&mut lines[1], "Temp: {}", cob.box_temperature),
+            None => write!(&mut lines[1], "Temp:   timeout"),
+        };
+
+        let now = Instant::now().as_millis();
+        for (i, (node, label)) in NODES.into_iter().enumerate() {
+            let text = match liveness.status(node, now) {
+                Liveness::Unknown => "?",
+                Liveness::Alive(NodeState::Booting) => "booting",
+                Liveness::Alive(NodeState::Operational) => "ok",
+                Liveness::Alive(NodeState::ShuttingDown) => "off",
+                Liveness::Alive(NodeState::Fault) => "FAULT",
+                Liveness::Lost => "LOST",
+            };
+            let _ = write!(&mut lines[2 + i], "{:<5} {}", label, text);
+            let _ = match &bus_status[i] {
+                Some(diag) if matches!(liveness.status(node, now), Liveness::Alive(_)) => {
+                    write_bus_status(&mut lines[4 + i], label, diag.state, diag.tec, diag.rec)
+                }
+                _ => write!(&mut lines[4 + i], "{:<4} -", label),
+            };
+        }
+        let own = CAN_STATS.status();
+        let _ = write_bus_status(&mut lines[6], "Own", own.state, own.tec, own.rec);
+
+        for (row, (line, shown)) in lines.iter_mut().zip(&mut shown).enumerate() {
+            if line == shown {
+                continue;
             }
+            *shown = line.clone();
+            // Pad to erase the rest of the previous text.
+            while line.push(' ').is_ok() {}
+            let _ = display.set_position(0, row as u8).await;
+            let _ = display.write_str(line).await;
         }
     }
 }